@@ -0,0 +1,47 @@
+fn main() {
+    #[cfg(feature = "ffi")]
+    generate_header();
+}
+
+/// Checked-in copy of the C header, which is only rewritten when this variable is set.
+#[cfg(feature = "ffi")]
+const REGENERATE_HEADER: &str = "CHEMU_REGENERATE_HEADER";
+
+/// Writes the C header for the API in `src/ffi.rs` to `OUT_DIR`, so building never touches the
+/// source tree. Setting `CHEMU_REGENERATE_HEADER` copies it over `include/chemu.h` as well, and
+/// otherwise a warning is given when the checked-in header is out of date.
+#[cfg(feature = "ffi")]
+fn generate_header() {
+    use std::path::PathBuf;
+
+    println!("cargo:rerun-if-changed=src/ffi.rs");
+    println!("cargo:rerun-if-changed=include/chemu.h");
+    println!("cargo:rerun-if-env-changed={}", REGENERATE_HEADER);
+
+    let config = cbindgen::Config {
+        language: cbindgen::Language::C,
+        header: Some("/* Generated from src/ffi.rs by build.rs. Don't edit it by hand. */".into()),
+        include_guard: Some("CHEMU_H".into()),
+        cpp_compat: true,
+        usize_is_size_t: true,
+        ..Default::default()
+    };
+
+    let bindings = cbindgen::Builder::new()
+        .with_config(config)
+        .with_src("src/ffi.rs")
+        .generate()
+        .expect("Couldn't generate the C header");
+    let generated = PathBuf::from(std::env::var_os("OUT_DIR").unwrap()).join("chemu.h");
+    bindings.write_to_file(&generated);
+
+    let published = PathBuf::from("include/chemu.h");
+    if std::env::var_os(REGENERATE_HEADER).is_some() {
+        std::fs::copy(&generated, &published).expect("Couldn't update include/chemu.h");
+    } else if std::fs::read(&generated).ok() != std::fs::read(&published).ok() {
+        println!(
+            "cargo:warning=include/chemu.h is out of date, rebuild with {}=1 to update it",
+            REGENERATE_HEADER
+        );
+    }
+}
@@ -0,0 +1,88 @@
+/*
+ * Exercises the C API: runs a ROM that waits for a key and draws its digit, then checks the
+ * display, the sound timer and the handling of a program that can't be run.
+ *
+ *     cargo build --no-default-features --features ffi
+ *     cc examples/c/ffi_test.c -Iinclude -Ltarget/debug -lchemu -o target/ffi_test
+ *     LD_LIBRARY_PATH=target/debug target/ffi_test
+ */
+
+#include <stdio.h>
+#include <string.h>
+
+#include "chemu.h"
+
+#define CHECK(condition)                                                   \
+    do {                                                                   \
+        if (!(condition)) {                                                \
+            fprintf(stderr, "%s:%d: check failed: %s\n", __FILE__, __LINE__, \
+                    #condition);                                           \
+            return 1;                                                      \
+        }                                                                  \
+    } while (0)
+
+/* Waits for a key, draws its digit in the top left corner, then sounds the buzzer forever */
+static const uint8_t DIGIT_ROM[] = {
+    0xF0, 0x0A, /* LD V0, K */
+    0xF0, 0x29, /* LD F, V0 */
+    0x61, 0x00, /* LD V1, 0 */
+    0xD1, 0x15, /* DRW V1, V1, 5 */
+    0x62, 0x10, /* LD V2, 16 */
+    0xF2, 0x18, /* LD ST, V2 */
+    0x12, 0x0C, /* JP 20C */
+};
+
+/* The font's sprite for 7 */
+static const uint8_t SEVEN[] = {0xF0, 0x10, 0x20, 0x40, 0x40};
+
+/* 0x0000 isn't a valid instruction */
+static const uint8_t INVALID_ROM[] = {0x00, 0x00};
+
+int main(void) {
+    uint8_t framebuffer[CHEMU_FRAMEBUFFER_SIZE];
+
+    CHECK(chemu_api_version() == CHEMU_API_VERSION);
+    CHECK(chemu_machine_new(NULL, 0) == NULL);
+
+    ChemuMachine *machine = chemu_machine_new(DIGIT_ROM, sizeof DIGIT_ROM);
+    CHECK(machine != NULL);
+    chemu_machine_seed_random(machine, 1);
+
+    /* Nothing is drawn until a key is pressed and released */
+    CHECK(chemu_machine_run_frames(machine, 10));
+    CHECK(chemu_machine_copy_framebuffer(machine, framebuffer, sizeof framebuffer));
+    for (size_t i = 0; i < sizeof framebuffer; i++) {
+        CHECK(framebuffer[i] == 0);
+    }
+    CHECK(chemu_machine_sound_timer(machine) == 0);
+    chemu_machine_mark_presented(machine);
+
+    chemu_machine_set_key(machine, 7, true);
+    CHECK(chemu_machine_run_frames(machine, 1));
+    chemu_machine_set_key(machine, 7, false);
+    CHECK(chemu_machine_step(machine, 6));
+
+    CHECK(chemu_machine_display_changed(machine));
+    CHECK(!chemu_machine_copy_framebuffer(machine, framebuffer, sizeof framebuffer - 1));
+    CHECK(chemu_machine_copy_framebuffer(machine, framebuffer, sizeof framebuffer));
+    for (int y = 0; y < CHEMU_DISPLAY_HEIGHT; y++) {
+        for (int x = 0; x < CHEMU_DISPLAY_WIDTH; x++) {
+            int expected = y < 5 && x < 8 ? (SEVEN[y] >> (7 - x)) & 1 : 0;
+            CHECK(framebuffer[y * CHEMU_DISPLAY_WIDTH + x] == expected);
+        }
+    }
+    CHECK(chemu_machine_sound_timer(machine) == 16);
+
+    /* Out of range keys are ignored */
+    chemu_machine_set_key(machine, 16, true);
+    chemu_machine_free(machine);
+
+    machine = chemu_machine_new(INVALID_ROM, sizeof INVALID_ROM);
+    CHECK(!chemu_machine_step(machine, 1));
+    CHECK(!chemu_machine_run_frames(machine, 1));
+    chemu_machine_free(machine);
+    chemu_machine_free(NULL);
+
+    printf("FFI checks passed\n");
+    return 0;
+}
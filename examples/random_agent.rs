@@ -0,0 +1,66 @@
+//! Plays a ROM with many agents pressing random keys, and reports the reward each episode earned.
+//!
+//! ```text
+//! cargo run --no-default-features --features std --example random_agent -- rom.ch8 spec.toml [steps]
+//! ```
+
+use chemu::env::{RewardSpec, VecEnvironment};
+use chemu::keyboard::Key;
+use rand::Rng;
+
+const ENVIRONMENTS: usize = 16;
+const THREADS: usize = 4;
+const FRAME_SKIP: usize = 4;
+
+fn main() {
+    let mut args = std::env::args().skip(1);
+    let (rom_path, spec_path) = match (args.next(), args.next()) {
+        (Some(rom), Some(spec)) => (rom, spec),
+        _ => {
+            eprintln!("Usage: random_agent rom.ch8 spec.toml [steps]");
+            return;
+        }
+    };
+    let steps: usize = args
+        .next()
+        .and_then(|steps| steps.parse().ok())
+        .unwrap_or(1000);
+
+    let rom = std::fs::read(rom_path).unwrap();
+    let spec = RewardSpec::from_toml(&std::fs::read_to_string(spec_path).unwrap()).unwrap();
+
+    let mut environments = VecEnvironment::new(&rom, spec, FRAME_SKIP, ENVIRONMENTS, THREADS);
+    println!(
+        "Playing {} environments on {} threads for {} steps",
+        environments.len(),
+        THREADS,
+        steps
+    );
+    environments.reset_with_seed(0);
+
+    let mut random = rand::thread_rng();
+    let mut returns = vec![0.0; environments.len()];
+    let mut episodes = 0;
+    for _ in 0..steps {
+        let actions: Vec<_> = (0..environments.len())
+            .map(|_| match random.gen_range(0, 17) {
+                16 => None,
+                key => Some(Key(key)),
+            })
+            .collect();
+
+        for (i, (_, reward, done)) in environments.step(&actions).into_iter().enumerate() {
+            returns[i] += reward;
+            if done {
+                println!(
+                    "Environment {} finished an episode with reward {}",
+                    i, returns[i]
+                );
+                returns[i] = 0.0;
+                episodes += 1;
+            }
+        }
+    }
+
+    println!("{} episodes finished in {} steps", episodes, steps);
+}
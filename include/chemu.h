@@ -0,0 +1,140 @@
+/* Generated from src/ffi.rs by build.rs. Don't edit it by hand. */
+
+#ifndef CHEMU_H
+#define CHEMU_H
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+/**
+ * Incremented whenever a change to the API would break existing callers.
+ */
+#define CHEMU_API_VERSION 1
+
+/**
+ * Width of the display in pixels.
+ */
+#define CHEMU_DISPLAY_WIDTH 64
+
+/**
+ * Height of the display in pixels.
+ */
+#define CHEMU_DISPLAY_HEIGHT 32
+
+/**
+ * Size of the buffer `chemu_machine_copy_framebuffer` needs.
+ */
+#define CHEMU_FRAMEBUFFER_SIZE 2048
+
+/**
+ * A CHIP-8 machine running a ROM.
+ */
+typedef struct ChemuMachine ChemuMachine;
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+/**
+ * Returns `CHEMU_API_VERSION` as it was when the library was built.
+ */
+uint32_t chemu_api_version(void);
+
+/**
+ * Creates a machine with the ROM loaded into memory. The ROM is copied, so the buffer can be
+ * freed straight away. Returns null if `rom` is null.
+ *
+ * # Safety
+ * `rom` must point to at least `length` readable bytes.
+ */
+struct ChemuMachine *chemu_machine_new(const uint8_t *rom, size_t length);
+
+/**
+ * Frees a machine. Passing null does nothing.
+ *
+ * # Safety
+ * `machine` must be null or a pointer returned by `chemu_machine_new` that hasn't been freed.
+ */
+void chemu_machine_free(struct ChemuMachine *machine);
+
+/**
+ * Starts the random number generator from a seed, so that runs given the same input behave
+ * identically.
+ *
+ * # Safety
+ * `machine` must be a valid pointer returned by `chemu_machine_new`.
+ */
+void chemu_machine_seed_random(struct ChemuMachine *machine, uint64_t seed);
+
+/**
+ * Runs 60 Hz frames, ticking the timers after each. Returns false if the program has hit an
+ * instruction the emulator can't run, in which case the machine stops for good.
+ *
+ * # Safety
+ * `machine` must be a valid pointer returned by `chemu_machine_new`.
+ */
+bool chemu_machine_run_frames(struct ChemuMachine *machine, uint32_t frames);
+
+/**
+ * Executes instructions without ticking the timers. Returns false if the program has hit an
+ * instruction the emulator can't run, in which case the machine stops for good.
+ *
+ * # Safety
+ * `machine` must be a valid pointer returned by `chemu_machine_new`.
+ */
+bool chemu_machine_step(struct ChemuMachine *machine, uint32_t instructions);
+
+/**
+ * Presses or releases a hex key from 0 to 15. Other keys are ignored.
+ *
+ * # Safety
+ * `machine` must be a valid pointer returned by `chemu_machine_new`.
+ */
+void chemu_machine_set_key(struct ChemuMachine *machine, uint8_t key, bool pressed);
+
+/**
+ * Copies the display into `buffer`, one byte per pixel in rows from the top left, with 1 for lit
+ * pixels and 0 for the rest. Returns false without copying anything if `length` is less than
+ * `CHEMU_FRAMEBUFFER_SIZE`.
+ *
+ * # Safety
+ * `machine` must be a valid pointer returned by `chemu_machine_new`, and `buffer` must point to at
+ * least `length` writable bytes.
+ */
+bool chemu_machine_copy_framebuffer(const struct ChemuMachine *machine,
+                                    uint8_t *buffer,
+                                    size_t length);
+
+/**
+ * Whether the display has changed since the last call to `chemu_machine_mark_presented`, so
+ * callers can skip redrawing it.
+ *
+ * # Safety
+ * `machine` must be a valid pointer returned by `chemu_machine_new`.
+ */
+bool chemu_machine_display_changed(const struct ChemuMachine *machine);
+
+/**
+ * Records that the current display has been drawn.
+ *
+ * # Safety
+ * `machine` must be a valid pointer returned by `chemu_machine_new`.
+ */
+void chemu_machine_mark_presented(struct ChemuMachine *machine);
+
+/**
+ * The sound timer's value. The buzzer sounds for as long as it's above zero.
+ *
+ * # Safety
+ * `machine` must be a valid pointer returned by `chemu_machine_new`.
+ */
+uint8_t chemu_machine_sound_timer(const struct ChemuMachine *machine);
+
+#ifdef __cplusplus
+}  // extern "C"
+#endif  // __cplusplus
+
+#endif  /* CHEMU_H */
@@ -0,0 +1,352 @@
+//! A two-pass assembler for the mnemonics in Cowgod's Chip-8 technical reference, and a
+//! disassembler that writes programs back out in the same syntax.
+//!
+//! Each line holds an optional `label:`, then an instruction or directive, then an optional
+//! `; comment`. Numbers are decimal, or hex after `#`, `$` or `0x`, and labels can be used wherever
+//! a number can. `DB` and `DW` emit bytes and 16-bit words, so sprites and other data can be
+//! included:
+//!
+//! ```text
+//! start:  LD I, sprite
+//!         DRW V0, V1, 5
+//!         JP start
+//! sprite: DB #F0, #90, #F0, #90, #90
+//! ```
+
+use crate::instruction;
+use crate::instruction::Instruction;
+use crate::machine::Register;
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::error::Error;
+use std::fmt;
+use std::fmt::Write;
+use std::fmt::{Display, Formatter};
+
+/// Where programs are loaded into memory, which is the address of the first byte assembled.
+const ORIGIN: usize = 0x200;
+/// Programs can't extend past the end of memory.
+const MEMORY_SIZE: usize = 4096;
+
+/// Assembles a program into a ROM that can be loaded into a machine.
+pub fn assemble(source: &str) -> Result<Vec<u8>, AssembleError> {
+    // The first pass finds each label's address, so the second can assemble references to labels
+    // that come later in the program
+    let mut lines = Vec::new();
+    let mut labels = HashMap::new();
+    let mut address = ORIGIN;
+    for (i, text) in source.lines().enumerate() {
+        let line_number = i + 1;
+        let error = |kind| AssembleError {
+            line: line_number,
+            kind,
+        };
+        let line = parse_line(text).map_err(error)?;
+        if let Some(label) = line.label {
+            if labels.insert(label.to_ascii_lowercase(), address).is_some() {
+                return Err(error(AssembleErrorKind::DuplicateLabel(label.to_string())));
+            }
+        }
+        if let Some((mnemonic, operands)) = line.statement {
+            address += statement_size(mnemonic, &operands);
+            if address > MEMORY_SIZE {
+                return Err(error(AssembleErrorKind::ProgramTooLarge));
+            }
+            lines.push((line_number, mnemonic, operands));
+        }
+    }
+
+    let mut rom = Vec::new();
+    for (line, mnemonic, operands) in lines {
+        assemble_statement(mnemonic, &operands, &labels, &mut rom)
+            .map_err(|kind| AssembleError { line, kind })?;
+    }
+    Ok(rom)
+}
+
+/// Disassembles a ROM into source that assembles back into the same bytes. Each line has a comment
+/// with its address and encoding. Words that aren't instructions are written as `DW`, and a
+/// trailing odd byte as `DB`. Data mixed in with the code, such as sprites, is disassembled as
+/// instructions where it can be.
+pub fn disassemble(rom: &[u8]) -> String {
+    let mut source = String::new();
+    for (i, bytes) in rom.chunks(2).enumerate() {
+        let address = ORIGIN + i * 2;
+        let (text, encoding) = match *bytes {
+            [high, low] => {
+                let word = u16::from_be_bytes([high, low]);
+                let text = match instruction::decode(word) {
+                    Ok(instruction) => instruction.to_string(),
+                    Err(_) => format!("DW #{:04X}", word),
+                };
+                (text, format!("{:04X}", word))
+            }
+            [byte] => (format!("DB #{:02X}", byte), format!("{:02X}", byte)),
+            _ => unreachable!(),
+        };
+        let _ = writeln!(source, "{:<20}; {:03X}: {}", text, address, encoding);
+    }
+    source
+}
+
+/// Error that occurs when a program can't be assembled, along with the line it occurred on.
+#[derive(Debug)]
+pub struct AssembleError {
+    /// The line number, starting from 1.
+    pub line: usize,
+    pub kind: AssembleErrorKind,
+}
+
+#[derive(Debug)]
+pub enum AssembleErrorKind {
+    UnknownMnemonic(String),
+    /// The operands don't match any form of the instruction.
+    InvalidOperands,
+    InvalidNumber(String),
+    InvalidLabel(String),
+    DuplicateLabel(String),
+    UnknownLabel(String),
+    /// A number is too large for the operand it's used as, which holds this many bits.
+    OutOfRange {
+        value: u32,
+        bits: u32,
+    },
+    ProgramTooLarge,
+}
+
+impl Display for AssembleError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}: ", self.line)?;
+        match &self.kind {
+            AssembleErrorKind::UnknownMnemonic(mnemonic) => {
+                write!(f, "unknown instruction: {}", mnemonic)
+            }
+            AssembleErrorKind::InvalidOperands => write!(f, "invalid operands"),
+            AssembleErrorKind::InvalidNumber(text) => write!(f, "invalid number: {}", text),
+            AssembleErrorKind::InvalidLabel(label) => write!(f, "invalid label: {}", label),
+            AssembleErrorKind::DuplicateLabel(label) => {
+                write!(f, "label defined more than once: {}", label)
+            }
+            AssembleErrorKind::UnknownLabel(label) => write!(f, "unknown label: {}", label),
+            AssembleErrorKind::OutOfRange { value, bits } => {
+                write!(f, "#{:X} doesn't fit in {} bits", value, bits)
+            }
+            AssembleErrorKind::ProgramTooLarge => write!(f, "program doesn't fit in memory"),
+        }
+    }
+}
+
+impl Error for AssembleError {}
+
+struct Line<'a> {
+    label: Option<&'a str>,
+    statement: Option<(&'a str, Vec<Operand<'a>>)>,
+}
+
+enum Operand<'a> {
+    Register(Register),
+    /// A number or a label standing for one.
+    Value(Value<'a>),
+    /// One of the names that only appear in particular instructions: I, [I], DT, ST, K, F and B.
+    Special(&'a str),
+}
+
+enum Value<'a> {
+    Number(u32),
+    Label(&'a str),
+}
+
+fn parse_line(text: &str) -> Result<Line<'_>, AssembleErrorKind> {
+    let mut text = text.split(';').next().unwrap_or("").trim();
+
+    let mut label = None;
+    if let Some(colon) = text.find(':') {
+        let name = text[..colon].trim();
+        if !is_identifier(name) {
+            return Err(AssembleErrorKind::InvalidLabel(name.to_string()));
+        }
+        label = Some(name);
+        text = text[colon + 1..].trim();
+    }
+
+    if text.is_empty() {
+        return Ok(Line {
+            label,
+            statement: None,
+        });
+    }
+    let (mnemonic, rest) = match text.find(char::is_whitespace) {
+        Some(space) => (&text[..space], text[space..].trim()),
+        None => (text, ""),
+    };
+    let operands = if rest.is_empty() {
+        Vec::new()
+    } else {
+        rest.split(',')
+            .map(|operand| parse_operand(operand.trim()))
+            .collect::<Result<_, _>>()?
+    };
+    Ok(Line {
+        label,
+        statement: Some((mnemonic, operands)),
+    })
+}
+
+fn parse_operand(text: &str) -> Result<Operand<'_>, AssembleErrorKind> {
+    const SPECIAL: [&str; 7] = ["I", "[I]", "DT", "ST", "K", "F", "B"];
+    if let Some(&special) = SPECIAL.iter().find(|name| name.eq_ignore_ascii_case(text)) {
+        return Ok(Operand::Special(special));
+    }
+
+    let bytes = text.as_bytes();
+    if bytes.len() == 2 && bytes[0].eq_ignore_ascii_case(&b'v') {
+        if let Some(digit) = (bytes[1] as char).to_digit(16) {
+            let register = Register::try_from(digit as u16).expect("hex digits are registers");
+            return Ok(Operand::Register(register));
+        }
+    }
+
+    let hex = text
+        .strip_prefix('#')
+        .or_else(|| text.strip_prefix('$'))
+        .or_else(|| text.strip_prefix("0x"))
+        .or_else(|| text.strip_prefix("0X"));
+    let number = match hex {
+        Some(digits) => u32::from_str_radix(digits, 16),
+        None if text.starts_with(|c: char| c.is_ascii_digit()) => text.parse(),
+        None if is_identifier(text) => return Ok(Operand::Value(Value::Label(text))),
+        None => return Err(AssembleErrorKind::InvalidNumber(text.to_string())),
+    };
+    number
+        .map(|number| Operand::Value(Value::Number(number)))
+        .map_err(|_| AssembleErrorKind::InvalidNumber(text.to_string()))
+}
+
+fn is_identifier(text: &str) -> bool {
+    let mut chars = text.chars();
+    matches!(chars.next(), Some(c) if c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '.')
+}
+
+/// How many bytes a statement assembles to.
+fn statement_size(mnemonic: &str, operands: &[Operand]) -> usize {
+    if mnemonic.eq_ignore_ascii_case("db") {
+        operands.len()
+    } else if mnemonic.eq_ignore_ascii_case("dw") {
+        operands.len() * 2
+    } else {
+        2
+    }
+}
+
+fn assemble_statement(
+    mnemonic: &str,
+    operands: &[Operand],
+    labels: &HashMap<String, usize>,
+    rom: &mut Vec<u8>,
+) -> Result<(), AssembleErrorKind> {
+    let value = |value: &Value, bits: u32| -> Result<u32, AssembleErrorKind> {
+        let number = match value {
+            Value::Number(number) => *number,
+            Value::Label(label) => *labels
+                .get(&label.to_ascii_lowercase())
+                .ok_or_else(|| AssembleErrorKind::UnknownLabel(label.to_string()))?
+                as u32,
+        };
+        if number >> bits != 0 {
+            return Err(AssembleErrorKind::OutOfRange {
+                value: number,
+                bits,
+            });
+        }
+        Ok(number)
+    };
+    let addr = |operand: &Value| value(operand, 12).map(|addr| addr as u16);
+    let byte = |operand: &Value| value(operand, 8).map(|byte| byte as u8);
+
+    let mnemonic = mnemonic.to_ascii_uppercase();
+    if mnemonic == "DB" || mnemonic == "DW" {
+        for operand in operands {
+            match operand {
+                Operand::Value(operand) if mnemonic == "DB" => rom.push(byte(operand)?),
+                Operand::Value(operand) => {
+                    let word = value(operand, 16)? as u16;
+                    rom.extend_from_slice(&word.to_be_bytes());
+                }
+                _ => return Err(AssembleErrorKind::InvalidOperands),
+            }
+        }
+        return Ok(());
+    }
+
+    use Operand::{Register as R, Special as S, Value as N};
+    let instruction = match (mnemonic.as_str(), operands) {
+        ("SYS", [N(a)]) => Instruction::Sys { addr: addr(a)? },
+        ("CLS", []) => Instruction::Clr,
+        ("RET", []) => Instruction::Ret,
+        ("JP", [N(a)]) => Instruction::Jmp { addr: addr(a)? },
+        ("JP", [R(Register::V0), N(a)]) => Instruction::JmpOff {
+            base_addr: addr(a)?,
+        },
+        ("CALL", [N(a)]) => Instruction::Call { addr: addr(a)? },
+        ("SE", [R(x), N(b)]) => Instruction::SeImm {
+            register: *x,
+            value: byte(b)?,
+        },
+        ("SE", [R(x), R(y)]) => Instruction::SeReg { reg1: *x, reg2: *y },
+        ("SNE", [R(x), N(b)]) => Instruction::SneImm {
+            register: *x,
+            value: byte(b)?,
+        },
+        ("SNE", [R(x), R(y)]) => Instruction::SneReg { reg1: *x, reg2: *y },
+        ("LD", [R(x), N(b)]) => Instruction::LdImm {
+            register: *x,
+            value: byte(b)?,
+        },
+        ("LD", [R(x), R(y)]) => Instruction::LdReg { dest: *x, src: *y },
+        ("LD", [S("I"), N(a)]) => Instruction::LdAddr { addr: addr(a)? },
+        ("LD", [R(x), S("DT")]) => Instruction::ReadDelay { register: *x },
+        ("LD", [R(x), S("K")]) => Instruction::LdKey { register: *x },
+        ("LD", [S("DT"), R(x)]) => Instruction::StrDelay { register: *x },
+        ("LD", [S("ST"), R(x)]) => Instruction::StrSound { register: *x },
+        ("LD", [S("F"), R(x)]) => Instruction::LdDigit { register: *x },
+        ("LD", [S("B"), R(x)]) => Instruction::LdBcd { register: *x },
+        ("LD", [S("[I]"), R(x)]) => Instruction::StrArray { end: *x },
+        ("LD", [R(x), S("[I]")]) => Instruction::LdArray { end: *x },
+        ("ADD", [R(x), N(b)]) => Instruction::AddImm {
+            register: *x,
+            value: byte(b)?,
+        },
+        ("ADD", [R(x), R(y)]) => Instruction::AddReg { dest: *x, src: *y },
+        ("ADD", [S("I"), R(x)]) => Instruction::AddAddr { register: *x },
+        ("OR", [R(x), R(y)]) => Instruction::Or { dest: *x, src: *y },
+        ("AND", [R(x), R(y)]) => Instruction::And { dest: *x, src: *y },
+        ("XOR", [R(x), R(y)]) => Instruction::Xor { dest: *x, src: *y },
+        ("SUB", [R(x), R(y)]) => Instruction::Sub { dest: *x, src: *y },
+        ("SUBN", [R(x), R(y)]) => Instruction::SubNeg { dest: *x, src: *y },
+        // The source register can be left out, as some assemblers only take one
+        ("SHR", [R(x)]) => Instruction::Shr { dest: *x, src: *x },
+        ("SHR", [R(x), R(y)]) => Instruction::Shr { dest: *x, src: *y },
+        ("SHL", [R(x)]) => Instruction::Shl { dest: *x, src: *x },
+        ("SHL", [R(x), R(y)]) => Instruction::Shl { dest: *x, src: *y },
+        ("RND", [R(x), N(b)]) => Instruction::Rnd {
+            register: *x,
+            mask: byte(b)?,
+        },
+        ("DRW", [R(x), R(y), N(n)]) => Instruction::Drw {
+            x: *x,
+            y: *y,
+            length: value(n, 4)? as u8,
+        },
+        ("SKP", [R(x)]) => Instruction::Skp { keycode: *x },
+        ("SKNP", [R(x)]) => Instruction::SkpNeg { keycode: *x },
+        (
+            "SYS" | "CLS" | "RET" | "JP" | "CALL" | "SE" | "SNE" | "LD" | "ADD" | "OR" | "AND"
+            | "XOR" | "SUB" | "SUBN" | "SHR" | "SHL" | "RND" | "DRW" | "SKP" | "SKNP",
+            _,
+        ) => return Err(AssembleErrorKind::InvalidOperands),
+        _ => return Err(AssembleErrorKind::UnknownMnemonic(mnemonic)),
+    };
+    rom.extend_from_slice(&instruction.encode().to_be_bytes());
+    Ok(())
+}
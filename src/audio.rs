@@ -0,0 +1,58 @@
+/// Frequency of the buzzer's tone in hertz unless another is set.
+pub const DEFAULT_TONE: f32 = 440.0;
+/// Amplitude of the tone at full volume, kept well below full scale so it isn't harsh.
+const AMPLITUDE: f32 = (i16::MAX / 8) as f32;
+
+/// Generates the square wave that the Chip-8 buzzer plays while the sound timer is running.
+pub struct Beeper {
+    sample_rate: u32,
+    tone: f32,
+    amplitude: i16,
+    /// Position within the current period of the wave, from 0 up to 1.
+    phase: f32,
+}
+
+impl Beeper {
+    /// Creates a beeper playing the default tone at full volume.
+    pub fn new(sample_rate: u32) -> Beeper {
+        Beeper {
+            sample_rate,
+            tone: DEFAULT_TONE,
+            amplitude: AMPLITUDE as i16,
+            phase: 0.0,
+        }
+    }
+
+    /// Sets the frequency of the tone in hertz.
+    pub fn set_tone(&mut self, frequency: f32) {
+        self.tone = frequency;
+    }
+
+    /// Sets the volume from 0 for silence to 1 for full volume.
+    pub fn set_volume(&mut self, volume: f32) {
+        self.amplitude = (AMPLITUDE * volume.clamp(0.0, 1.0)) as i16;
+    }
+
+    /// Fills the buffer with samples for each channel, interleaved. The tone carries on from where
+    /// the last call left off, so there's no click between buffers. Silence is written when the
+    /// buzzer isn't active.
+    pub fn fill(&mut self, active: bool, channels: usize, samples: &mut [i16]) {
+        let step = self.tone / self.sample_rate as f32;
+
+        for frame in samples.chunks_mut(channels) {
+            let sample = if !active {
+                0
+            } else if self.phase < 0.5 {
+                self.amplitude
+            } else {
+                -self.amplitude
+            };
+
+            for channel in frame.iter_mut() {
+                *channel = sample;
+            }
+
+            self.phase = (self.phase + step) % 1.0;
+        }
+    }
+}
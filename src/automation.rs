@@ -0,0 +1,358 @@
+//! A JSON-RPC 2.0 interface for driving a machine from scripts, such as bots and QA playthroughs.
+//! Requests and responses are sent one per line, over stdin and stdout or a Unix socket. The
+//! machine only runs when a request tells it to.
+//!
+//! Methods, with their parameters:
+//!
+//! - `load` `{"path": "rom.ch8"}`: starts a new machine running the ROM.
+//! - `step` `{"count": 1}`: executes up to 1,000,000 instructions. Returns `{"pc", "fault"}`, where
+//!   `fault` describes what halted the machine, or is null if it's still running.
+//! - `run_frames` `{"count": 1}`: runs up to 3600 60 Hz frames, ticking the timers after each.
+//!   Returns the same as `step`.
+//! - `press` and `release` `{"key": 5}`: presses or releases a hex key.
+//! - `read_memory` `{"address": 512, "length": 16}`: returns an array of bytes.
+//! - `write_register` `{"register": "VA", "value": 3}`: sets `V0` to `VF`, `I` or `PC`.
+//! - `screenshot`: returns the display as `{"width", "height", "rows"}`, with each row a string
+//!   of `0` and `1` characters.
+//! - `save_state`: returns `{"state"}`, a base64-encoded save state.
+//! - `load_state` `{"state": "..."}`: restores a state returned by `save_state`.
+
+use crate::machine::{Machine, INSTRUCTIONS_PER_FRAME};
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::io::{BufRead, Write};
+
+const PARSE_ERROR: i64 = -32700;
+const INVALID_REQUEST: i64 = -32600;
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_PARAMS: i64 = -32602;
+/// Returned when a method needs a machine but no ROM has been loaded yet.
+const NO_MACHINE: i64 = -32000;
+/// Returned when a ROM or save state can't be loaded.
+const LOAD_FAILED: i64 = -32001;
+
+/// Most instructions a single `step` request can run, so one request can't hold up the server
+/// for long.
+const MAX_STEPS: u64 = 1_000_000;
+/// Most frames a single `run_frames` request can run, a minute's worth.
+const MAX_FRAMES: u64 = 3600;
+
+#[derive(Deserialize)]
+struct Request {
+    jsonrpc: String,
+    /// Requests without an ID are notifications, which don't get a response.
+    id: Option<Value>,
+    method: String,
+    #[serde(default)]
+    params: Value,
+}
+
+struct RpcError {
+    code: i64,
+    message: String,
+}
+
+impl RpcError {
+    fn new(code: i64, message: impl Into<String>) -> RpcError {
+        RpcError {
+            code,
+            message: message.into(),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct LoadParams {
+    path: String,
+}
+
+#[derive(Deserialize)]
+struct CountParams {
+    #[serde(default = "one")]
+    count: u64,
+}
+
+fn one() -> u64 {
+    1
+}
+
+#[derive(Deserialize)]
+struct KeyParams {
+    key: u8,
+}
+
+#[derive(Deserialize)]
+struct ReadMemoryParams {
+    address: usize,
+    length: usize,
+}
+
+#[derive(Deserialize)]
+struct WriteRegisterParams {
+    register: String,
+    value: usize,
+}
+
+#[derive(Deserialize)]
+struct LoadStateParams {
+    state: String,
+}
+
+/// A machine controlled by JSON-RPC requests.
+#[derive(Default)]
+pub struct Session {
+    machine: Option<Machine>,
+}
+
+impl Session {
+    pub fn new() -> Session {
+        Session::default()
+    }
+
+    /// Answers requests read from `input` until it's closed, writing each response to `output`.
+    pub fn serve(&mut self, input: impl BufRead, mut output: impl Write) -> std::io::Result<()> {
+        for line in input.lines() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+
+            if let Some(response) = self.handle(&line) {
+                writeln!(output, "{}", response)?;
+                output.flush()?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Handles a single request, returning the response to send. Notifications have no response.
+    pub fn handle(&mut self, request: &str) -> Option<String> {
+        let request: Value = match serde_json::from_str(request) {
+            Ok(request) => request,
+            Err(e) => {
+                return Some(error_response(
+                    Value::Null,
+                    RpcError::new(PARSE_ERROR, e.to_string()),
+                ))
+            }
+        };
+
+        let id = request.get("id").cloned().unwrap_or(Value::Null);
+        let request: Request = match serde_json::from_value(request) {
+            Ok(request) => request,
+            Err(e) => {
+                return Some(error_response(
+                    id,
+                    RpcError::new(INVALID_REQUEST, e.to_string()),
+                ))
+            }
+        };
+        if request.jsonrpc != "2.0" {
+            return Some(error_response(
+                id,
+                RpcError::new(INVALID_REQUEST, "only JSON-RPC 2.0 is supported"),
+            ));
+        }
+
+        let result = self.call(&request.method, request.params);
+        let id = request.id?;
+        let response = match result {
+            Ok(result) => json!({ "jsonrpc": "2.0", "id": id, "result": result }),
+            Err(error) => return Some(error_response(id, error)),
+        };
+        Some(response.to_string())
+    }
+
+    fn call(&mut self, method: &str, params: Value) -> Result<Value, RpcError> {
+        if method == "load" {
+            let params: LoadParams = parse_params(params)?;
+            let rom = std::fs::read(&params.path)
+                .map_err(|e| RpcError::new(LOAD_FAILED, format!("couldn't read ROM: {}", e)))?;
+            self.machine = Some(Machine::from_rom(&rom));
+            return Ok(Value::Null);
+        }
+
+        let machine = self
+            .machine
+            .as_mut()
+            .ok_or_else(|| RpcError::new(NO_MACHINE, "no ROM has been loaded"))?;
+
+        match method {
+            "step" => {
+                let params: CountParams = parse_params(params)?;
+                check_count(params.count, MAX_STEPS)?;
+                for _ in 0..params.count {
+                    if machine.fault().is_some() {
+                        break;
+                    }
+                    machine.exec_next();
+                }
+                Ok(run_result(machine))
+            }
+            "run_frames" => {
+                let params: CountParams = parse_params(params)?;
+                check_count(params.count, MAX_FRAMES)?;
+                for _ in 0..params.count {
+                    if machine.fault().is_some() {
+                        break;
+                    }
+                    machine.run_frame(INSTRUCTIONS_PER_FRAME);
+                }
+                Ok(run_result(machine))
+            }
+            "press" | "release" => {
+                let params: KeyParams = parse_params(params)?;
+                if params.key > 0xF {
+                    return Err(RpcError::new(INVALID_PARAMS, "keys go from 0 to 15"));
+                }
+
+                let key = crate::keyboard::Key(params.key);
+                if method == "press" {
+                    machine.keyboard_mut().press(key);
+                } else {
+                    machine.keyboard_mut().release(key);
+                }
+                Ok(Value::Null)
+            }
+            "read_memory" => {
+                let params: ReadMemoryParams = parse_params(params)?;
+                let memory = machine.memory();
+                let end = params.address.saturating_add(params.length);
+                if end > memory.len() {
+                    return Err(RpcError::new(
+                        INVALID_PARAMS,
+                        format!("memory ends at {:#05X}", memory.len()),
+                    ));
+                }
+                Ok(json!(&memory[params.address..end]))
+            }
+            "write_register" => {
+                let params: WriteRegisterParams = parse_params(params)?;
+                write_register(machine, &params.register, params.value)?;
+                Ok(Value::Null)
+            }
+            "screenshot" => {
+                let display = machine.display();
+                let rows: Vec<String> = display
+                    .pixels()
+                    .iter()
+                    .map(|row| {
+                        row.iter()
+                            .map(|&pixel| if pixel { '1' } else { '0' })
+                            .collect()
+                    })
+                    .collect();
+                Ok(json!({
+                    "width": crate::display::WIDTH,
+                    "height": crate::display::HEIGHT,
+                    "rows": rows,
+                }))
+            }
+            "save_state" => Ok(json!({ "state": base64::encode(machine.save_state()) })),
+            "load_state" => {
+                let params: LoadStateParams = parse_params(params)?;
+                let state = base64::decode(&params.state)
+                    .map_err(|e| RpcError::new(INVALID_PARAMS, e.to_string()))?;
+                machine
+                    .load_state(&state)
+                    .map_err(|e| RpcError::new(LOAD_FAILED, e.to_string()))?;
+                Ok(Value::Null)
+            }
+            _ => Err(RpcError::new(
+                METHOD_NOT_FOUND,
+                format!("unknown method: {}", method),
+            )),
+        }
+    }
+}
+
+fn parse_params<T: DeserializeOwned>(params: Value) -> Result<T, RpcError> {
+    // Methods whose parameters all have defaults can be called without any
+    let params = if params.is_null() { json!({}) } else { params };
+    serde_json::from_value(params).map_err(|e| RpcError::new(INVALID_PARAMS, e.to_string()))
+}
+
+fn check_count(count: u64, max: u64) -> Result<(), RpcError> {
+    if count > max {
+        return Err(RpcError::new(
+            INVALID_PARAMS,
+            format!("count can be at most {}, so split longer runs up", max),
+        ));
+    }
+    Ok(())
+}
+
+/// Where the machine got to after running, and what stopped it if it faulted. A faulted machine
+/// stays halted until a state is loaded or a new ROM is.
+fn run_result(machine: &Machine) -> Value {
+    json!({
+        "pc": machine.program_counter(),
+        "fault": machine.fault().map(|fault| fault.to_string()),
+    })
+}
+
+fn write_register(machine: &mut Machine, register: &str, value: usize) -> Result<(), RpcError> {
+    let out_of_range =
+        || RpcError::new(INVALID_PARAMS, format!("{} can't hold {}", register, value));
+    let memory_size = machine.memory().len();
+
+    match register.to_ascii_uppercase().as_str() {
+        "I" if value < memory_size => machine.set_address_register(value),
+        "PC" if value < memory_size => machine.set_program_counter(value),
+        "I" | "PC" => return Err(out_of_range()),
+        name if name.len() == 2 && name.starts_with('V') => {
+            let index = usize::from_str_radix(&name[1..], 16).map_err(|_| {
+                RpcError::new(INVALID_PARAMS, format!("unknown register: {}", register))
+            })?;
+            if value > u8::MAX as usize {
+                return Err(out_of_range());
+            }
+            machine.registers_mut()[index] = value as u8;
+        }
+        _ => {
+            return Err(RpcError::new(
+                INVALID_PARAMS,
+                format!("unknown register: {}", register),
+            ))
+        }
+    }
+
+    Ok(())
+}
+
+fn error_response(id: Value, error: RpcError) -> String {
+    json!({
+        "jsonrpc": "2.0",
+        "id": id,
+        "error": { "code": error.code, "message": error.message },
+    })
+    .to_string()
+}
+
+/// Listens on a Unix socket, answering requests from one client at a time. The machine carries on
+/// from where the last client left it.
+#[cfg(unix)]
+pub fn serve_socket(session: &mut Session, path: &std::path::Path) -> std::io::Result<()> {
+    use std::io::BufReader;
+    use std::os::unix::fs::FileTypeExt;
+    use std::os::unix::net::UnixListener;
+
+    // A socket left behind by an earlier run would stop the bind from succeeding
+    if let Ok(metadata) = std::fs::symlink_metadata(path) {
+        if metadata.file_type().is_socket() {
+            std::fs::remove_file(path)?;
+        }
+    }
+    let listener = UnixListener::bind(path)?;
+
+    for stream in listener.incoming() {
+        let stream = stream?;
+        let reader = BufReader::new(stream.try_clone()?);
+        // A client going away mid-request only ends its connection
+        let _ = session.serve(reader, stream);
+    }
+
+    Ok(())
+}
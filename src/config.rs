@@ -0,0 +1,293 @@
+//! Settings read from `~/.config/chemu/config.toml`. Anything set at the top level applies to
+//! every ROM, and can be overridden for a single ROM by the SHA-1 digest of its contents or by its
+//! file name. Digests take priority, and stay with the ROM however its file is named, so a config
+//! can be shared for a collection of games:
+//!
+//! ```toml
+//! palette = "amber"
+//! persistence = "decay=100"
+//! filter = "scale2x,crt"
+//! ipf = 10
+//! audio = { volume = 0.5 }
+//!
+//! [roms.PONG2]
+//! palette = "#000000,#FF0000"
+//!
+//! [roms.0123456789abcdef0123456789abcdef01234567]
+//! quirks = "vip"
+//! speed = 0.5
+//! ```
+
+use crate::filter::{FilterChain, FilterError};
+use crate::palette::{Palette, PaletteError};
+use crate::persistence::{Persistence, PersistenceError};
+use crate::quirks::{Quirks, QuirksError};
+use crate::sha1;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::fmt::Formatter;
+use std::path::{Path, PathBuf};
+
+/// Settings that can be given globally or for a single ROM.
+#[derive(Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Settings {
+    /// A built-in theme or list of colours, as accepted by `Palette::parse`.
+    pub palette: Option<String>,
+    /// A way of hiding flicker, as accepted by `Persistence::parse`.
+    pub persistence: Option<String>,
+    /// Upscaling filters, as accepted by `FilterChain::parse`.
+    pub filter: Option<String>,
+    /// Instructions run in each 60 Hz frame.
+    pub ipf: Option<usize>,
+    /// How fast the machine starts out running, as a multiple of normal speed.
+    pub speed: Option<f64>,
+    /// The interpreter to behave like, as accepted by `Quirks::parse`.
+    pub quirks: Option<String>,
+    /// The keymap layout to start from, where the keymap file doesn't choose one.
+    pub keymap: Option<String>,
+    /// Size of the window as a multiple of the display's size.
+    pub scale: Option<usize>,
+    #[serde(default)]
+    pub audio: AudioSettings,
+}
+
+/// Settings for the buzzer.
+#[derive(Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AudioSettings {
+    /// From 0 for silence to 1 for full volume.
+    pub volume: Option<f32>,
+    /// Frequency of the tone in hertz.
+    pub tone: Option<f32>,
+}
+
+/// Identifies a ROM when looking up its overrides, both by its contents and by its file name.
+pub struct RomId {
+    /// The file name without the extension.
+    pub name: String,
+    /// The SHA-1 digest of the ROM's contents, in lowercase hex.
+    pub sha1: String,
+}
+
+impl RomId {
+    pub fn new(name: &str, rom: &[u8]) -> RomId {
+        let sha1 = sha1::digest(rom)
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect();
+        RomId {
+            name: name.to_string(),
+            sha1,
+        }
+    }
+}
+
+/// The contents of the config file.
+#[derive(Default, Deserialize)]
+#[serde(default)]
+pub struct ConfigFile {
+    #[serde(flatten)]
+    pub default: Settings,
+    /// Per-ROM overrides, keyed by SHA-1 digest in hex or by file name without the extension.
+    pub roms: HashMap<String, Settings>,
+    /// Settings found for the ROM being run somewhere other than the config file, such as the ROM
+    /// database. They apply unless the config file has overrides for the ROM.
+    #[serde(skip)]
+    pub detected: Settings,
+}
+
+/// Directory holding chemu's config files, following the XDG base directory convention.
+pub fn config_dir() -> Option<PathBuf> {
+    let config_dir = match std::env::var_os("XDG_CONFIG_HOME") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => PathBuf::from(std::env::var_os("HOME")?).join(".config"),
+    };
+
+    Some(config_dir.join("chemu"))
+}
+
+impl ConfigFile {
+    /// Reads a config file from disk. A missing file is treated as an empty one so the defaults
+    /// are used.
+    pub fn load(path: &Path) -> Result<ConfigFile, ConfigError> {
+        match std::fs::read_to_string(path) {
+            Ok(contents) => toml::from_str(&contents).map_err(ConfigError::from),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(ConfigFile::default()),
+            Err(e) => Err(ConfigError::from(e)),
+        }
+    }
+
+    /// Location of the user's config file.
+    pub fn default_path() -> Option<PathBuf> {
+        Some(config_dir()?.join("config.toml"))
+    }
+
+    /// The palette for a ROM, taken from its overrides if it has any and the global settings
+    /// otherwise.
+    pub fn palette_for(&self, rom: &RomId) -> Result<Palette, ConfigError> {
+        match self.setting(rom, |settings| settings.palette.as_ref()) {
+            Some(palette) => Palette::parse(palette).map_err(ConfigError::from),
+            None => Ok(Palette::default()),
+        }
+    }
+
+    /// The persistence mode for a ROM, taken from its overrides if it has any and the global
+    /// settings otherwise.
+    pub fn persistence_for(&self, rom: &RomId) -> Result<Persistence, ConfigError> {
+        match self.setting(rom, |settings| settings.persistence.as_ref()) {
+            Some(persistence) => Persistence::parse(persistence).map_err(ConfigError::from),
+            None => Ok(Persistence::default()),
+        }
+    }
+
+    /// The upscaling filters for a ROM, taken from its overrides if it has any and the global
+    /// settings otherwise.
+    pub fn filters_for(&self, rom: &RomId) -> Result<FilterChain, ConfigError> {
+        match self.setting(rom, |settings| settings.filter.as_ref()) {
+            Some(filters) => FilterChain::parse(filters).map_err(ConfigError::from),
+            None => Ok(FilterChain::default()),
+        }
+    }
+
+    /// The quirks for a ROM, or `None` if neither its overrides nor the global settings give any.
+    pub fn quirks_for(&self, rom: &RomId) -> Result<Option<Quirks>, ConfigError> {
+        self.setting(rom, |settings| settings.quirks.as_ref())
+            .map(|quirks| Quirks::parse(quirks).map_err(ConfigError::from))
+            .transpose()
+    }
+
+    /// Instructions per frame for a ROM, if set.
+    pub fn ipf_for(&self, rom: &RomId) -> Result<Option<usize>, ConfigError> {
+        match self.setting(rom, |settings| settings.ipf.as_ref()) {
+            Some(0) => Err(ConfigError::OutOfRange("ipf")),
+            ipf => Ok(ipf.copied()),
+        }
+    }
+
+    /// The starting speed for a ROM, if set.
+    pub fn speed_for(&self, rom: &RomId) -> Option<f64> {
+        self.setting(rom, |settings| settings.speed.as_ref())
+            .copied()
+    }
+
+    /// The name of the keymap layout for a ROM, if set.
+    pub fn keymap_for(&self, rom: &RomId) -> Option<&str> {
+        self.setting(rom, |settings| settings.keymap.as_ref())
+            .map(String::as_str)
+    }
+
+    /// The window scale for a ROM, if set.
+    pub fn scale_for(&self, rom: &RomId) -> Result<Option<usize>, ConfigError> {
+        match self.setting(rom, |settings| settings.scale.as_ref()) {
+            Some(0) => Err(ConfigError::OutOfRange("scale")),
+            scale => Ok(scale.copied()),
+        }
+    }
+
+    /// The buzzer's volume for a ROM, if set.
+    pub fn volume_for(&self, rom: &RomId) -> Option<f32> {
+        self.setting(rom, |settings| settings.audio.volume.as_ref())
+            .copied()
+    }
+
+    /// The frequency of the buzzer's tone for a ROM, if set.
+    pub fn tone_for(&self, rom: &RomId) -> Result<Option<f32>, ConfigError> {
+        match self.setting(rom, |settings| settings.audio.tone.as_ref()) {
+            Some(&tone) if tone <= 0.0 => Err(ConfigError::OutOfRange("tone")),
+            tone => Ok(tone.copied()),
+        }
+    }
+
+    /// Looks a setting up in the ROM's overrides by digest, then by name, then in the detected
+    /// settings, then in the global settings.
+    fn setting<'a, T>(
+        &'a self,
+        rom: &RomId,
+        get: impl Fn(&'a Settings) -> Option<&'a T>,
+    ) -> Option<&'a T> {
+        let by_digest = self
+            .roms
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(&rom.sha1))
+            .map(|(_, settings)| settings);
+        by_digest
+            .and_then(&get)
+            .or_else(|| self.roms.get(&rom.name).and_then(&get))
+            .or_else(|| get(&self.detected))
+            .or_else(|| get(&self.default))
+    }
+}
+
+/// Error that occurs while loading the config file.
+#[derive(Debug)]
+pub enum ConfigError {
+    /// The config file couldn't be read.
+    Io(std::io::Error),
+    /// The config file isn't valid TOML or doesn't match the expected structure.
+    Parse(toml::de::Error),
+    /// A palette isn't a theme name or list of colours.
+    Palette(PaletteError),
+    /// A persistence mode isn't one of the supported modes.
+    Persistence(PersistenceError),
+    /// A filter isn't one of the supported filters.
+    Filter(FilterError),
+    /// A quirks setting names an unknown profile or quirk.
+    Quirks(QuirksError),
+    /// The named setting is out of the range it can take.
+    OutOfRange(&'static str),
+}
+
+impl From<std::io::Error> for ConfigError {
+    fn from(error: std::io::Error) -> Self {
+        ConfigError::Io(error)
+    }
+}
+
+impl From<toml::de::Error> for ConfigError {
+    fn from(error: toml::de::Error) -> Self {
+        ConfigError::Parse(error)
+    }
+}
+
+impl From<PaletteError> for ConfigError {
+    fn from(error: PaletteError) -> Self {
+        ConfigError::Palette(error)
+    }
+}
+
+impl From<PersistenceError> for ConfigError {
+    fn from(error: PersistenceError) -> Self {
+        ConfigError::Persistence(error)
+    }
+}
+
+impl From<FilterError> for ConfigError {
+    fn from(error: FilterError) -> Self {
+        ConfigError::Filter(error)
+    }
+}
+
+impl From<QuirksError> for ConfigError {
+    fn from(error: QuirksError) -> Self {
+        ConfigError::Quirks(error)
+    }
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::Io(error) => error.fmt(f),
+            ConfigError::Parse(error) => error.fmt(f),
+            ConfigError::Palette(error) => write!(f, "invalid palette: {}", error),
+            ConfigError::Persistence(error) => write!(f, "invalid persistence: {}", error),
+            ConfigError::Filter(error) => write!(f, "invalid filter: {}", error),
+            ConfigError::Quirks(error) => write!(f, "invalid quirks: {}", error),
+            ConfigError::OutOfRange(setting) => write!(f, "{} is out of range", setting),
+        }
+    }
+}
+
+impl Error for ConfigError {}
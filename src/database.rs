@@ -0,0 +1,309 @@
+//! Metadata about known ROMs, in the format of the community CHIP-8 database. ROMs are looked up by
+//! the SHA-1 of their contents, and matches give the title and authors along with the platform the
+//! ROM was written for and how it should be run: quirks, speed, keys and colours.
+//!
+//! A copy of the database is built in from `data/chip-8-database`, and a newer one can be loaded
+//! from a directory holding the same three files.
+
+use crate::quirks::Quirks;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::fmt::Formatter;
+use std::path::Path;
+
+const BUNDLED_PROGRAMS: &str = include_str!("../data/chip-8-database/programs.json");
+const BUNDLED_HASHES: &str = include_str!("../data/chip-8-database/sha1-hashes.json");
+const BUNDLED_PLATFORMS: &str = include_str!("../data/chip-8-database/platforms.json");
+
+/// The database's programs, the index of each ROM's program and the platforms ROMs run on.
+pub struct Database {
+    programs: Vec<Program>,
+    hashes: HashMap<String, usize>,
+    platforms: Vec<Platform>,
+}
+
+/// A program, which can have several ROMs for different versions or platforms.
+#[derive(Debug, Deserialize)]
+pub struct Program {
+    pub title: String,
+    #[serde(default)]
+    pub authors: Vec<String>,
+    #[serde(default)]
+    pub roms: HashMap<String, Rom>,
+}
+
+/// How to run one of a program's ROMs.
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Rom {
+    /// The platforms the ROM runs on, with the best first.
+    #[serde(default)]
+    pub platforms: Vec<String>,
+    /// Instructions per frame, when the platform's usual speed doesn't suit the ROM.
+    pub tickrate: Option<usize>,
+    /// Hex keys for the actions the ROM uses, such as `up` or `player2A`.
+    #[serde(default)]
+    pub keys: HashMap<String, u8>,
+    pub colors: Option<Colours>,
+    /// Quirks that differ from the platform's usual ones, by platform.
+    #[serde(default)]
+    pub quirky_platforms: HashMap<String, QuirkFlags>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Colours {
+    /// The background colour, followed by the colours of lit pixels.
+    #[serde(default)]
+    pub pixels: Vec<String>,
+}
+
+/// A platform, such as the COSMAC VIP or XO-CHIP.
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Platform {
+    pub id: String,
+    pub name: String,
+    pub default_tickrate: Option<usize>,
+    #[serde(default)]
+    pub quirks: QuirkFlags,
+}
+
+/// Quirks as the database describes them. Anything left out keeps the value it already has.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct QuirkFlags {
+    /// 8XY6 and 8XYE shift VX in place, ignoring VY.
+    pub shift: Option<bool>,
+    /// FX55 and FX65 increment I by X rather than X + 1.
+    pub memory_increment_by_x: Option<bool>,
+    /// FX55 and FX65 leave I unchanged.
+    pub memory_leave_i_unchanged: Option<bool>,
+    /// Sprites wrap around the edges of the display.
+    pub wrap: Option<bool>,
+    /// BNNN jumps to NNN plus VX.
+    pub jump: Option<bool>,
+    /// Drawing waits for the next frame. Chemu doesn't support this.
+    pub vblank: Option<bool>,
+    /// 8XY1, 8XY2 and 8XY3 reset VF.
+    pub logic: Option<bool>,
+}
+
+impl QuirkFlags {
+    /// Applies the flags that are set on top of some quirks.
+    fn apply(&self, quirks: &mut Quirks) {
+        if let Some(shift) = self.shift {
+            quirks.shift_reads_vy = !shift;
+        }
+        if let Some(leave_i_unchanged) = self.memory_leave_i_unchanged {
+            quirks.load_store_increments_i = !leave_i_unchanged;
+        }
+        if let Some(increment_by_x) = self.memory_increment_by_x {
+            quirks.load_store_increments_by_x = increment_by_x;
+        }
+        if let Some(wrap) = self.wrap {
+            quirks.clip_sprites = !wrap;
+        }
+        if let Some(jump) = self.jump {
+            quirks.jump_uses_vx = jump;
+        }
+        if let Some(logic) = self.logic {
+            quirks.logic_resets_vf = logic;
+        }
+    }
+}
+
+/// A ROM found in the database.
+pub struct Match<'a> {
+    pub program: &'a Program,
+    pub rom: &'a Rom,
+    /// The first of the ROM's platforms that the database describes.
+    pub platform: Option<&'a Platform>,
+}
+
+impl Match<'_> {
+    /// The quirks of the ROM's platform, adjusted for the ROM where it needs them to be.
+    pub fn quirks(&self) -> Option<Quirks> {
+        let platform = self.platform?;
+        let mut quirks = Quirks::default();
+        platform.quirks.apply(&mut quirks);
+        if let Some(flags) = self.rom.quirky_platforms.get(&platform.id) {
+            flags.apply(&mut quirks);
+        }
+        Some(quirks)
+    }
+
+    /// Instructions per frame for the ROM, or its platform's usual number.
+    pub fn tickrate(&self) -> Option<usize> {
+        self.rom
+            .tickrate
+            .or_else(|| self.platform?.default_tickrate)
+            .filter(|&tickrate| tickrate > 0)
+    }
+
+    /// The ROM's colours, as accepted by `Palette::parse`. Only the first four are used, since
+    /// Chemu draws at most two bit planes.
+    pub fn palette(&self) -> Option<String> {
+        let colours = self.rom.colors.as_ref()?;
+        if colours.pixels.is_empty() {
+            return None;
+        }
+        let count = colours.pixels.len().min(4);
+        Some(colours.pixels[..count].join(","))
+    }
+
+    /// A title for the window, with the program's authors if they're known.
+    pub fn window_title(&self) -> String {
+        if self.program.authors.is_empty() {
+            self.program.title.clone()
+        } else {
+            format!(
+                "{} by {}",
+                self.program.title,
+                self.program.authors.join(", ")
+            )
+        }
+    }
+}
+
+impl Database {
+    /// The copy of the database built into Chemu.
+    pub fn bundled() -> Database {
+        Database::parse(BUNDLED_PROGRAMS, BUNDLED_HASHES, BUNDLED_PLATFORMS)
+            .expect("the bundled database is valid")
+    }
+
+    /// Loads the database from a directory holding `programs.json`, `sha1-hashes.json` and
+    /// `platforms.json`.
+    pub fn load(dir: &Path) -> Result<Database, DatabaseError> {
+        let read = |name: &str| std::fs::read_to_string(dir.join(name));
+        Database::parse(
+            &read("programs.json")?,
+            &read("sha1-hashes.json")?,
+            &read("platforms.json")?,
+        )
+    }
+
+    fn parse(programs: &str, hashes: &str, platforms: &str) -> Result<Database, DatabaseError> {
+        let programs: Vec<Program> = serde_json::from_str(programs)?;
+        let hashes: HashMap<String, usize> = serde_json::from_str(hashes)?;
+        if let Some((hash, _)) = hashes.iter().find(|(_, &index)| index >= programs.len()) {
+            return Err(DatabaseError::MissingProgram(hash.clone()));
+        }
+
+        Ok(Database {
+            programs,
+            hashes: hashes
+                .into_iter()
+                .map(|(hash, index)| (hash.to_ascii_lowercase(), index))
+                .collect(),
+            platforms: serde_json::from_str(platforms)?,
+        })
+    }
+
+    /// Finds a platform by its id, such as `superchip`.
+    pub fn platform(&self, id: &str) -> Option<&Platform> {
+        self.platforms.iter().find(|platform| platform.id == id)
+    }
+
+    /// Looks a ROM up by the SHA-1 of its contents, in hex.
+    pub fn lookup(&self, sha1: &str) -> Option<Match<'_>> {
+        let sha1 = sha1.to_ascii_lowercase();
+        let program = &self.programs[*self.hashes.get(&sha1)?];
+        let rom = program
+            .roms
+            .iter()
+            .find(|(hash, _)| hash.eq_ignore_ascii_case(&sha1))
+            .map(|(_, rom)| rom)?;
+        let platform = rom.platforms.iter().find_map(|id| self.platform(id));
+
+        Some(Match {
+            program,
+            rom,
+            platform,
+        })
+    }
+}
+
+/// Error that occurs while loading the database.
+#[derive(Debug)]
+pub enum DatabaseError {
+    /// One of the database files couldn't be read.
+    Io(std::io::Error),
+    /// One of the database files isn't valid JSON or doesn't match the expected structure.
+    Parse(serde_json::Error),
+    /// A hash refers to a program that isn't in the list of programs.
+    MissingProgram(String),
+}
+
+impl From<std::io::Error> for DatabaseError {
+    fn from(error: std::io::Error) -> Self {
+        DatabaseError::Io(error)
+    }
+}
+
+impl From<serde_json::Error> for DatabaseError {
+    fn from(error: serde_json::Error) -> Self {
+        DatabaseError::Parse(error)
+    }
+}
+
+impl fmt::Display for DatabaseError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            DatabaseError::Io(error) => error.fmt(f),
+            DatabaseError::Parse(error) => error.fmt(f),
+            DatabaseError::MissingProgram(hash) => {
+                write!(f, "no program for ROM with SHA-1 {}", hash)
+            }
+        }
+    }
+}
+
+impl Error for DatabaseError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PROGRAMS: &str = r#"[{
+        "title": "Test",
+        "roms": {
+            "0123456789abcdef0123456789abcdef01234567": { "platforms": ["chip48"] },
+            "89abcdef0123456789abcdef0123456789abcdef": {
+                "platforms": ["chip48"],
+                "quirkyPlatforms": { "chip48": { "memoryIncrementByX": false } }
+            }
+        }
+    }]"#;
+    const HASHES: &str = r#"{
+        "0123456789abcdef0123456789abcdef01234567": 0,
+        "89ABCDEF0123456789ABCDEF0123456789ABCDEF": 0
+    }"#;
+
+    #[test]
+    fn bundled_database_parses() {
+        let database = Database::bundled();
+        assert!(database.platform("chip48").is_some());
+    }
+
+    #[test]
+    fn memory_increment_by_x_maps_onto_quirk() {
+        let database = Database::parse(PROGRAMS, HASHES, BUNDLED_PLATFORMS).unwrap();
+
+        let quirks = database
+            .lookup("0123456789abcdef0123456789abcdef01234567")
+            .and_then(|found| found.quirks())
+            .unwrap();
+        assert!(quirks.load_store_increments_i);
+        assert!(quirks.load_store_increments_by_x);
+
+        let quirks = database
+            .lookup("89abcdef0123456789abcdef0123456789abcdef")
+            .and_then(|found| found.quirks())
+            .unwrap();
+        assert!(quirks.load_store_increments_i);
+        assert!(!quirks.load_store_increments_by_x);
+    }
+}
@@ -0,0 +1,331 @@
+//! Guesses which platform a ROM was written for by scanning its code, for ROMs the database doesn't
+//! know. Code is found by following jumps, calls and skips from the start of the program, so data
+//! such as sprites isn't mistaken for instructions.
+//!
+//! Opcodes that only exist in SUPER-CHIP or XO-CHIP give the platform away, though Chemu can't run
+//! those platforms' programs. Plain CHIP-8 programs can still hint at the interpreter they expect
+//! through how they use shifts and FX55/FX65.
+
+use crate::instruction;
+use crate::instruction::Instruction;
+use crate::quirks::Quirks;
+use std::fmt;
+use std::fmt::Formatter;
+
+/// Where programs are loaded into memory.
+const PROGRAM_START: usize = 0x200;
+const MEMORY_SIZE: usize = 4096;
+/// The XO-CHIP instruction that loads a 16-bit address, which is followed by the address.
+const LONG_LOAD: u16 = 0xF000;
+
+/// A platform a ROM can be written for.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Platform {
+    Chip8,
+    SuperChip,
+    XoChip,
+}
+
+impl Platform {
+    pub fn name(self) -> &'static str {
+        match self {
+            Platform::Chip8 => "CHIP-8",
+            Platform::SuperChip => "SUPER-CHIP",
+            Platform::XoChip => "XO-CHIP",
+        }
+    }
+
+    /// Whether Chemu can run the platform's programs. It only has CHIP-8's instructions, so
+    /// SUPER-CHIP and XO-CHIP programs fault at their first extended opcode.
+    pub fn is_supported(self) -> bool {
+        self == Platform::Chip8
+    }
+}
+
+/// How sure a guess is.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Confidence {
+    /// Nothing in the code points either way, so the guess is just the default.
+    Low,
+    /// A single hint, which a stray instruction could give by accident.
+    Medium,
+    /// Several hints that agree.
+    High,
+}
+
+impl fmt::Display for Confidence {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Confidence::Low => "low",
+            Confidence::Medium => "medium",
+            Confidence::High => "high",
+        })
+    }
+}
+
+/// Something found in a ROM's code that hints at its platform or quirks.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Evidence {
+    /// Where the first instance was found.
+    pub address: usize,
+    pub description: &'static str,
+    /// How many times it was found.
+    pub count: usize,
+}
+
+/// The result of scanning a ROM.
+#[derive(Clone, Debug)]
+pub struct Detection {
+    pub platform: Platform,
+    /// The quirks the code relies on, if they differ from Chemu's own and Chemu can run the
+    /// platform.
+    pub quirks: Option<Quirks>,
+    pub confidence: Confidence,
+    /// What the guess is based on, in the order it was first found.
+    pub evidence: Vec<Evidence>,
+    /// How many instructions were found by following the code.
+    pub instructions: usize,
+}
+
+/// Scans the code reachable from the start of a ROM and guesses its platform and quirks.
+pub fn detect(rom: &[u8]) -> Detection {
+    let end = PROGRAM_START + rom.len().min(MEMORY_SIZE - PROGRAM_START);
+    let word_at = |address: usize| -> Option<u16> {
+        if address >= PROGRAM_START && address + 1 < end {
+            let offset = address - PROGRAM_START;
+            Some(u16::from_be_bytes([rom[offset], rom[offset + 1]]))
+        } else {
+            None
+        }
+    };
+    // XO-CHIP's long load is four bytes, so skipping over it skips all four
+    let size_at = |address: usize| match word_at(address) {
+        Some(LONG_LOAD) => 4,
+        _ => 2,
+    };
+
+    let mut scan = Scan::default();
+    let mut visited = vec![false; MEMORY_SIZE];
+    let mut pending = vec![PROGRAM_START];
+    while let Some(address) = pending.pop() {
+        let opcode = match word_at(address) {
+            Some(opcode) if !visited[address] => opcode,
+            _ => continue,
+        };
+        visited[address] = true;
+        scan.instructions += 1;
+        let next = address + size_at(address);
+
+        let extension = extension_opcode(opcode);
+        if let Some((platform, description)) = extension {
+            scan.note(platform, address, description);
+        }
+
+        match instruction::decode(opcode) {
+            // 00FD exits the interpreter, and the rest of the 0NNN range just carries on
+            Ok(Instruction::Sys { addr: 0x0FD }) => {}
+            Ok(Instruction::Jmp { addr }) => pending.push(addr as usize),
+            Ok(Instruction::Call { addr }) => {
+                pending.push(next);
+                pending.push(addr as usize);
+            }
+            // Where these go depends on registers and the stack, which a static scan can't know
+            Ok(Instruction::Ret) | Ok(Instruction::JmpOff { .. }) => {}
+            Ok(Instruction::SeImm { .. })
+            | Ok(Instruction::SneImm { .. })
+            | Ok(Instruction::SeReg { .. })
+            | Ok(Instruction::SneReg { .. })
+            | Ok(Instruction::Skp { .. })
+            | Ok(Instruction::SkpNeg { .. }) => {
+                pending.push(next + size_at(next));
+                pending.push(next);
+            }
+            // Naming a separate VY only makes sense if the interpreter shifts it, which Chemu does
+            // already, so this just points towards the VIP's CHIP-8
+            Ok(Instruction::Shr { dest, src }) | Ok(Instruction::Shl { dest, src }) => {
+                if src as u8 != dest as u8 {
+                    scan.shifts_from_vy.push(address);
+                }
+                pending.push(next);
+            }
+            Ok(instruction) => {
+                if repeats_load_store(&instruction, address, &word_at) {
+                    scan.repeated_load_stores.push(address);
+                }
+                pending.push(next);
+            }
+            // Extension opcodes carry on to the next instruction. Anything else is probably data
+            // reached by a skip over a jump, so the path ends there.
+            Err(_) if extension.is_some() => pending.push(next),
+            Err(_) => {}
+        }
+    }
+
+    scan.finish()
+}
+
+/// Recognises opcodes that only SUPER-CHIP or XO-CHIP have. XO-CHIP has every SUPER-CHIP opcode
+/// too, so those are reported as SUPER-CHIP.
+fn extension_opcode(opcode: u16) -> Option<(Platform, &'static str)> {
+    let x = (opcode >> 8) & 0xF;
+    Some(match opcode {
+        0x00C1..=0x00CF => (Platform::SuperChip, "00CN scrolls down"),
+        0x00FB => (Platform::SuperChip, "00FB scrolls right"),
+        0x00FC => (Platform::SuperChip, "00FC scrolls left"),
+        0x00FD => (Platform::SuperChip, "00FD exits"),
+        0x00FE => (Platform::SuperChip, "00FE switches to low resolution"),
+        0x00FF => (Platform::SuperChip, "00FF switches to high resolution"),
+        _ if opcode & 0xF00F == 0xD000 => (Platform::SuperChip, "DXY0 draws a 16x16 sprite"),
+        _ if opcode & 0xF0FF == 0xF030 => (Platform::SuperChip, "FX30 points I at a large digit"),
+        _ if opcode & 0xF0FF == 0xF075 && x < 8 => {
+            (Platform::SuperChip, "FX75 saves flag registers")
+        }
+        _ if opcode & 0xF0FF == 0xF085 && x < 8 => {
+            (Platform::SuperChip, "FX85 loads flag registers")
+        }
+        0x00D1..=0x00DF => (Platform::XoChip, "00DN scrolls up"),
+        LONG_LOAD => (Platform::XoChip, "F000 NNNN loads a 16-bit address"),
+        0xF002 => (Platform::XoChip, "F002 loads an audio pattern"),
+        _ if opcode & 0xF00F == 0x5002 => (Platform::XoChip, "5XY2 stores a range of registers"),
+        _ if opcode & 0xF00F == 0x5003 => (Platform::XoChip, "5XY3 loads a range of registers"),
+        _ if opcode & 0xF0FF == 0xF001 => (Platform::XoChip, "FN01 selects bit planes"),
+        _ if opcode & 0xF0FF == 0xF03A => (Platform::XoChip, "FX3A sets the pitch"),
+        // Flag registers past V7 only exist on XO-CHIP
+        _ if opcode & 0xF0FF == 0xF075 => (Platform::XoChip, "FX75 saves flags past V7"),
+        _ if opcode & 0xF0FF == 0xF085 => (Platform::XoChip, "FX85 loads flags past V7"),
+        _ => return None,
+    })
+}
+
+/// Whether a store is followed straight away by another store of at least as many registers, or a
+/// load by another load. Without I advancing in between, the second store would overwrite the
+/// first and the second load would read the same bytes again, so the program expects I to advance.
+/// A store followed by a load proves nothing, since reading back what was just stored is harmless.
+fn repeats_load_store(
+    instruction: &Instruction,
+    address: usize,
+    word_at: &impl Fn(usize) -> Option<u16>,
+) -> bool {
+    let next = match word_at(address + 2).and_then(|opcode| instruction::decode(opcode).ok()) {
+        Some(next) => next,
+        None => return false,
+    };
+    match (instruction, next) {
+        (Instruction::StrArray { end }, Instruction::StrArray { end: next_end }) => {
+            next_end as u8 >= *end as u8
+        }
+        (Instruction::LdArray { .. }, Instruction::LdArray { .. }) => true,
+        _ => false,
+    }
+}
+
+#[derive(Default)]
+struct Scan {
+    instructions: usize,
+    evidence: Vec<(Platform, Evidence)>,
+    /// Shifts that name a source register other than the destination, which only makes sense if
+    /// the interpreter shifts VY.
+    shifts_from_vy: Vec<usize>,
+    /// Stores and loads followed straight away by another of the same kind.
+    repeated_load_stores: Vec<usize>,
+}
+
+impl Scan {
+    fn note(&mut self, platform: Platform, address: usize, description: &'static str) {
+        match self
+            .evidence
+            .iter_mut()
+            .find(|(_, evidence)| evidence.description == description)
+        {
+            Some((_, evidence)) => evidence.count += 1,
+            None => self.evidence.push((
+                platform,
+                Evidence {
+                    address,
+                    description,
+                    count: 1,
+                },
+            )),
+        }
+    }
+
+    fn finish(mut self) -> Detection {
+        let platform = if self.has(Platform::XoChip) {
+            Platform::XoChip
+        } else if self.has(Platform::SuperChip) {
+            Platform::SuperChip
+        } else {
+            Platform::Chip8
+        };
+
+        // CHIP-8 idioms only matter for CHIP-8 programs. Extended ones can't run, so there are no
+        // quirks to suggest for them.
+        let mut quirks = None;
+        if platform == Platform::Chip8 {
+            if let Some(&address) = self.shifts_from_vy.first() {
+                let count = self.shifts_from_vy.len();
+                self.note_idiom(address, "8XY6/8XYE shift a separate VY", count);
+            }
+            // One pair on its own is too little to change what every FX55 and FX65 in the program
+            // does, so it's only reported
+            if let Some(&address) = self.repeated_load_stores.first() {
+                let count = self.repeated_load_stores.len();
+                self.note_idiom(
+                    address,
+                    "back-to-back FX55s or FX65s rely on I advancing",
+                    count,
+                );
+                if count > 1 {
+                    quirks = Some(Quirks {
+                        load_store_increments_i: true,
+                        ..Quirks::default()
+                    });
+                }
+            }
+        }
+
+        // Hints for the chosen platform count towards the confidence; SUPER-CHIP opcodes in an
+        // XO-CHIP program are expected either way
+        let hints: Vec<&Evidence> = self
+            .evidence
+            .iter()
+            .filter(|(found_on, _)| *found_on == platform)
+            .map(|(_, evidence)| evidence)
+            .collect();
+        let total: usize = hints.iter().map(|evidence| evidence.count).sum();
+        let confidence = match (hints.len(), total) {
+            (0, _) => Confidence::Low,
+            (1, 1) => Confidence::Medium,
+            _ => Confidence::High,
+        };
+
+        Detection {
+            platform,
+            quirks,
+            confidence,
+            evidence: self
+                .evidence
+                .into_iter()
+                .map(|(_, evidence)| evidence)
+                .collect(),
+            instructions: self.instructions,
+        }
+    }
+
+    fn has(&self, platform: Platform) -> bool {
+        self.evidence
+            .iter()
+            .any(|(found_on, _)| *found_on == platform)
+    }
+
+    fn note_idiom(&mut self, address: usize, description: &'static str, count: usize) {
+        self.evidence.push((
+            Platform::Chip8,
+            Evidence {
+                address,
+                description,
+                count,
+            },
+        ));
+    }
+}
@@ -1,40 +1,17 @@
-use sdl2::pixels::Color;
-use sdl2::rect::Rect;
-use sdl2::render::WindowCanvas;
-use sdl2::Sdl;
-
-const WIDTH: usize = 64;
-const HEIGHT: usize = 32;
-const OFF_COLOUR: Color = Color::RGB(0, 0, 0);
-const ON_COLOUR: Color = Color::RGB(255, 255, 255);
+pub const WIDTH: usize = 64;
+pub const HEIGHT: usize = 32;
 
+/// The Chip-8 framebuffer. Frontends read the pixels from here to present them.
 pub struct Display {
-    width: u32,
-    height: u32,
     pixels: [[bool; WIDTH]; HEIGHT],
-    canvas: WindowCanvas,
     update_pending: bool,
 }
 
 impl Display {
-    pub fn new(sdl_context: Sdl, width: u32, height: u32) -> Display {
-        let video = sdl_context.video().unwrap();
-        let window = video
-            .window("Chemu", width, height)
-            .position_centered()
-            .build()
-            .unwrap();
-        let mut canvas = window.into_canvas().build().unwrap();
-        canvas.set_draw_color(OFF_COLOUR);
-        canvas.clear();
-        canvas.present();
-
+    pub fn new() -> Display {
         Display {
-            width,
-            height,
             pixels: [[false; WIDTH]; HEIGHT],
-            canvas,
-            update_pending: false,
+            update_pending: true,
         }
     }
 
@@ -48,14 +25,31 @@ impl Display {
         self.update_pending = true;
     }
 
+    /// XORs a sprite onto the display, wrapping around the edges. Returns whether any lit pixel
+    /// was turned off.
     pub fn draw(&mut self, x: usize, y: usize, sprite: &[u8]) -> bool {
+        self.draw_sprite(x, y, sprite, false)
+    }
+
+    /// XORs a sprite onto the display, cutting it off at the edges. The sprite's position still
+    /// wraps around, so it always starts on the display. Returns whether any lit pixel was turned
+    /// off.
+    pub fn draw_clipped(&mut self, x: usize, y: usize, sprite: &[u8]) -> bool {
+        self.draw_sprite(x, y, sprite, true)
+    }
+
+    fn draw_sprite(&mut self, x: usize, y: usize, sprite: &[u8], clip: bool) -> bool {
+        let (x, y) = (x % WIDTH, y % HEIGHT);
         let mut overwritten = false;
 
         for (i, &row) in sprite.iter().enumerate() {
+            if clip && y + i >= HEIGHT {
+                break;
+            }
             let mut mask: u8 = 0x80;
             for j in 0..8 {
                 let pixel = mask & row;
-                if pixel != 0 {
+                if pixel != 0 && !(clip && x + j >= WIDTH) {
                     // Flip pixel
                     if self.pixels[(y + i) % HEIGHT][(x + j) % WIDTH] {
                         overwritten = true;
@@ -72,32 +66,28 @@ impl Display {
         overwritten
     }
 
-    pub fn update(&mut self) {
-        if !self.update_pending {
-            return;
-        }
-
-        let height_scale = self.height / HEIGHT as u32;
-        let width_scale = self.width / WIDTH as u32;
+    pub fn pixels(&self) -> &[[bool; WIDTH]; HEIGHT] {
+        &self.pixels
+    }
 
-        self.canvas.set_draw_color(OFF_COLOUR);
-        self.canvas.clear();
-        self.canvas.set_draw_color(ON_COLOUR);
+    /// Replaces every pixel, such as when restoring a saved state.
+    pub fn set_pixels(&mut self, pixels: [[bool; WIDTH]; HEIGHT]) {
+        self.pixels = pixels;
+        self.update_pending = true;
+    }
 
-        for (j, row) in self.pixels.iter().enumerate() {
-            let y_scaled = j * height_scale as usize;
-            for (i, pixel) in row.iter().enumerate() {
-                if *pixel {
-                    let x_scaled = i * width_scale as usize;
-                    let rect =
-                        Rect::new(x_scaled as i32, y_scaled as i32, width_scale, height_scale);
-                    self.canvas.draw_rect(rect).unwrap();
-                    self.canvas.fill_rect(rect).unwrap();
-                }
-            }
-        }
+    /// Whether the pixels have changed since the frontend last presented them.
+    pub fn update_pending(&self) -> bool {
+        self.update_pending
+    }
 
-        self.canvas.present();
+    pub fn mark_presented(&mut self) {
         self.update_pending = false;
     }
 }
+
+impl Default for Display {
+    fn default() -> Self {
+        Display::new()
+    }
+}
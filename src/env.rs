@@ -0,0 +1,437 @@
+//! An environment for training agents on CHIP-8 games, in the style of OpenAI Gym. Each step
+//! holds down the key chosen by the agent for a number of frames, then reports the display, the
+//! reward earned and whether the episode is over. Rewards come from a per-ROM `RewardSpec`
+//! describing where the game keeps its score and lives.
+//!
+//! ```toml
+//! # A game that keeps its score at 0x2F0, its lives in VE and sets V9 to 1 at game over
+//! score = { memory = 0x2F0 }
+//! lives = { register = 14 }
+//! done_when = { register = 9, equals = 1 }
+//! max_steps = 10000
+//! ```
+
+use crate::display::{HEIGHT, WIDTH};
+use crate::keyboard::Key;
+use crate::machine::{Machine, INSTRUCTIONS_PER_FRAME};
+use serde::de::Error;
+use serde::Deserialize;
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::thread::{self, JoinHandle};
+
+/// The display, as seen by the agent.
+pub type Observation = [[bool; WIDTH]; HEIGHT];
+
+/// The key to hold down for a step, if any.
+pub type Action = Option<Key>;
+
+/// What a step returns: the display afterwards, the reward earned and whether the episode is over.
+pub type Step = (Observation, f64, bool);
+
+/// Number of V registers a `Location` can name.
+const REGISTERS: usize = 16;
+
+/// Somewhere in the machine a game keeps a number.
+#[derive(Copy, Clone, Debug, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Location {
+    /// A byte of memory.
+    Memory(usize),
+    /// One of V0 to VF, numbered from 0 to 15.
+    Register(usize),
+}
+
+impl Location {
+    fn read(self, machine: &Machine) -> u8 {
+        match self {
+            Location::Memory(address) => machine.memory().get(address).copied().unwrap_or(0),
+            Location::Register(index) => machine.registers().get(index).copied().unwrap_or(0),
+        }
+    }
+}
+
+/// Ends the episode once a location holds a particular value, such as a game over flag.
+#[derive(Copy, Clone, Debug, Deserialize)]
+pub struct Condition {
+    #[serde(flatten)]
+    pub location: Location,
+    pub equals: u8,
+}
+
+/// How rewards and the end of an episode are read from a particular ROM.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RewardSpec {
+    /// Counter whose increase is given as reward, such as the score.
+    pub score: Option<Location>,
+    /// Counter whose decrease is given as a penalty of 1 per life lost. The episode ends when it
+    /// drops to zero, but not while it's still zero from before the game has set it.
+    pub lives: Option<Location>,
+    /// Ends the episode when this condition is met.
+    pub done_when: Option<Condition>,
+    /// Ends the episode after this many steps, since some games never end on their own.
+    pub max_steps: Option<u64>,
+}
+
+impl RewardSpec {
+    /// Parses a spec written in TOML.
+    pub fn from_toml(spec: &str) -> Result<RewardSpec, toml::de::Error> {
+        let spec: RewardSpec = toml::from_str(spec)?;
+        spec.check().map_err(toml::de::Error::custom)?;
+        Ok(spec)
+    }
+
+    /// Makes sure every location the spec reads exists, so a typo doesn't quietly read 0.
+    fn check(&self) -> Result<(), String> {
+        let locations = [
+            self.score,
+            self.lives,
+            self.done_when.map(|condition| condition.location),
+        ];
+        for location in locations.iter().flatten() {
+            if let Location::Register(index) = *location {
+                if index >= REGISTERS {
+                    return Err(format!(
+                        "register {} doesn't exist, registers go from 0 to {}",
+                        index,
+                        REGISTERS - 1
+                    ));
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A single game being played by an agent.
+pub struct Environment {
+    rom: Vec<u8>,
+    spec: RewardSpec,
+    frame_skip: usize,
+    machine: Machine,
+    held: Action,
+    score: u8,
+    lives: u8,
+    steps: u64,
+    done: bool,
+}
+
+impl Environment {
+    /// Creates an environment playing the ROM. Each step runs `frame_skip` frames, which must be
+    /// at least 1, and the spec can only read registers V0 to VF.
+    pub fn new(rom: &[u8], spec: RewardSpec, frame_skip: usize) -> Environment {
+        assert!(frame_skip > 0, "each step must run at least one frame");
+        if let Err(e) = spec.check() {
+            panic!("{}", e);
+        }
+
+        let mut environment = Environment {
+            rom: rom.to_vec(),
+            spec,
+            frame_skip,
+            machine: Machine::from_rom(rom),
+            held: None,
+            score: 0,
+            lives: 0,
+            steps: 0,
+            done: false,
+        };
+        environment.reset();
+        environment
+    }
+
+    /// Starts a new episode from the beginning of the ROM.
+    pub fn reset(&mut self) -> Observation {
+        self.machine = Machine::from_rom(&self.rom);
+        self.start_episode()
+    }
+
+    /// Starts a new episode with the random number generator seeded, so that the episode plays out
+    /// the same way every time the agent takes the same actions.
+    pub fn reset_with_seed(&mut self, seed: u64) -> Observation {
+        self.machine = Machine::from_rom(&self.rom);
+        self.machine.seed_random(seed);
+        self.start_episode()
+    }
+
+    fn start_episode(&mut self) -> Observation {
+        self.held = None;
+        self.score = self.spec.score.map_or(0, |score| score.read(&self.machine));
+        self.lives = self.spec.lives.map_or(0, |lives| lives.read(&self.machine));
+        self.steps = 0;
+        self.done = false;
+        *self.machine.display().pixels()
+    }
+
+    /// Holds down the action's key, releasing any other, and runs the machine for a step. Once the
+    /// episode is over, steps do nothing until the environment is reset. Episodes also end when the
+    /// program hits an instruction the emulator can't run, since the machine halts there.
+    pub fn step(&mut self, action: Action) -> Step {
+        if self.done {
+            return (*self.machine.display().pixels(), 0.0, true);
+        }
+
+        if action != self.held {
+            if let Some(key) = self.held {
+                self.machine.keyboard_mut().release(key);
+            }
+            if let Some(key) = action {
+                self.machine.keyboard_mut().press(key);
+            }
+            self.held = action;
+        }
+
+        for _ in 0..self.frame_skip {
+            self.machine.run_frame(INSTRUCTIONS_PER_FRAME);
+        }
+        self.steps += 1;
+
+        let mut reward = 0.0;
+        if let Some(score) = self.spec.score {
+            let new_score = score.read(&self.machine);
+            // Scores kept in a byte wrap around, so the change is taken modulo 256
+            reward += new_score.wrapping_sub(self.score) as i8 as f64;
+            self.score = new_score;
+        }
+        if let Some(lives) = self.spec.lives {
+            let new_lives = lives.read(&self.machine);
+            if new_lives < self.lives {
+                reward -= (self.lives - new_lives) as f64;
+            }
+            // Games set their lives some way in, often after a title screen, so until then the
+            // counter is just the 0 memory starts out as
+            self.done |= self.lives > 0 && new_lives == 0;
+            self.lives = new_lives;
+        }
+        if let Some(condition) = self.spec.done_when {
+            self.done |= condition.location.read(&self.machine) == condition.equals;
+        }
+        if let Some(max_steps) = self.spec.max_steps {
+            self.done |= self.steps >= max_steps;
+        }
+        self.done |= self.machine.fault().is_some();
+
+        (*self.machine.display().pixels(), reward, self.done)
+    }
+
+    pub fn machine(&self) -> &Machine {
+        &self.machine
+    }
+}
+
+enum Command {
+    /// Resets every environment, seeding them in order from the given seed if there is one.
+    Reset(Option<u64>),
+    Step(Vec<Action>),
+}
+
+/// A thread stepping its share of a `VecEnvironment`'s environments.
+struct Worker {
+    commands: Sender<Command>,
+    results: Receiver<Vec<Step>>,
+    count: usize,
+    thread: JoinHandle<()>,
+}
+
+/// Many copies of an environment, stepped in parallel on a pool of threads. Environments whose
+/// episodes end are reset straight away, so the observation returned with `done` set is the
+/// first of the next episode. If the environments were last reset with a seed, these resets are
+/// seeded too.
+pub struct VecEnvironment {
+    workers: Vec<Worker>,
+}
+
+impl VecEnvironment {
+    /// Creates `count` environments playing the ROM, spread evenly over `threads` threads.
+    pub fn new(
+        rom: &[u8],
+        spec: RewardSpec,
+        frame_skip: usize,
+        count: usize,
+        threads: usize,
+    ) -> VecEnvironment {
+        let threads = threads.clamp(1, count.max(1));
+        let mut workers = Vec::with_capacity(threads);
+        let mut first = 0;
+
+        for i in 0..threads {
+            // Earlier threads take one extra environment each when they don't divide evenly
+            let share = count / threads + (i < count % threads) as usize;
+            let environments: Vec<Environment> = (0..share)
+                .map(|_| Environment::new(rom, spec.clone(), frame_skip))
+                .collect();
+
+            let (commands, command_receiver) = channel();
+            let (result_sender, results) = channel();
+            let thread = thread::spawn(move || {
+                run_worker(environments, first, count, command_receiver, result_sender)
+            });
+            workers.push(Worker {
+                commands,
+                results,
+                count: share,
+                thread,
+            });
+            first += share;
+        }
+
+        VecEnvironment { workers }
+    }
+
+    pub fn len(&self) -> usize {
+        self.workers.iter().map(|worker| worker.count).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Starts a new episode in every environment.
+    pub fn reset(&mut self) -> Vec<Observation> {
+        self.reset_all(None)
+    }
+
+    /// Starts a new episode in every environment, seeding the first with `seed`, the second with
+    /// `seed + 1` and so on. When an episode ends, the environment's next episode is seeded with
+    /// its last seed plus the number of environments, so no two episodes share a seed and the
+    /// whole run can be repeated.
+    pub fn reset_with_seed(&mut self, seed: u64) -> Vec<Observation> {
+        self.reset_all(Some(seed))
+    }
+
+    fn reset_all(&mut self, seed: Option<u64>) -> Vec<Observation> {
+        for worker in &self.workers {
+            worker.commands.send(Command::Reset(seed)).unwrap();
+        }
+
+        self.collect()
+            .into_iter()
+            .map(|(observation, _, _)| observation)
+            .collect()
+    }
+
+    /// Steps every environment, taking one action for each.
+    pub fn step(&mut self, actions: &[Action]) -> Vec<Step> {
+        assert_eq!(
+            actions.len(),
+            self.len(),
+            "every environment needs an action"
+        );
+
+        let mut actions = actions;
+        for worker in &self.workers {
+            let (share, rest) = actions.split_at(worker.count);
+            worker.commands.send(Command::Step(share.to_vec())).unwrap();
+            actions = rest;
+        }
+
+        self.collect()
+    }
+
+    fn collect(&self) -> Vec<Step> {
+        let mut steps = Vec::with_capacity(self.len());
+        for worker in &self.workers {
+            steps.extend(worker.results.recv().expect("environment thread panicked"));
+        }
+        steps
+    }
+}
+
+impl Drop for VecEnvironment {
+    fn drop(&mut self) {
+        for worker in self.workers.drain(..) {
+            // Closing the channel tells the worker to stop
+            drop(worker.commands);
+            let _ = worker.thread.join();
+        }
+    }
+}
+
+/// Runs commands for a share of the environments, the first of which is number `first` out of
+/// `count` overall.
+fn run_worker(
+    mut environments: Vec<Environment>,
+    first: usize,
+    count: usize,
+    commands: Receiver<Command>,
+    results: Sender<Vec<Step>>,
+) {
+    // The seed each environment's current episode started with, if it was seeded
+    let mut seeds: Vec<Option<u64>> = vec![None; environments.len()];
+
+    for command in commands {
+        let steps = match command {
+            Command::Reset(seed) => environments
+                .iter_mut()
+                .zip(seeds.iter_mut())
+                .enumerate()
+                .map(|(i, (environment, episode_seed))| {
+                    *episode_seed = seed.map(|seed| seed.wrapping_add((first + i) as u64));
+                    (reset(environment, *episode_seed), 0.0, false)
+                })
+                .collect(),
+            Command::Step(actions) => environments
+                .iter_mut()
+                .zip(seeds.iter_mut())
+                .zip(actions)
+                .map(|((environment, episode_seed), action)| {
+                    let (observation, reward, done) = environment.step(action);
+                    if done {
+                        *episode_seed = episode_seed.map(|seed| seed.wrapping_add(count as u64));
+                        (reset(environment, *episode_seed), reward, done)
+                    } else {
+                        (observation, reward, done)
+                    }
+                })
+                .collect(),
+        };
+
+        if results.send(steps).is_err() {
+            return;
+        }
+    }
+}
+
+fn reset(environment: &mut Environment, seed: Option<u64>) -> Observation {
+    match seed {
+        Some(seed) => environment.reset_with_seed(seed),
+        None => environment.reset(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lives_in_ve() -> RewardSpec {
+        RewardSpec::from_toml("lives = { register = 14 }").unwrap()
+    }
+
+    #[test]
+    fn lives_unset_at_start_dont_end_the_episode() {
+        // Waits on a title screen for a key
+        let rom = [0xF0, 0x0A, 0x12, 0x00];
+        let mut environment = Environment::new(&rom, lives_in_ve(), 1);
+        for _ in 0..10 {
+            let (_, reward, done) = environment.step(None);
+            assert_eq!(reward, 0.0);
+            assert!(!done);
+        }
+    }
+
+    #[test]
+    fn losing_the_last_life_ends_the_episode() {
+        // LD VE, 2 then take a life away each step until none are left
+        let rom = [0x6E, 0x02, 0xF0, 0x0A, 0x7E, 0xFF, 0x12, 0x02];
+        let mut environment = Environment::new(&rom, lives_in_ve(), 1);
+        assert!(!environment.step(None).2);
+
+        let press = Some(Key(0));
+        assert!(!environment.step(press).2);
+        let (_, reward, done) = environment.step(None);
+        assert_eq!((reward, done), (-1.0, false));
+        environment.step(press);
+        let (_, reward, done) = environment.step(None);
+        assert_eq!((reward, done), (-1.0, true));
+    }
+}
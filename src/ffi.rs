@@ -0,0 +1,179 @@
+//! A C API for embedding the emulator in other applications. Build it with `cargo build --release
+//! --no-default-features --features ffi`, then link against the resulting library and include
+//! `include/chemu.h`. Set `CHEMU_REGENERATE_HEADER=1` while building to update the header after
+//! changing this file.
+//!
+//! Machines are created from a ROM with `chemu_machine_new` and must be freed with
+//! `chemu_machine_free`. A machine may be used from any thread, but only one at a time.
+
+use crate::display::{HEIGHT, WIDTH};
+use crate::keyboard::Key;
+use crate::machine::{Machine, INSTRUCTIONS_PER_FRAME};
+
+/// Incremented whenever a change to the API would break existing callers.
+pub const CHEMU_API_VERSION: u32 = 1;
+
+/// Width of the display in pixels.
+pub const CHEMU_DISPLAY_WIDTH: usize = 64;
+/// Height of the display in pixels.
+pub const CHEMU_DISPLAY_HEIGHT: usize = 32;
+/// Size of the buffer `chemu_machine_copy_framebuffer` needs.
+pub const CHEMU_FRAMEBUFFER_SIZE: usize = 2048;
+
+// The header is generated from this file alone, so the sizes above have to be spelled out
+const _: () = assert!(CHEMU_DISPLAY_WIDTH == WIDTH && CHEMU_DISPLAY_HEIGHT == HEIGHT);
+const _: () = assert!(CHEMU_FRAMEBUFFER_SIZE == WIDTH * HEIGHT);
+
+/// A CHIP-8 machine running a ROM.
+pub struct ChemuMachine {
+    machine: Machine,
+}
+
+/// Returns `CHEMU_API_VERSION` as it was when the library was built.
+#[no_mangle]
+pub extern "C" fn chemu_api_version() -> u32 {
+    CHEMU_API_VERSION
+}
+
+/// Creates a machine with the ROM loaded into memory. The ROM is copied, so the buffer can be
+/// freed straight away. Returns null if `rom` is null.
+///
+/// # Safety
+/// `rom` must point to at least `length` readable bytes.
+#[no_mangle]
+pub unsafe extern "C" fn chemu_machine_new(rom: *const u8, length: usize) -> *mut ChemuMachine {
+    if rom.is_null() {
+        return std::ptr::null_mut();
+    }
+
+    let rom = std::slice::from_raw_parts(rom, length);
+    Box::into_raw(Box::new(ChemuMachine {
+        machine: Machine::from_rom(rom),
+    }))
+}
+
+/// Frees a machine. Passing null does nothing.
+///
+/// # Safety
+/// `machine` must be null or a pointer returned by `chemu_machine_new` that hasn't been freed.
+#[no_mangle]
+pub unsafe extern "C" fn chemu_machine_free(machine: *mut ChemuMachine) {
+    if !machine.is_null() {
+        drop(Box::from_raw(machine));
+    }
+}
+
+/// Starts the random number generator from a seed, so that runs given the same input behave
+/// identically.
+///
+/// # Safety
+/// `machine` must be a valid pointer returned by `chemu_machine_new`.
+#[no_mangle]
+pub unsafe extern "C" fn chemu_machine_seed_random(machine: *mut ChemuMachine, seed: u64) {
+    (*machine).machine.seed_random(seed);
+}
+
+/// Runs 60 Hz frames, ticking the timers after each. Returns false if the program has hit an
+/// instruction the emulator can't run, in which case the machine stops for good.
+///
+/// # Safety
+/// `machine` must be a valid pointer returned by `chemu_machine_new`.
+#[no_mangle]
+pub unsafe extern "C" fn chemu_machine_run_frames(machine: *mut ChemuMachine, frames: u32) -> bool {
+    let machine = &mut (*machine).machine;
+    for _ in 0..frames {
+        if machine.fault().is_some() {
+            break;
+        }
+        machine.run_frame(INSTRUCTIONS_PER_FRAME);
+    }
+    machine.fault().is_none()
+}
+
+/// Executes instructions without ticking the timers. Returns false if the program has hit an
+/// instruction the emulator can't run, in which case the machine stops for good.
+///
+/// # Safety
+/// `machine` must be a valid pointer returned by `chemu_machine_new`.
+#[no_mangle]
+pub unsafe extern "C" fn chemu_machine_step(machine: *mut ChemuMachine, instructions: u32) -> bool {
+    let machine = &mut (*machine).machine;
+    for _ in 0..instructions {
+        if machine.fault().is_some() {
+            break;
+        }
+        machine.exec_next();
+    }
+    machine.fault().is_none()
+}
+
+/// Presses or releases a hex key from 0 to 15. Other keys are ignored.
+///
+/// # Safety
+/// `machine` must be a valid pointer returned by `chemu_machine_new`.
+#[no_mangle]
+pub unsafe extern "C" fn chemu_machine_set_key(machine: *mut ChemuMachine, key: u8, pressed: bool) {
+    if key > 0xF {
+        return;
+    }
+
+    let keyboard = (*machine).machine.keyboard_mut();
+    if pressed {
+        keyboard.press(Key(key));
+    } else {
+        keyboard.release(Key(key));
+    }
+}
+
+/// Copies the display into `buffer`, one byte per pixel in rows from the top left, with 1 for lit
+/// pixels and 0 for the rest. Returns false without copying anything if `length` is less than
+/// `CHEMU_FRAMEBUFFER_SIZE`.
+///
+/// # Safety
+/// `machine` must be a valid pointer returned by `chemu_machine_new`, and `buffer` must point to at
+/// least `length` writable bytes.
+#[no_mangle]
+pub unsafe extern "C" fn chemu_machine_copy_framebuffer(
+    machine: *const ChemuMachine,
+    buffer: *mut u8,
+    length: usize,
+) -> bool {
+    if buffer.is_null() || length < CHEMU_FRAMEBUFFER_SIZE {
+        return false;
+    }
+
+    let buffer = std::slice::from_raw_parts_mut(buffer, CHEMU_FRAMEBUFFER_SIZE);
+    let pixels = (*machine).machine.display().pixels().iter().flatten();
+    for (byte, &pixel) in buffer.iter_mut().zip(pixels) {
+        *byte = pixel as u8;
+    }
+    true
+}
+
+/// Whether the display has changed since the last call to `chemu_machine_mark_presented`, so
+/// callers can skip redrawing it.
+///
+/// # Safety
+/// `machine` must be a valid pointer returned by `chemu_machine_new`.
+#[no_mangle]
+pub unsafe extern "C" fn chemu_machine_display_changed(machine: *const ChemuMachine) -> bool {
+    (*machine).machine.display().update_pending()
+}
+
+/// Records that the current display has been drawn.
+///
+/// # Safety
+/// `machine` must be a valid pointer returned by `chemu_machine_new`.
+#[no_mangle]
+pub unsafe extern "C" fn chemu_machine_mark_presented(machine: *mut ChemuMachine) {
+    (*machine).machine.display_mut().mark_presented();
+}
+
+/// The sound timer's value. The buzzer sounds for as long as it's above zero.
+///
+/// # Safety
+/// `machine` must be a valid pointer returned by `chemu_machine_new`.
+#[no_mangle]
+pub unsafe extern "C" fn chemu_machine_sound_timer(machine: *const ChemuMachine) -> u8 {
+    (*machine).machine.sound_timer()
+}
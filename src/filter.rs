@@ -0,0 +1,354 @@
+//! Pixel art upscaling filters, applied to the display's colours before they're presented. Filters
+//! only use integer arithmetic, so the same input always gives exactly the same output on every
+//! platform, and their output can be compared against saved images.
+
+use crate::display::{HEIGHT, WIDTH};
+use crate::palette::{Palette, Rgb};
+use std::error::Error;
+use std::fmt;
+use std::fmt::Formatter;
+
+/// An image made of RGB pixels, stored in rows from the top left.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Image {
+    width: usize,
+    height: usize,
+    pixels: Vec<Rgb>,
+}
+
+impl Image {
+    pub fn new(width: usize, height: usize, colour: Rgb) -> Image {
+        Image {
+            width,
+            height,
+            pixels: vec![colour; width * height],
+        }
+    }
+
+    /// Colours the display with a palette, given the brightness of each pixel from 0 for off to
+    /// 255 for on.
+    pub fn from_intensities(intensities: &[[u8; WIDTH]; HEIGHT], palette: &Palette) -> Image {
+        Image {
+            width: WIDTH,
+            height: HEIGHT,
+            pixels: intensities
+                .iter()
+                .flatten()
+                .map(|&intensity| palette.blend(intensity))
+                .collect(),
+        }
+    }
+
+    /// Colours the display with a palette's off and on colours.
+    pub fn from_pixels(pixels: &[[bool; WIDTH]; HEIGHT], palette: &Palette) -> Image {
+        Image {
+            width: WIDTH,
+            height: HEIGHT,
+            pixels: pixels
+                .iter()
+                .flatten()
+                .map(|&pixel| palette.colour(pixel))
+                .collect(),
+        }
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    pub fn pixels(&self) -> &[Rgb] {
+        &self.pixels
+    }
+
+    pub fn get(&self, x: usize, y: usize) -> Rgb {
+        self.pixels[y * self.width + x]
+    }
+
+    pub fn set(&mut self, x: usize, y: usize, colour: Rgb) {
+        self.pixels[y * self.width + x] = colour;
+    }
+
+    /// Enlarges the image by a whole number, drawing each pixel as a square block.
+    pub fn scaled(&self, scale: usize) -> Image {
+        let mut output = Image::new(self.width * scale, self.height * scale, Rgb(0, 0, 0));
+        for y in 0..output.height {
+            for x in 0..output.width {
+                output.set(x, y, self.get(x / scale, y / scale));
+            }
+        }
+        output
+    }
+
+    /// The pixel at an offset from the given one, repeating the edge pixels for positions outside
+    /// the image.
+    fn neighbour(&self, x: usize, y: usize, dx: isize, dy: isize) -> Rgb {
+        let x = (x as isize + dx).max(0).min(self.width as isize - 1) as usize;
+        let y = (y as isize + dy).max(0).min(self.height as isize - 1) as usize;
+        self.get(x, y)
+    }
+}
+
+/// A single upscaling filter.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Filter {
+    /// Doubles the size, rounding off diagonal edges without adding any colours (also known as
+    /// EPX).
+    Scale2x,
+    /// Triples the size, rounding off diagonal edges without adding any colours.
+    Scale3x,
+    /// Doubles the size, blending colours along edges in the style of HQx and xBR for smoother
+    /// curves.
+    Smooth,
+    /// Triples the size and darkens every third row and tints the columns red, green and blue in
+    /// turn, imitating a CRT's scanlines and shadow mask.
+    Crt,
+}
+
+/// Names of the filters, as accepted by `Filter::parse`.
+pub const FILTERS: [(&str, Filter); 4] = [
+    ("scale2x", Filter::Scale2x),
+    ("scale3x", Filter::Scale3x),
+    ("smooth", Filter::Smooth),
+    ("crt", Filter::Crt),
+];
+
+impl Filter {
+    pub fn parse(name: &str) -> Result<Filter, FilterError> {
+        FILTERS
+            .iter()
+            .find(|(filter, _)| filter.eq_ignore_ascii_case(name))
+            .map(|&(_, filter)| filter)
+            .ok_or(FilterError)
+    }
+
+    /// How many times larger the output is than the input, in each direction.
+    pub fn scale(self) -> usize {
+        match self {
+            Filter::Scale2x | Filter::Smooth => 2,
+            Filter::Scale3x | Filter::Crt => 3,
+        }
+    }
+
+    pub fn apply(self, image: &Image) -> Image {
+        let scale = self.scale();
+        let mut output = Image::new(image.width * scale, image.height * scale, Rgb(0, 0, 0));
+        for y in 0..image.height {
+            for x in 0..image.width {
+                let block = match self {
+                    Filter::Scale2x => scale2x(image, x, y),
+                    Filter::Scale3x => scale3x(image, x, y),
+                    Filter::Smooth => smooth(image, x, y),
+                    Filter::Crt => crt(image, x, y),
+                };
+                for (i, &colour) in block[..scale * scale].iter().enumerate() {
+                    output.set(x * scale + i % scale, y * scale + i / scale, colour);
+                }
+            }
+        }
+        output
+    }
+}
+
+/// Filters applied one after another, such as `scale2x,crt`.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct FilterChain {
+    filters: Vec<Filter>,
+}
+
+impl FilterChain {
+    pub fn new(filters: Vec<Filter>) -> FilterChain {
+        FilterChain { filters }
+    }
+
+    /// Parses a comma-separated list of filter names. `none` or an empty string gives an empty
+    /// chain.
+    pub fn parse(spec: &str) -> Result<FilterChain, FilterError> {
+        if spec.is_empty() || spec.eq_ignore_ascii_case("none") {
+            return Ok(FilterChain::default());
+        }
+
+        spec.split(',')
+            .map(|name| Filter::parse(name.trim()))
+            .collect::<Result<_, _>>()
+            .map(FilterChain::new)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.filters.is_empty()
+    }
+
+    pub fn filters(&self) -> &[Filter] {
+        &self.filters
+    }
+
+    /// How many times larger the output is than the input, in each direction.
+    pub fn scale(&self) -> usize {
+        self.filters.iter().map(|filter| filter.scale()).product()
+    }
+
+    pub fn apply(&self, image: Image) -> Image {
+        self.filters
+            .iter()
+            .fold(image, |image, filter| filter.apply(&image))
+    }
+}
+
+/// Up to a 3x3 block of output pixels, in rows.
+type Block = [Rgb; 9];
+
+// The scaling filters name the input pixels around the centre E like this:
+//
+//   A B C
+//   D E F
+//   G H I
+
+fn scale2x(image: &Image, x: usize, y: usize) -> Block {
+    let [_, b, _, d, e, f, _, h, _] = neighbourhood(image, x, y);
+    let mut block = [e; 9];
+    if b != h && d != f {
+        block[0] = if d == b { d } else { e };
+        block[1] = if b == f { f } else { e };
+        block[2] = if d == h { d } else { e };
+        block[3] = if h == f { f } else { e };
+    }
+    block
+}
+
+fn scale3x(image: &Image, x: usize, y: usize) -> Block {
+    let [a, b, c, d, e, f, g, h, i] = neighbourhood(image, x, y);
+    let mut block = [e; 9];
+    if b != h && d != f {
+        block[0] = if d == b { d } else { e };
+        block[1] = if (d == b && e != c) || (b == f && e != a) {
+            b
+        } else {
+            e
+        };
+        block[2] = if b == f { f } else { e };
+        block[3] = if (d == b && e != g) || (d == h && e != a) {
+            d
+        } else {
+            e
+        };
+        block[5] = if (b == f && e != i) || (h == f && e != c) {
+            f
+        } else {
+            e
+        };
+        block[6] = if d == h { d } else { e };
+        block[7] = if (d == h && e != i) || (h == f && e != g) {
+            h
+        } else {
+            e
+        };
+        block[8] = if h == f { f } else { e };
+    }
+    block
+}
+
+fn smooth(image: &Image, x: usize, y: usize) -> Block {
+    let [a, b, c, d, e, f, g, h, i] = neighbourhood(image, x, y);
+    let mut block = [e; 9];
+    if similar(b, h) || similar(d, f) {
+        return block;
+    }
+
+    // Each corner is on an edge when the two pixels beside it match each other but not the
+    // centre. Rather than taking the neighbours' colour outright like Scale2x, the corner is
+    // blended towards it, more strongly when the edge continues through the diagonal pixel.
+    let corners = [(d, b, a), (b, f, c), (d, h, g), (h, f, i)];
+    for (corner, &(side, other, diagonal)) in block.iter_mut().zip(corners.iter()) {
+        if similar(side, other) && !similar(side, e) {
+            let weight = if similar(diagonal, e) { 128 } else { 192 };
+            *corner = mix(e, mix(side, other, 128), weight);
+        }
+    }
+    block
+}
+
+fn crt(image: &Image, x: usize, y: usize) -> Block {
+    let Rgb(r, g, b) = image.get(x, y);
+    let mut block = [Rgb(0, 0, 0); 9];
+    for (i, pixel) in block.iter_mut().enumerate() {
+        let (column, row) = (i % 3, i / 3);
+        // Each column lets its own primary colour through fully and dims the other two
+        let mask = |channel: u8, index: usize| {
+            if column == index {
+                channel
+            } else {
+                scale_channel(channel, 160)
+            }
+        };
+        let mut colour = Rgb(mask(r, 0), mask(g, 1), mask(b, 2));
+        // The gap between scanlines
+        if row == 2 {
+            colour = Rgb(
+                scale_channel(colour.0, 96),
+                scale_channel(colour.1, 96),
+                scale_channel(colour.2, 96),
+            );
+        }
+        *pixel = colour;
+    }
+    block
+}
+
+fn neighbourhood(image: &Image, x: usize, y: usize) -> [Rgb; 9] {
+    let mut pixels = [Rgb(0, 0, 0); 9];
+    for (i, pixel) in pixels.iter_mut().enumerate() {
+        *pixel = image.neighbour(x, y, i as isize % 3 - 1, i as isize / 3 - 1);
+    }
+    pixels
+}
+
+/// Whether two colours look alike, comparing them in YUV with the thresholds HQx uses, since the
+/// eye is more sensitive to differences in brightness than in hue.
+fn similar(first: Rgb, second: Rgb) -> bool {
+    let (y1, u1, v1) = yuv(first);
+    let (y2, u2, v2) = yuv(second);
+    (y1 - y2).abs() <= 48 && (u1 - u2).abs() <= 7 && (v1 - v2).abs() <= 6
+}
+
+fn yuv(Rgb(r, g, b): Rgb) -> (i32, i32, i32) {
+    let (r, g, b) = (r as i32, g as i32, b as i32);
+    let y = (r + g + b) >> 2;
+    let u = 128 + ((r - b) >> 2);
+    let v = 128 + ((2 * g - r - b) >> 3);
+    (y, u, v)
+}
+
+/// Mixes two colours, with a weight from 0 for all of the first to 256 for all of the second.
+fn mix(first: Rgb, second: Rgb, weight: u32) -> Rgb {
+    let channel = |a: u8, b: u8| ((a as u32 * (256 - weight) + b as u32 * weight + 128) >> 8) as u8;
+    Rgb(
+        channel(first.0, second.0),
+        channel(first.1, second.1),
+        channel(first.2, second.2),
+    )
+}
+
+/// Scales a colour channel by a factor out of 256.
+fn scale_channel(channel: u8, factor: u32) -> u8 {
+    ((channel as u32 * factor) >> 8) as u8
+}
+
+/// Error that occurs when a filter name isn't recognised.
+#[derive(Debug)]
+pub struct FilterError;
+
+impl fmt::Display for FilterError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "unknown filter, expected none or a list of")?;
+        for (i, (name, _)) in FILTERS.iter().enumerate() {
+            let separator = if i == 0 { " " } else { ", " };
+            write!(f, "{}{}", separator, name)?;
+        }
+        Ok(())
+    }
+}
+
+impl Error for FilterError {}
@@ -0,0 +1,113 @@
+//! Encoders for terminal graphics protocols, which let terminals show the display as a bitmap
+//! rather than with text characters.
+
+use crate::display::{HEIGHT, WIDTH};
+use crate::palette::{Palette, Rgb};
+
+/// Largest payload allowed in a single kitty graphics escape sequence.
+const KITTY_CHUNK_SIZE: usize = 4096;
+
+/// Encodes the pixels as a sixel image, with each pixel scaled up to a square `scale` sixels wide.
+pub fn sixel(pixels: &[[bool; WIDTH]; HEIGHT], scale: usize, palette: &Palette) -> String {
+    let width = WIDTH * scale;
+    let height = HEIGHT * scale;
+
+    // Enter sixel mode with square pixels, then give the image size and define colour registers 0
+    // and 1. Sixel colour components are percentages rather than bytes.
+    let mut image = format!("\x1bP0;1;0q\"1;1;{};{}", width, height);
+    for (register, &Rgb(r, g, b)) in [palette.off(), palette.on()].iter().enumerate() {
+        image.push_str(&format!(
+            "#{};2;{};{};{}",
+            register,
+            r as u32 * 100 / 255,
+            g as u32 * 100 / 255,
+            b as u32 * 100 / 255
+        ));
+    }
+
+    // Each sixel covers a column of six pixels, so the image is drawn in bands six pixels tall. Each
+    // band is drawn once per colour, returning to the start of the band in between.
+    for band in (0..height).step_by(6) {
+        for (register, colour) in [false, true].iter().enumerate() {
+            image.push_str(&format!("#{}", register));
+
+            let mut run: Option<(u8, usize)> = None;
+            for x in 0..width {
+                let mut bits = 0;
+                for dy in 0..6 {
+                    let y = band + dy;
+                    if y < height && pixels[y / scale][x / scale] == *colour {
+                        bits |= 1 << dy;
+                    }
+                }
+
+                run = match run {
+                    Some((previous, count)) if previous == bits => Some((bits, count + 1)),
+                    Some((previous, count)) => {
+                        push_sixel_run(&mut image, previous, count);
+                        Some((bits, 1))
+                    }
+                    None => Some((bits, 1)),
+                };
+            }
+            if let Some((bits, count)) = run {
+                push_sixel_run(&mut image, bits, count);
+            }
+
+            image.push('$');
+        }
+        image.push('-');
+    }
+
+    image.push_str("\x1b\\");
+    image
+}
+
+fn push_sixel_run(image: &mut String, bits: u8, count: usize) {
+    let sixel = (0x3F + bits) as char;
+    if count > 3 {
+        image.push_str(&format!("!{}{}", count, sixel));
+    } else {
+        for _ in 0..count {
+            image.push(sixel);
+        }
+    }
+}
+
+/// Encodes the pixels as a kitty graphics protocol image, with each pixel scaled up to a square
+/// `scale` pixels wide. The image always uses the same id, so each frame replaces the last one
+/// rather than stacking up in the terminal's memory.
+pub fn kitty(pixels: &[[bool; WIDTH]; HEIGHT], scale: usize, palette: &Palette) -> String {
+    let width = WIDTH * scale;
+    let height = HEIGHT * scale;
+
+    let mut rgb = Vec::with_capacity(width * height * 3);
+    for y in 0..height {
+        for x in 0..width {
+            let Rgb(r, g, b) = palette.colour(pixels[y / scale][x / scale]);
+            rgb.extend_from_slice(&[r, g, b]);
+        }
+    }
+
+    let payload = base64::encode(&rgb);
+    let chunks: Vec<_> = payload.as_bytes().chunks(KITTY_CHUNK_SIZE).collect();
+
+    let mut image = String::new();
+    for (i, chunk) in chunks.iter().enumerate() {
+        let more = if i + 1 < chunks.len() { 1 } else { 0 };
+        // The control data only needs to be sent with the first chunk. q=2 stops the terminal from
+        // replying, since nothing reads the responses.
+        if i == 0 {
+            image.push_str(&format!(
+                "\x1b_Ga=T,f=24,s={},v={},i=1,p=1,q=2,m={};",
+                width, height, more
+            ));
+        } else {
+            image.push_str(&format!("\x1b_Gm={};", more));
+        }
+        image.push_str(std::str::from_utf8(chunk).unwrap());
+        image.push_str("\x1b\\");
+    }
+
+    image
+}
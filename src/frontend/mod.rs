@@ -0,0 +1,79 @@
+use crate::keyboard::Keyboard;
+use crate::machine::Machine;
+
+#[cfg(all(unix, feature = "sdl"))]
+mod graphics;
+#[cfg(feature = "sdl")]
+pub mod sdl;
+#[cfg(all(unix, feature = "sdl"))]
+pub mod tui;
+pub mod vnc;
+pub mod web;
+
+/// Ways of drawing the display in a terminal.
+#[derive(Copy, Clone, Debug)]
+pub enum TerminalGraphics {
+    /// Half-block characters, so each character cell holds two pixels stacked on top of each
+    /// other. Works in any terminal with Unicode support.
+    Blocks,
+    /// A bitmap image sent using the sixel protocol.
+    Sixel,
+    /// A bitmap image sent using the kitty graphics protocol.
+    Kitty,
+}
+
+/// Requests from the user to control the emulator itself, rather than the program it's running.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Command {
+    /// Pauses the machine, or resumes it if it's paused.
+    TogglePause,
+    /// Runs a single frame while paused.
+    FrameAdvance,
+    /// Reloads the program into a fresh machine.
+    Reset,
+    SpeedDown,
+    SpeedUp,
+    /// Starts or stops running the machine faster.
+    FastForward(bool),
+    Quit,
+    /// Switches between a window and fullscreen.
+    ToggleFullscreen,
+    /// Saves a picture of the window's contents.
+    Screenshot,
+    /// Saves a 1-bit picture of the display at its own size.
+    BitmapScreenshot,
+    /// Starts recording, or stops and saves the recording.
+    ToggleRecording,
+    /// Shows or hides the status line.
+    ToggleStatus,
+    /// Shows or hides the keypad overlay.
+    ToggleKeypad,
+}
+
+/// A way of presenting a running machine to the user and reading their input.
+pub trait Frontend {
+    /// Applies all input received since the last call to the keypad.
+    fn process_events(&mut self, keyboard: &mut Keyboard);
+
+    /// Presents the machine's current state. The machine's display reports whether the pixels
+    /// changed since the last call, so frontends can skip redrawing them.
+    fn render(&mut self, machine: &Machine);
+
+    /// Called once for every 60 Hz tick of the machine's timers, which happen in emulated time, so
+    /// they stop while paused and speed up with the machine.
+    fn tick(&mut self, _machine: &Machine) {}
+
+    /// Takes the next command the user gave since the last call, if any. Commands are collected
+    /// while processing events.
+    fn poll_command(&mut self) -> Option<Command> {
+        None
+    }
+
+    /// Tells the user something briefly, such as that the machine was paused. Frontends without
+    /// anywhere to show it can ignore it.
+    fn show_message(&mut self, _text: &str) {}
+
+    /// Called when the machine is reset or a state is loaded, so frontends can forget anything
+    /// they kept from earlier frames, such as the history used to smooth flicker.
+    fn machine_replaced(&mut self) {}
+}
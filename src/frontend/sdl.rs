@@ -0,0 +1,941 @@
+use crate::audio::Beeper;
+use crate::display::{HEIGHT, WIDTH};
+use crate::filter::{FilterChain, Image};
+use crate::frontend::{Command, Frontend};
+use crate::keyboard::{Key, Keyboard};
+use crate::keymap::{AxisDirection, ControllerInput, Hotkey, Keymap, Modifier};
+use crate::machine::Machine;
+use crate::osd;
+use crate::osd::{Osd, Surface};
+use crate::palette::{Palette, Rgb};
+use crate::persistence::{Persistence, PersistenceFilter};
+use crate::recording;
+use crate::recording::Recorder;
+use crate::screenshot;
+use crate::screenshot::Format;
+use sdl2::audio::{AudioCallback, AudioDevice, AudioSpecDesired};
+use sdl2::controller::GameController;
+use sdl2::event::{Event, WindowEvent};
+use sdl2::keyboard::{Mod, Scancode};
+use sdl2::mouse::MouseButton;
+use sdl2::pixels::{Color, PixelFormatEnum};
+use sdl2::rect::Rect;
+use sdl2::render::{BlendMode, Texture, WindowCanvas};
+use sdl2::video::FullscreenType;
+use sdl2::{EventPump, GameControllerSubsystem, Sdl};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// How often frames are fed to the persistence filter and on-screen display.
+const FRAME_DURATION: Duration = Duration::from_nanos(1_000_000_000 / 60);
+
+/// Bytes per pixel in the streaming texture.
+const TEXTURE_PIXEL_SIZE: usize = 3;
+
+/// Sample rate requested for the buzzer. SDL converts it if the device wants another.
+const SAMPLE_RATE: i32 = 44100;
+
+/// The mouse id SDL gives the mouse events it makes up from touches. Touches are handled directly,
+/// so these are ignored.
+const TOUCH_MOUSE_ID: u32 = u32::MAX;
+/// The keypad panel takes up at most this fraction of the window's width.
+const MAX_PANEL_FRACTION: usize = 3;
+
+/// How the display is fitted into the window when their shapes don't match.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Scaling {
+    /// As large as fits while keeping the display's shape, with bars filling the rest.
+    Fit,
+    /// The largest whole multiple of the display's size that fits, so every pixel is the same size.
+    Integer,
+    /// Fills the whole window, distorting the display's shape.
+    Stretch,
+}
+
+impl Scaling {
+    /// Where to draw a display of the given size in a window of the given size, centring it when
+    /// it doesn't fill the window. `None` fills the whole window.
+    fn destination(self, display: (usize, usize), window: (usize, usize)) -> Option<Rect> {
+        let (width, height) = match self {
+            Scaling::Stretch => return None,
+            Scaling::Fit => {
+                // Compare the two aspect ratios without dividing, to see which side limits the size
+                if window.0 * display.1 <= window.1 * display.0 {
+                    (window.0, window.0 * display.1 / display.0)
+                } else {
+                    (window.1 * display.0 / display.1, window.1)
+                }
+            }
+            Scaling::Integer => {
+                // Windows smaller than the display still show it, just shrunk to fit
+                let scale = (window.0 / display.0).min(window.1 / display.1);
+                if scale == 0 {
+                    return Scaling::Fit.destination(display, window);
+                }
+                (display.0 * scale, display.1 * scale)
+            }
+        };
+
+        Some(Rect::new(
+            ((window.0 - width) / 2) as i32,
+            ((window.1 - height) / 2) as i32,
+            width as u32,
+            height as u32,
+        ))
+    }
+}
+
+/// How clicks and touches on the keypad panel press keys.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum KeypadMode {
+    /// Keys are held for as long as the button or finger is.
+    Momentary,
+    /// Each click or touch toggles a key, which stays held until it's clicked again. Useful for
+    /// holding several keys at once with a mouse.
+    Latched,
+}
+
+/// Plays the buzzer on SDL's audio thread while the machine's sound timer is running.
+struct Buzzer {
+    beeper: Beeper,
+    channels: usize,
+    /// Set by the frontend each frame from the machine's sound timer.
+    active: Arc<AtomicBool>,
+}
+
+impl AudioCallback for Buzzer {
+    type Channel = i16;
+
+    fn callback(&mut self, samples: &mut [i16]) {
+        let active = self.active.load(Ordering::Relaxed);
+        self.beeper.fill(active, self.channels, samples);
+    }
+}
+
+/// Something pressing a key on the keypad panel.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+enum Pointer {
+    Mouse,
+    Finger(i64),
+}
+
+/// Shows the display in a resizable SDL window, and reads the hex keypad from SDL keyboard and
+/// game controller events. F11 or Alt+Enter toggles fullscreen, F12 saves a PNG screenshot of the
+/// window's contents to the current directory and Shift+F12 saves a PBM one. F9 starts and stops
+/// recording an animation, which is saved to the current directory when it stops. F1 shows and
+/// hides the on-screen status line, and F2 the on-screen keypad. The keymap's hotkeys and closing
+/// the window are passed on as commands.
+///
+/// A clickable keypad panel can be shown beside the game, which presses keys when clicked or
+/// touched just like the keys they're bound to.
+///
+/// The buzzer sounds while the sound timer runs, unless no audio device could be opened.
+///
+/// The pixels are uploaded to a texture each frame and scaled by the GPU, so drawing costs the same
+/// however many pixels are lit.
+pub struct SdlFrontend {
+    canvas: WindowCanvas,
+    scaling: Scaling,
+    filters: FilterChain,
+    /// Holds one texel per pixel of the filtered display. Created on the first frame, and again
+    /// whenever the size of the display changes.
+    texture: Option<Texture>,
+    texture_size: (usize, usize),
+    /// Set when the window needs redrawing even though the display hasn't changed, such as after
+    /// being resized.
+    redraw: bool,
+    palette: Palette,
+    /// Included in screenshot file names.
+    rom_name: String,
+    /// Taken the next time the display is drawn.
+    pending_screenshot: Option<Format>,
+    /// Set while recording.
+    recorder: Option<Recorder>,
+    recording_format: recording::Format,
+    persistence: PersistenceFilter,
+    osd: Osd,
+    /// Playing as long as an audio device could be opened.
+    audio: Option<AudioDevice<Buzzer>>,
+    sound_active: Arc<AtomicBool>,
+    /// When the persistence filter and on-screen display are next due a frame.
+    next_frame: Instant,
+    event_pump: EventPump,
+    controller_subsystem: GameControllerSubsystem,
+    /// Open controllers by joystick instance id. Controllers stop sending events once dropped.
+    controllers: HashMap<u32, GameController>,
+    commands: VecDeque<Command>,
+    held: HeldInputs,
+    keypad_panel: Option<KeypadMode>,
+    /// The keys lit up on the keypad panel when it was last drawn.
+    panel_keys: [bool; 16],
+}
+
+impl SdlFrontend {
+    pub fn new(
+        sdl_context: &Sdl,
+        width: u32,
+        height: u32,
+        scaling: Scaling,
+        palette: Palette,
+        persistence: Persistence,
+        keymap: Keymap,
+    ) -> SdlFrontend {
+        let video = sdl_context.video().unwrap();
+        let window = video
+            .window("Chemu", width, height)
+            .position_centered()
+            .resizable()
+            .build()
+            .unwrap();
+        let mut canvas = window.into_canvas().build().unwrap();
+        canvas.set_draw_color(Color::RGB(0, 0, 0));
+        canvas.clear();
+        canvas.present();
+        // The on-screen display is drawn over the game with translucent boxes
+        canvas.set_blend_mode(BlendMode::Blend);
+
+        // The emulator is still playable without sound, so a missing audio device isn't an error
+        let sound_active = Arc::new(AtomicBool::new(false));
+        let desired = AudioSpecDesired {
+            freq: Some(SAMPLE_RATE),
+            channels: Some(1),
+            samples: None,
+        };
+        let audio = sdl_context.audio().and_then(|audio| {
+            audio.open_playback(None, &desired, |spec| Buzzer {
+                beeper: Beeper::new(spec.freq as u32),
+                channels: spec.channels as usize,
+                active: Arc::clone(&sound_active),
+            })
+        });
+        let audio = match audio {
+            Ok(device) => {
+                device.resume();
+                Some(device)
+            }
+            Err(e) => {
+                eprintln!("Couldn't open an audio device, so there will be no sound");
+                eprintln!("Cause: {}", e);
+                None
+            }
+        };
+
+        SdlFrontend {
+            canvas,
+            scaling,
+            filters: FilterChain::default(),
+            texture: None,
+            texture_size: (0, 0),
+            redraw: true,
+            palette,
+            rom_name: String::from("chemu"),
+            pending_screenshot: None,
+            recorder: None,
+            recording_format: recording::Format::Gif,
+            persistence: PersistenceFilter::new(persistence),
+            osd: Osd::new(),
+            audio,
+            sound_active,
+            next_frame: Instant::now(),
+            event_pump: sdl_context.event_pump().unwrap(),
+            controller_subsystem: sdl_context.game_controller().unwrap(),
+            controllers: HashMap::new(),
+            commands: VecDeque::new(),
+            held: HeldInputs::new(keymap),
+            keypad_panel: None,
+            panel_keys: [false; 16],
+        }
+    }
+
+    /// Sets the filters the display is upscaled with before it's presented.
+    pub fn set_filters(&mut self, filters: FilterChain) {
+        self.filters = filters;
+        self.redraw = true;
+    }
+
+    /// Sets the text in the window's title bar.
+    pub fn set_title(&mut self, title: &str) {
+        let _ = self.canvas.window_mut().set_title(title);
+    }
+
+    /// Sets the name screenshots are saved under.
+    pub fn set_rom_name(&mut self, rom_name: &str) {
+        self.rom_name = rom_name.to_string();
+    }
+
+    /// Sets the buzzer's volume, from 0 for silence to 1 for full volume, and the frequency of its
+    /// tone in hertz.
+    pub fn set_sound(&mut self, volume: f32, tone: f32) {
+        if let Some(audio) = &mut self.audio {
+            let mut buzzer = audio.lock();
+            buzzer.beeper.set_volume(volume);
+            buzzer.beeper.set_tone(tone);
+        }
+    }
+
+    /// The on-screen display, for showing messages or changing what it shows.
+    pub fn osd_mut(&mut self) -> &mut Osd {
+        &mut self.osd
+    }
+
+    /// Sets the format recordings started with F9 are saved in.
+    pub fn set_recording_format(&mut self, format: recording::Format) {
+        self.recording_format = format;
+    }
+
+    /// Shows a clickable keypad panel to the right of the game, or hides it when `None`. Showing it
+    /// widens the window to make room, unless the window is fullscreen.
+    pub fn set_keypad_panel(&mut self, mode: Option<KeypadMode>) {
+        if self.keypad_panel.is_none() && mode.is_some() && !self.is_fullscreen() {
+            let (width, height) = self.canvas.window().size();
+            let _ = self.canvas.window_mut().set_size(width + height, height);
+        }
+        self.keypad_panel = mode;
+        self.redraw = true;
+    }
+
+    /// Where the keypad panel goes in the window, as the position of its top left corner and the
+    /// size of each of its square keys, in pixels.
+    fn panel_layout(&self) -> Option<(usize, usize, usize)> {
+        self.keypad_panel?;
+        let (width, height) = self.canvas.output_size().unwrap();
+        let (width, height) = (width as usize, height as usize);
+        let cell = (height / 4).min(width / MAX_PANEL_FRACTION / 4);
+        Some((width - 4 * cell, (height - 4 * cell) / 2, cell))
+    }
+
+    /// The size of the part of the window the game is drawn in, which is all of it unless the
+    /// keypad panel is showing.
+    fn game_area(&self) -> (usize, usize) {
+        let (width, height) = self.canvas.output_size().unwrap();
+        match self.panel_layout() {
+            Some((left, _, _)) => (left, height as usize),
+            None => (width as usize, height as usize),
+        }
+    }
+
+    /// Converts a position in window coordinates to pixels, which differ on high DPI displays.
+    fn window_to_output(&self, x: f32, y: f32) -> (usize, usize) {
+        let (window_width, window_height) = self.canvas.window().size();
+        let (width, height) = self.canvas.output_size().unwrap();
+        (
+            (x * width as f32 / window_width.max(1) as f32) as usize,
+            (y * height as f32 / window_height.max(1) as f32) as usize,
+        )
+    }
+
+    /// Handles a click or touch starting at a position in pixels, pressing the key under it if
+    /// it's on the keypad panel.
+    fn pointer_down(&mut self, pointer: Pointer, x: usize, y: usize, keyboard: &mut Keyboard) {
+        let (mode, (left, top, cell)) = match (self.keypad_panel, self.panel_layout()) {
+            (Some(mode), Some(layout)) => (mode, layout),
+            _ => return,
+        };
+        if x < left || y < top {
+            return;
+        }
+        let key = match osd::keypad_key_at(x - left, y - top, (cell, cell)) {
+            Some(key) => key,
+            None => return,
+        };
+
+        match mode {
+            KeypadMode::Momentary => {
+                self.held.pointers_pressed.insert(pointer, key);
+            }
+            KeypadMode::Latched => {
+                let latched = &mut self.held.latched[key.0 as usize];
+                *latched = !*latched;
+            }
+        }
+        self.held.sync_key(key, keyboard);
+    }
+
+    fn pointer_up(&mut self, pointer: Pointer, keyboard: &mut Keyboard) {
+        if let Some(key) = self.held.pointers_pressed.remove(&pointer) {
+            self.held.sync_key(key, keyboard);
+        }
+    }
+
+    /// Starts recording, or stops and saves the recording if one is in progress. Recordings are
+    /// drawn at the display's current size in the window, without filters.
+    pub fn toggle_recording(&mut self) {
+        match self.recorder.take() {
+            Some(recorder) => {
+                let path = Path::new(".").join(screenshot::file_name(
+                    &self.rom_name,
+                    self.recording_format.extension(),
+                ));
+                match recorder.save(&path, self.recording_format) {
+                    Ok(()) => {
+                        println!("Saved recording to {}", path.display());
+                        self.osd.message("Recording saved");
+                    }
+                    Err(e) => {
+                        eprintln!("Couldn't save recording: {}", e);
+                        self.osd.message("Couldn't save recording");
+                    }
+                }
+            }
+            None => {
+                let area = self.game_area();
+                let destination = self.scaling.destination((WIDTH, HEIGHT), area);
+                let width = destination.map_or(area.0, |rect| rect.width() as usize);
+                self.recorder = Some(Recorder::new(self.palette, width / WIDTH));
+                println!("Recording started");
+                self.osd.message("Recording started");
+            }
+        }
+    }
+
+    pub fn is_fullscreen(&self) -> bool {
+        self.canvas.window().fullscreen_state() != FullscreenType::Off
+    }
+
+    /// Switches between a window and fullscreen at the desktop's resolution.
+    pub fn set_fullscreen(&mut self, fullscreen: bool) {
+        let state = if fullscreen {
+            FullscreenType::Desktop
+        } else {
+            FullscreenType::Off
+        };
+        // Not every platform supports fullscreen, in which case the window is left as it is
+        let _ = self.canvas.window_mut().set_fullscreen(state);
+        self.redraw = true;
+    }
+
+    /// Makes sure the texture matches the size of the filtered display, recreating it if the size
+    /// has changed.
+    fn resize_texture(&mut self, width: usize, height: usize) {
+        if self.texture.is_some() && self.texture_size == (width, height) {
+            return;
+        }
+
+        if let Some(texture) = self.texture.take() {
+            // Textures aren't freed on drop
+            unsafe { texture.destroy() };
+        }
+        // Pixels are scaled up with nearest-neighbour sampling so they stay sharp
+        sdl2::hint::set("SDL_RENDER_SCALE_QUALITY", "nearest");
+        self.texture = Some(
+            self.canvas
+                .texture_creator()
+                .create_texture_streaming(PixelFormatEnum::RGB24, width as u32, height as u32)
+                .unwrap(),
+        );
+        self.texture_size = (width, height);
+    }
+
+    /// Carries out commands for the window itself, and queues the rest for the emulator.
+    fn run_command(&mut self, command: Command) {
+        match command {
+            Command::ToggleFullscreen => self.set_fullscreen(!self.is_fullscreen()),
+            Command::Screenshot | Command::BitmapScreenshot => {
+                self.pending_screenshot = if command == Command::BitmapScreenshot {
+                    Some(Format::Pbm)
+                } else {
+                    Some(Format::Png)
+                };
+                self.redraw = true;
+            }
+            Command::ToggleRecording => self.toggle_recording(),
+            Command::ToggleStatus => self.osd.set_status_visible(!self.osd.status_visible()),
+            Command::ToggleKeypad => self.osd.set_keypad_visible(!self.osd.keypad_visible()),
+            command => self.commands.push_back(command),
+        }
+    }
+
+    /// Updates the keypad from an SDL event. Inputs that aren't in the keymap are ignored. Events
+    /// don't have to come from the event pump, so synthetic events can be injected here.
+    pub fn process_event(&mut self, event: Event, keyboard: &mut Keyboard) {
+        match event {
+            Event::Quit { .. } => self.commands.push_back(Command::Quit),
+            Event::KeyDown {
+                scancode: Some(scancode),
+                keymod,
+                repeat,
+                ..
+            } if self
+                .held
+                .keymap
+                .hotkey_for(scancode, modifier(keymod))
+                .is_some() =>
+            {
+                let hotkey = self.held.keymap.hotkey_for(scancode, modifier(keymod));
+                let command = Command::from(hotkey.unwrap());
+                // Frame advance and speed changes repeat while held, but toggles don't
+                let repeatable = matches!(
+                    command,
+                    Command::FrameAdvance | Command::SpeedDown | Command::SpeedUp
+                );
+                if !repeat || repeatable {
+                    self.run_command(command);
+                }
+            }
+            Event::KeyUp {
+                scancode: Some(scancode),
+                ..
+            } if self.held.keymap.hotkey_for(scancode, None) == Some(Hotkey::FastForward) => {
+                self.commands.push_back(Command::FastForward(false))
+            }
+            Event::MouseButtonDown {
+                which,
+                mouse_btn: MouseButton::Left,
+                x,
+                y,
+                ..
+            } if which != TOUCH_MOUSE_ID => {
+                let (x, y) = self.window_to_output(x as f32, y as f32);
+                self.pointer_down(Pointer::Mouse, x, y, keyboard);
+            }
+            Event::MouseButtonUp {
+                which,
+                mouse_btn: MouseButton::Left,
+                ..
+            } if which != TOUCH_MOUSE_ID => self.pointer_up(Pointer::Mouse, keyboard),
+            Event::FingerDown {
+                finger_id, x, y, ..
+            } => {
+                // Touch positions are fractions of the window's size
+                let (window_width, window_height) = self.canvas.window().size();
+                let (x, y) =
+                    self.window_to_output(x * window_width as f32, y * window_height as f32);
+                self.pointer_down(Pointer::Finger(finger_id), x, y, keyboard);
+            }
+            Event::FingerUp { finger_id, .. } => {
+                self.pointer_up(Pointer::Finger(finger_id), keyboard)
+            }
+            Event::Window {
+                win_event: WindowEvent::SizeChanged(..) | WindowEvent::Exposed,
+                ..
+            } => self.redraw = true,
+            Event::ControllerDeviceAdded { which, .. } => {
+                // Added events carry the joystick index, but every later event uses the instance id.
+                if let Ok(controller) = self.controller_subsystem.open(which) {
+                    self.controllers
+                        .insert(controller.instance_id() as u32, controller);
+                }
+            }
+            Event::ControllerDeviceRemoved { which, .. } => {
+                self.controllers.remove(&which);
+                self.held.process_event(&event, keyboard);
+            }
+            event => self.held.process_event(&event, keyboard),
+        }
+    }
+}
+
+/// The keys, controller inputs and keypad panel presses holding hex keys down, and the keymap
+/// that says which hex keys they hold.
+struct HeldInputs {
+    keymap: Keymap,
+    scancodes_pressed: HashSet<Scancode>,
+    /// Controller inputs that are held, along with the instance id of the controller holding them.
+    controller_inputs_pressed: HashSet<(u32, ControllerInput)>,
+    /// Keys held on the keypad panel in momentary mode, by what's holding them.
+    pointers_pressed: HashMap<Pointer, Key>,
+    /// Keys toggled on with the keypad panel in latched mode.
+    latched: [bool; 16],
+}
+
+impl HeldInputs {
+    fn new(keymap: Keymap) -> HeldInputs {
+        HeldInputs {
+            keymap,
+            scancodes_pressed: HashSet::new(),
+            controller_inputs_pressed: HashSet::new(),
+            pointers_pressed: HashMap::new(),
+            latched: [false; 16],
+        }
+    }
+
+    /// Updates the keypad from a keyboard or game controller event. Other events, and inputs that
+    /// aren't in the keymap, are ignored.
+    fn process_event(&mut self, event: &Event, keyboard: &mut Keyboard) {
+        match *event {
+            Event::KeyDown {
+                scancode: Some(scancode),
+                ..
+            } => {
+                if let Some(key) = self.keymap.key_for(scancode) {
+                    self.scancodes_pressed.insert(scancode);
+                    self.sync_key(key, keyboard);
+                }
+            }
+            Event::KeyUp {
+                scancode: Some(scancode),
+                ..
+            } => {
+                if let Some(key) = self.keymap.key_for(scancode) {
+                    self.scancodes_pressed.remove(&scancode);
+                    self.sync_key(key, keyboard);
+                }
+            }
+            Event::ControllerButtonDown { which, button, .. } => {
+                self.set_controller_input(which, ControllerInput::Button(button), true, keyboard);
+            }
+            Event::ControllerButtonUp { which, button, .. } => {
+                self.set_controller_input(which, ControllerInput::Button(button), false, keyboard);
+            }
+            Event::ControllerAxisMotion {
+                which, axis, value, ..
+            } => {
+                let positive = ControllerInput::Axis(axis, AxisDirection::Positive);
+                let negative = ControllerInput::Axis(axis, AxisDirection::Negative);
+                let deadzone = self.keymap.deadzone();
+
+                // Moving straight from one side to the other releases the opposite direction, so
+                // both are always updated.
+                self.set_controller_input(which, positive, value > deadzone, keyboard);
+                self.set_controller_input(which, negative, value < -deadzone, keyboard);
+            }
+            Event::ControllerDeviceRemoved { which, .. } => {
+                let released: Vec<_> = self
+                    .controller_inputs_pressed
+                    .iter()
+                    .filter(|&&(instance_id, _)| instance_id == which)
+                    .map(|&(_, input)| input)
+                    .collect();
+                for input in released {
+                    self.set_controller_input(which, input, false, keyboard);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn set_controller_input(
+        &mut self,
+        which: u32,
+        input: ControllerInput,
+        held: bool,
+        keyboard: &mut Keyboard,
+    ) {
+        if let Some(key) = self.keymap.key_for_controller(input) {
+            if held {
+                self.controller_inputs_pressed.insert((which, input));
+            } else {
+                self.controller_inputs_pressed.remove(&(which, input));
+            }
+            self.sync_key(key, keyboard);
+        }
+    }
+
+    /// Presses or releases the hex key to match its bindings and the keypad panel. Several inputs
+    /// can press the same hex key, so the hex key stays down until all of them are released.
+    fn sync_key(&self, key: Key, keyboard: &mut Keyboard) {
+        let held = self
+            .scancodes_pressed
+            .iter()
+            .any(|&scancode| self.keymap.key_for(scancode) == Some(key))
+            || self
+                .controller_inputs_pressed
+                .iter()
+                .any(|&(_, input)| self.keymap.key_for_controller(input) == Some(key))
+            || self
+                .pointers_pressed
+                .values()
+                .any(|&pressed| pressed == key)
+            || self.latched[key.0 as usize];
+
+        if held {
+            keyboard.press(key);
+        } else {
+            keyboard.release(key);
+        }
+    }
+}
+
+impl Frontend for SdlFrontend {
+    fn process_events(&mut self, keyboard: &mut Keyboard) {
+        while let Some(event) = self.event_pump.poll_event() {
+            self.process_event(event, keyboard);
+        }
+    }
+
+    fn poll_command(&mut self) -> Option<Command> {
+        self.commands.pop_front()
+    }
+
+    fn show_message(&mut self, text: &str) {
+        self.osd.message(text);
+    }
+
+    fn tick(&mut self, machine: &Machine) {
+        // The frame rate shows how fast the machine is running, so it drops when paused and
+        // rises when fast-forwarding
+        self.osd.frame();
+        // Recordings are captured in emulated time, so they play back at the machine's own speed
+        // however it was paused or sped up while recording
+        if let Some(recorder) = &mut self.recorder {
+            recorder.capture(machine.display().pixels());
+        }
+    }
+
+    fn machine_replaced(&mut self) {
+        // Otherwise the old program's pixels would linger while they faded out
+        self.persistence.clear();
+        self.redraw = true;
+    }
+
+    fn render(&mut self, machine: &Machine) {
+        self.sound_active
+            .store(machine.sound_active(), Ordering::Relaxed);
+
+        let display = machine.display();
+        let persistent = self.persistence.mode() != Persistence::Off;
+        let mut changed = !persistent && display.update_pending();
+
+        // Frames are captured in real time, so fading can change the picture while the display
+        // doesn't. After a long stall the missed frames are skipped rather than caught up on.
+        let now = Instant::now();
+        if now.duration_since(self.next_frame) > FRAME_DURATION * 60 {
+            self.next_frame = now;
+        }
+        while self.next_frame <= now {
+            if persistent {
+                changed |= self.persistence.update(display.pixels());
+            }
+            self.next_frame += FRAME_DURATION;
+        }
+        changed |= self
+            .osd
+            .update(machine.instructions_executed(), machine.keyboard());
+        let keys = held_keys(machine.keyboard());
+        if self.keypad_panel.is_some() && keys != self.panel_keys {
+            changed = true;
+        }
+        if !changed && !self.redraw {
+            return;
+        }
+        self.redraw = false;
+
+        let image = if persistent {
+            Image::from_intensities(self.persistence.intensities(), &self.palette)
+        } else {
+            Image::from_pixels(display.pixels(), &self.palette)
+        };
+        let image = self.filters.apply(image);
+
+        self.resize_texture(image.width(), image.height());
+        let texture = self.texture.as_mut().unwrap();
+        texture
+            .with_lock(None, |buffer, pitch| {
+                for (y, row) in image.pixels().chunks(image.width()).enumerate() {
+                    let line = &mut buffer[y * pitch..];
+                    for (x, &Rgb(r, g, b)) in row.iter().enumerate() {
+                        let offset = x * TEXTURE_PIXEL_SIZE;
+                        line[offset..offset + TEXTURE_PIXEL_SIZE].copy_from_slice(&[r, g, b]);
+                    }
+                }
+            })
+            .unwrap();
+
+        self.canvas.set_draw_color(Color::RGB(0, 0, 0));
+        self.canvas.clear();
+        let area = self.game_area();
+        let destination = self
+            .scaling
+            .destination(self.texture_size, area)
+            .unwrap_or_else(|| Rect::new(0, 0, area.0 as u32, area.1 as u32));
+        let texture = self.texture.as_ref().unwrap();
+        self.canvas.copy(texture, None, destination).unwrap();
+        if let Some((left, top, cell)) = self.panel_layout() {
+            osd::draw_keypad(
+                &mut CanvasSurface(&mut self.canvas),
+                left,
+                top,
+                (cell, cell),
+                &keys,
+                255,
+            );
+            self.panel_keys = keys;
+        }
+        self.osd
+            .draw(&mut CanvasSurface(&mut self.canvas), area.0, area.1);
+        self.canvas.present();
+
+        if let Some(format) = self.pending_screenshot.take() {
+            // Screenshots are taken at the largest whole scale that fits in the window
+            let width = destination.width() as usize;
+            let scale = (width / image.width()).max(1);
+            let image = image.scaled(scale);
+            match screenshot::save_image(display, &image, format, Path::new("."), &self.rom_name) {
+                Ok(path) => {
+                    println!("Saved screenshot to {}", path.display());
+                    self.osd.message("Screenshot saved");
+                }
+                Err(e) => {
+                    eprintln!("Couldn't save screenshot: {}", e);
+                    self.osd.message("Couldn't save screenshot");
+                }
+            }
+        }
+    }
+}
+
+/// Draws the on-screen display straight onto the window, after the game has been copied there.
+struct CanvasSurface<'a>(&'a mut WindowCanvas);
+
+impl Surface for CanvasSurface<'_> {
+    fn fill(&mut self, x: usize, y: usize, width: usize, height: usize, colour: Rgb, alpha: u8) {
+        let Rgb(r, g, b) = colour;
+        self.0.set_draw_color(Color::RGBA(r, g, b, alpha));
+        let rect = Rect::new(x as i32, y as i32, width as u32, height as u32);
+        // A rectangle that can't be drawn only loses part of the overlay
+        let _ = self.0.fill_rect(rect);
+    }
+}
+
+/// The modifier held along with a key, for picking its hotkey. Alt wins over Ctrl, and Ctrl over
+/// Shift, when more than one is held.
+fn modifier(keymod: Mod) -> Option<Modifier> {
+    if keymod.intersects(Mod::LALTMOD | Mod::RALTMOD) {
+        Some(Modifier::Alt)
+    } else if keymod.intersects(Mod::LCTRLMOD | Mod::RCTRLMOD) {
+        Some(Modifier::Ctrl)
+    } else if keymod.intersects(Mod::LSHIFTMOD | Mod::RSHIFTMOD) {
+        Some(Modifier::Shift)
+    } else {
+        None
+    }
+}
+
+fn held_keys(keyboard: &Keyboard) -> [bool; 16] {
+    let mut keys = [false; 16];
+    for (key, held) in keys.iter_mut().enumerate() {
+        *held = keyboard.is_pressed(Key(key as u8));
+    }
+    keys
+}
+
+impl Drop for SdlFrontend {
+    fn drop(&mut self) {
+        // Recordings in progress are kept when quitting
+        if self.recorder.is_some() {
+            self.toggle_recording();
+        }
+
+        if let Some(texture) = self.texture.take() {
+            unsafe { texture.destroy() };
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sdl2::controller::{Axis, Button};
+
+    fn button(button: Button, down: bool) -> Event {
+        if down {
+            Event::ControllerButtonDown {
+                timestamp: 0,
+                which: 0,
+                button,
+            }
+        } else {
+            Event::ControllerButtonUp {
+                timestamp: 0,
+                which: 0,
+                button,
+            }
+        }
+    }
+
+    fn axis(which: u32, axis: Axis, value: i16) -> Event {
+        Event::ControllerAxisMotion {
+            timestamp: 0,
+            which,
+            axis,
+            value,
+        }
+    }
+
+    #[test]
+    fn buttons_press_and_release_keys() {
+        let mut held = HeldInputs::new(Keymap::default());
+        let mut keyboard = Keyboard::new();
+
+        held.process_event(&button(Button::DPadUp, true), &mut keyboard);
+        assert!(keyboard.is_pressed(Key(0x2)));
+        held.process_event(&button(Button::DPadUp, false), &mut keyboard);
+        assert!(!keyboard.is_pressed(Key(0x2)));
+
+        // Buttons that aren't bound do nothing
+        held.process_event(&button(Button::Guide, true), &mut keyboard);
+        assert!((0..16).all(|key| !keyboard.is_pressed(Key(key))));
+    }
+
+    #[test]
+    fn axes_press_past_the_deadzone() {
+        let mut held = HeldInputs::new(Keymap::default());
+        let mut keyboard = Keyboard::new();
+        let deadzone = held.keymap.deadzone();
+
+        held.process_event(&axis(0, Axis::LeftX, deadzone), &mut keyboard);
+        assert!(!keyboard.is_pressed(Key(0x6)));
+        held.process_event(&axis(0, Axis::LeftX, i16::MAX), &mut keyboard);
+        assert!(keyboard.is_pressed(Key(0x6)));
+
+        // Swinging straight to the other side releases the first direction
+        held.process_event(&axis(0, Axis::LeftX, i16::MIN), &mut keyboard);
+        assert!(!keyboard.is_pressed(Key(0x6)));
+        assert!(keyboard.is_pressed(Key(0x4)));
+
+        held.process_event(&axis(0, Axis::LeftX, 0), &mut keyboard);
+        assert!(!keyboard.is_pressed(Key(0x4)));
+    }
+
+    #[test]
+    fn keys_stay_held_until_every_input_is_released() {
+        let mut held = HeldInputs::new(Keymap::default());
+        let mut keyboard = Keyboard::new();
+
+        // The D-pad and left stick both press 2
+        held.process_event(&button(Button::DPadUp, true), &mut keyboard);
+        held.process_event(&axis(0, Axis::LeftY, i16::MIN), &mut keyboard);
+        held.process_event(&button(Button::DPadUp, false), &mut keyboard);
+        assert!(keyboard.is_pressed(Key(0x2)));
+        held.process_event(&axis(0, Axis::LeftY, 0), &mut keyboard);
+        assert!(!keyboard.is_pressed(Key(0x2)));
+    }
+
+    #[test]
+    fn removing_a_controller_releases_its_inputs() {
+        let mut held = HeldInputs::new(Keymap::default());
+        let mut keyboard = Keyboard::new();
+
+        held.process_event(&axis(1, Axis::LeftX, i16::MAX), &mut keyboard);
+        held.process_event(&axis(2, Axis::LeftY, i16::MAX), &mut keyboard);
+        held.process_event(
+            &Event::ControllerDeviceRemoved {
+                timestamp: 0,
+                which: 1,
+            },
+            &mut keyboard,
+        );
+        assert!(!keyboard.is_pressed(Key(0x6)));
+        assert!(keyboard.is_pressed(Key(0x8)));
+    }
+
+    #[test]
+    fn rebound_controller_inputs() {
+        let mut keymap = Keymap::default();
+        keymap.rebind_controller(
+            Key(0xA),
+            &[ControllerInput::Axis(
+                Axis::TriggerRight,
+                AxisDirection::Positive,
+            )],
+        );
+        let mut held = HeldInputs::new(keymap);
+        let mut keyboard = Keyboard::new();
+
+        held.process_event(&axis(0, Axis::TriggerRight, i16::MAX), &mut keyboard);
+        assert!(keyboard.is_pressed(Key(0xA)));
+    }
+}
@@ -0,0 +1,376 @@
+use crate::frontend::{graphics, Command, Frontend, TerminalGraphics};
+use crate::keyboard::{Key, Keyboard};
+use crate::keymap::{Hotkey, Keymap};
+use crate::machine::Machine;
+use crate::palette::Palette;
+use sdl2::keyboard::Scancode;
+use std::collections::VecDeque;
+use std::io::{Read, Write};
+use std::mem::MaybeUninit;
+use std::time::{Duration, Instant};
+
+/// Terminals only report key presses, so a key counts as held until this long after the last byte
+/// it sent. Auto-repeat keeps held keys down.
+const KEY_HOLD: Duration = Duration::from_millis(150);
+/// How often the status line's speed reading is recalculated.
+const SPEED_INTERVAL: Duration = Duration::from_millis(500);
+/// How long messages stay on the status line.
+const MESSAGE_DURATION: Duration = Duration::from_secs(2);
+
+const CTRL_C: u8 = 0x03;
+const ESCAPE: u8 = 0x1B;
+
+/// Draws the display in a terminal and reads keys from stdin in raw mode. Ctrl-C quits, and the
+/// keymap's hotkeys work apart from fast-forward, which needs to know when keys are released.
+pub struct TuiFrontend {
+    graphics: TerminalGraphics,
+    /// Size in terminal pixels of each display pixel, when drawing bitmaps.
+    scale: usize,
+    palette: Palette,
+    keymap: Keymap,
+    original_termios: libc::termios,
+    /// When each hex key should be released if its key isn't seen again.
+    held_until: [Option<Instant>; 16],
+    speed_sample: (Instant, u64),
+    instructions_per_second: u64,
+    commands: VecDeque<Command>,
+    /// Shown on the status line until it expires.
+    message: Option<(String, Instant)>,
+}
+
+impl TuiFrontend {
+    pub fn new(
+        graphics: TerminalGraphics,
+        scale: usize,
+        palette: Palette,
+        keymap: Keymap,
+    ) -> std::io::Result<TuiFrontend> {
+        let original_termios = unsafe {
+            let mut termios = MaybeUninit::uninit();
+            if libc::tcgetattr(libc::STDIN_FILENO, termios.as_mut_ptr()) != 0 {
+                return Err(std::io::Error::last_os_error());
+            }
+            termios.assume_init()
+        };
+
+        let mut raw = original_termios;
+        unsafe { libc::cfmakeraw(&mut raw) };
+        // Return from reads straight away, even when no keys have been pressed
+        raw.c_cc[libc::VMIN] = 0;
+        raw.c_cc[libc::VTIME] = 0;
+        if unsafe { libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &raw) } != 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+
+        // Switch to the alternate screen and hide the cursor
+        print!("\x1b[?1049h\x1b[?25l\x1b[2J");
+
+        Ok(TuiFrontend {
+            graphics,
+            scale,
+            palette,
+            keymap,
+            original_termios,
+            held_until: [None; 16],
+            speed_sample: (Instant::now(), 0),
+            instructions_per_second: 0,
+            commands: VecDeque::new(),
+            message: None,
+        })
+    }
+
+    fn restore_terminal(&self) {
+        print!("\x1b[?25h\x1b[?1049l");
+        std::io::stdout().flush().unwrap();
+        unsafe { libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &self.original_termios) };
+    }
+
+    /// Translates the bytes read from the terminal into the names SDL gives the equivalent keys,
+    /// so the same keymap can be used as in the SDL frontend.
+    fn key_names(input: &[u8]) -> Vec<String> {
+        let mut names = Vec::new();
+        let mut i = 0;
+
+        while i < input.len() {
+            let byte = input[i];
+            i += 1;
+            if byte == ESCAPE {
+                // Special keys arrive as CSI (ESC [) or SS3 (ESC O) sequences. Anything else after
+                // ESC is a key of its own, so the ESC was the Escape key.
+                let name = match input.get(i) {
+                    Some(b'[') => {
+                        let (name, length) = csi_key_name(&input[i + 1..]);
+                        i += 1 + length;
+                        name
+                    }
+                    Some(b'O') if i + 1 < input.len() => {
+                        let name = ss3_key_name(input[i + 1]);
+                        i += 2;
+                        name
+                    }
+                    _ => Some("Escape"),
+                };
+                names.extend(name.map(str::to_string));
+            } else if byte.is_ascii_graphic() {
+                names.push((byte as char).to_ascii_uppercase().to_string());
+            }
+        }
+
+        names
+    }
+
+    fn status_line(&mut self, machine: &Machine) -> String {
+        let now = Instant::now();
+        let (sampled_at, sampled_count) = self.speed_sample;
+        let elapsed = now - sampled_at;
+        if elapsed >= SPEED_INTERVAL {
+            let executed = machine
+                .instructions_executed()
+                .saturating_sub(sampled_count);
+            self.instructions_per_second = (executed as f64 / elapsed.as_secs_f64()) as u64;
+            self.speed_sample = (now, machine.instructions_executed());
+        }
+
+        let mut status = format!(
+            "PC {:03X}  I {:03X} ",
+            machine.program_counter(),
+            machine.address_register()
+        );
+        for (i, value) in machine.registers().iter().enumerate() {
+            status.push_str(&format!(" V{:X} {:02X}", i, value));
+        }
+        status.push_str(&format!("  {} ips", self.instructions_per_second));
+        match &self.message {
+            Some((message, expires)) if *expires > now => {
+                status.push_str("  ");
+                status.push_str(message);
+            }
+            _ => self.message = None,
+        }
+
+        status
+    }
+}
+
+/// Reads a CSI sequence from just after its ESC [, returning the name of its key, if it's one
+/// that's known, and how many bytes the sequence takes up.
+fn csi_key_name(sequence: &[u8]) -> (Option<&'static str>, usize) {
+    // The Linux console sends F1 to F5 as ESC [ [ A through ESC [ [ E
+    if let [b'[', last @ b'A'..=b'E', ..] = sequence {
+        let name = ["F1", "F2", "F3", "F4", "F5"][(last - b'A') as usize];
+        return (Some(name), 2);
+    }
+
+    // Parameter and intermediate bytes come before the final byte, which ends the sequence
+    let length = match sequence
+        .iter()
+        .position(|byte| (0x40..=0x7E).contains(byte))
+    {
+        Some(position) => position + 1,
+        None => return (None, sequence.len()),
+    };
+    let parameters = &sequence[..length - 1];
+    // Modifiers follow the first parameter after a semicolon, as in ESC [ 1 ; 5 A for Ctrl+Up
+    let number = parameters
+        .split(|&byte| byte == b';')
+        .next()
+        .and_then(|first| std::str::from_utf8(first).ok())
+        .and_then(|first| first.parse::<u8>().ok());
+
+    let name = match (sequence[length - 1], number) {
+        (b'A', _) => Some("Up"),
+        (b'B', _) => Some("Down"),
+        (b'C', _) => Some("Right"),
+        (b'D', _) => Some("Left"),
+        (b'H', _) => Some("Home"),
+        (b'F', _) => Some("End"),
+        (b'P', _) => Some("F1"),
+        (b'Q', _) => Some("F2"),
+        (b'R', _) => Some("F3"),
+        (b'S', _) => Some("F4"),
+        (b'~', Some(number)) => match number {
+            1 | 7 => Some("Home"),
+            2 => Some("Insert"),
+            3 => Some("Delete"),
+            4 | 8 => Some("End"),
+            5 => Some("PageUp"),
+            6 => Some("PageDown"),
+            11 => Some("F1"),
+            12 => Some("F2"),
+            13 => Some("F3"),
+            14 => Some("F4"),
+            15 => Some("F5"),
+            17 => Some("F6"),
+            18 => Some("F7"),
+            19 => Some("F8"),
+            20 => Some("F9"),
+            21 => Some("F10"),
+            23 => Some("F11"),
+            24 => Some("F12"),
+            _ => None,
+        },
+        _ => None,
+    };
+    (name, length)
+}
+
+/// The name of the key an SS3 sequence (ESC O followed by one byte) stands for.
+fn ss3_key_name(last: u8) -> Option<&'static str> {
+    match last {
+        b'A' => Some("Up"),
+        b'B' => Some("Down"),
+        b'C' => Some("Right"),
+        b'D' => Some("Left"),
+        b'H' => Some("Home"),
+        b'F' => Some("End"),
+        b'P' => Some("F1"),
+        b'Q' => Some("F2"),
+        b'R' => Some("F3"),
+        b'S' => Some("F4"),
+        b'M' => Some("Keypad Enter"),
+        _ => None,
+    }
+}
+
+impl Frontend for TuiFrontend {
+    fn process_events(&mut self, keyboard: &mut Keyboard) {
+        let mut input = [0; 64];
+        let read = std::io::stdin().read(&mut input).unwrap_or(0);
+
+        if input[..read].contains(&CTRL_C) {
+            self.commands.push_back(Command::Quit);
+        }
+
+        let now = Instant::now();
+        for name in TuiFrontend::key_names(&input[..read]) {
+            let scancode = match Scancode::from_name(&name) {
+                Some(scancode) => scancode,
+                None => continue,
+            };
+            match self.keymap.hotkey_for(scancode, None) {
+                Some(Hotkey::FastForward) => continue,
+                Some(hotkey) => {
+                    self.commands.push_back(Command::from(hotkey));
+                    continue;
+                }
+                None => {}
+            }
+
+            if let Some(key) = self.keymap.key_for(scancode) {
+                keyboard.press(key);
+                self.held_until[key.0 as usize] = Some(now + KEY_HOLD);
+            }
+        }
+
+        for (key, held_until) in self.held_until.iter_mut().enumerate() {
+            if let Some(deadline) = *held_until {
+                if now >= deadline {
+                    keyboard.release(Key(key as u8));
+                    *held_until = None;
+                }
+            }
+        }
+    }
+
+    fn poll_command(&mut self) -> Option<Command> {
+        self.commands.pop_front()
+    }
+
+    fn show_message(&mut self, text: &str) {
+        self.message = Some((text.to_string(), Instant::now() + MESSAGE_DURATION));
+    }
+
+    fn render(&mut self, machine: &Machine) {
+        let mut frame = String::new();
+
+        let display = machine.display();
+        if display.update_pending() {
+            frame.push_str("\x1b[H");
+            match self.graphics {
+                TerminalGraphics::Blocks => {
+                    for rows in display.pixels().chunks(2) {
+                        for (&top, &bottom) in rows[0].iter().zip(rows[1].iter()) {
+                            let cell = match (top, bottom) {
+                                (true, true) => '█',
+                                (true, false) => '▀',
+                                (false, true) => '▄',
+                                (false, false) => ' ',
+                            };
+                            frame.push(cell);
+                        }
+                        // Raw mode turns off output processing, so newlines need an explicit
+                        // carriage return
+                        frame.push_str("\r\n");
+                    }
+                }
+                TerminalGraphics::Sixel => {
+                    frame.push_str(&graphics::sixel(
+                        display.pixels(),
+                        self.scale,
+                        &self.palette,
+                    ));
+                    frame.push_str("\r\n");
+                }
+                TerminalGraphics::Kitty => {
+                    frame.push_str(&graphics::kitty(
+                        display.pixels(),
+                        self.scale,
+                        &self.palette,
+                    ));
+                    frame.push_str("\r\n");
+                }
+            }
+            // Remember where the status line goes, since the height of a bitmap in rows depends on
+            // the terminal's font size
+            frame.push_str("\x1b7");
+        } else {
+            frame.push_str("\x1b8");
+        }
+
+        frame.push_str("\x1b[2K");
+        frame.push_str(&self.status_line(machine));
+
+        let mut stdout = std::io::stdout();
+        stdout.write_all(frame.as_bytes()).unwrap();
+        stdout.flush().unwrap();
+    }
+}
+
+impl Drop for TuiFrontend {
+    fn drop(&mut self) {
+        self.restore_terminal();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn names(input: &[u8]) -> Vec<String> {
+        TuiFrontend::key_names(input)
+    }
+
+    #[test]
+    fn lone_escape() {
+        assert_eq!(names(b"\x1b"), ["Escape"]);
+        assert_eq!(names(b"\x1b1"), ["Escape", "1"]);
+    }
+
+    #[test]
+    fn arrows_and_navigation() {
+        assert_eq!(names(b"\x1b[A\x1bOB\x1b[1;5C"), ["Up", "Down", "Right"]);
+        assert_eq!(names(b"\x1b[H\x1b[4~\x1b[5~"), ["Home", "End", "PageUp"]);
+    }
+
+    #[test]
+    fn function_keys() {
+        assert_eq!(names(b"\x1bOP\x1b[15~\x1b[24~"), ["F1", "F5", "F12"]);
+        assert_eq!(names(b"\x1b[[B"), ["F2"]);
+    }
+
+    #[test]
+    fn sequences_dont_leak_keys() {
+        assert_eq!(names(b"\x1b[15~a"), ["F5", "A"]);
+        assert_eq!(names(b"\x1b[99~\x1b[1"), Vec::<String>::new());
+    }
+}
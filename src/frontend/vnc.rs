@@ -0,0 +1,608 @@
+//! A VNC server, so a running machine can be watched and played from any VNC viewer. The server
+//! speaks version 3.8 of the RFB protocol (falling back to 3.7 and 3.3) without authentication,
+//! and sends the display using the raw encoding.
+
+use crate::display::{HEIGHT, WIDTH};
+use crate::frontend::Frontend;
+use crate::keyboard::{Key, Keyboard};
+use crate::machine::Machine;
+use crate::palette::{Palette, Rgb};
+use std::collections::HashMap;
+use std::convert::TryInto;
+use std::io::{Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::sync::atomic::{AtomicBool, AtomicU8, Ordering};
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+use std::time::Duration;
+
+const PROTOCOL_VERSION: &[u8; 12] = b"RFB 003.008\n";
+const SECURITY_NONE: u8 = 1;
+const ENCODING_RAW: i32 = 0;
+
+const SET_PIXEL_FORMAT: u8 = 0;
+const SET_ENCODINGS: u8 = 2;
+const FRAMEBUFFER_UPDATE_REQUEST: u8 = 3;
+const KEY_EVENT: u8 = 4;
+const POINTER_EVENT: u8 = 5;
+const CLIENT_CUT_TEXT: u8 = 6;
+
+/// States of a viewer's outstanding framebuffer update request.
+const NO_REQUEST: u8 = 0;
+const INCREMENTAL_REQUEST: u8 = 1;
+const FULL_REQUEST: u8 = 2;
+
+/// How long a viewer's writer waits for the display to change before checking whether the viewer
+/// has disconnected.
+const UPDATE_POLL: Duration = Duration::from_millis(100);
+
+/// Serves the display to VNC viewers. Viewers that connect to the control address can press keys,
+/// while viewers on the view-only address can only watch.
+pub struct VncFrontend {
+    shared: Arc<Shared>,
+    key_events: Receiver<(Key, bool)>,
+    local_addresses: (SocketAddr, SocketAddr),
+}
+
+/// State shared between the emulator and the threads serving each viewer.
+struct Shared {
+    frame: Mutex<Frame>,
+    frame_changed: Condvar,
+    palette: Palette,
+    scale: usize,
+}
+
+#[derive(Clone)]
+struct Frame {
+    pixels: [[bool; WIDTH]; HEIGHT],
+    /// Incremented every time the pixels change, so viewers can tell whether they're up to date.
+    version: u64,
+}
+
+impl VncFrontend {
+    /// Starts listening for viewers. Each display pixel is drawn as a square `scale` pixels wide.
+    /// Keys are bound by the names SDL gives them, such as `W`, `Space` or `Keypad 8`, so the same
+    /// keymap can be used as in the SDL frontend without needing SDL to read it.
+    pub fn new(
+        control_address: SocketAddr,
+        view_only_address: SocketAddr,
+        scale: usize,
+        palette: Palette,
+        bindings: HashMap<String, Key>,
+    ) -> std::io::Result<VncFrontend> {
+        let shared = Arc::new(Shared {
+            frame: Mutex::new(Frame {
+                pixels: [[false; WIDTH]; HEIGHT],
+                version: 0,
+            }),
+            frame_changed: Condvar::new(),
+            palette,
+            scale,
+        });
+        let (key_sender, key_events) = channel();
+
+        let control_listener = TcpListener::bind(control_address)?;
+        let view_only_listener = TcpListener::bind(view_only_address)?;
+        let local_addresses = (
+            control_listener.local_addr()?,
+            view_only_listener.local_addr()?,
+        );
+
+        let control = Control {
+            key_events: key_sender,
+            bindings: Arc::new(bindings),
+            presses: Arc::new(Mutex::new([0; 16])),
+        };
+        let control_shared = shared.clone();
+        thread::spawn(move || accept_viewers(control_listener, control_shared, Some(control)));
+        let view_only_shared = shared.clone();
+        thread::spawn(move || accept_viewers(view_only_listener, view_only_shared, None));
+
+        Ok(VncFrontend {
+            shared,
+            key_events,
+            local_addresses,
+        })
+    }
+
+    /// The control and view-only addresses the server is listening on, which is useful when it
+    /// was asked for port 0.
+    pub fn local_addresses(&self) -> (SocketAddr, SocketAddr) {
+        self.local_addresses
+    }
+}
+
+impl Frontend for VncFrontend {
+    fn process_events(&mut self, keyboard: &mut Keyboard) {
+        for (key, down) in self.key_events.try_iter() {
+            if down {
+                keyboard.press(key);
+            } else {
+                keyboard.release(key);
+            }
+        }
+    }
+
+    fn render(&mut self, machine: &Machine) {
+        let display = machine.display();
+        if !display.update_pending() {
+            return;
+        }
+
+        let mut frame = self.shared.frame.lock().unwrap();
+        frame.pixels = *display.pixels();
+        frame.version += 1;
+        self.shared.frame_changed.notify_all();
+    }
+}
+
+/// What a viewer needs to be able to press keys.
+#[derive(Clone)]
+struct Control {
+    key_events: Sender<(Key, bool)>,
+    bindings: Arc<HashMap<String, Key>>,
+    /// How many viewers are holding each hex key. Keys are only released once every viewer
+    /// holding them has let go.
+    presses: Arc<Mutex<[usize; 16]>>,
+}
+
+impl Control {
+    /// Records whether a viewer is holding a key, pressing or releasing it on the machine when
+    /// it's the first viewer to hold it or the last to let go. Returns false once the emulator
+    /// has gone away.
+    fn set_held(&self, key: Key, down: bool, held: &mut [bool; 16]) -> bool {
+        let index = key.0 as usize;
+        if held[index] == down {
+            return true;
+        }
+        held[index] = down;
+
+        let mut presses = self.presses.lock().unwrap();
+        let count = &mut presses[index];
+        let changed = if down {
+            *count += 1;
+            *count == 1
+        } else {
+            *count -= 1;
+            *count == 0
+        };
+        !changed || self.key_events.send((key, down)).is_ok()
+    }
+}
+
+/// Hands each incoming connection off to its own thread. Viewers are only given control if they
+/// connected to the control address.
+fn accept_viewers(listener: TcpListener, shared: Arc<Shared>, control: Option<Control>) {
+    for stream in listener.incoming() {
+        let stream = match stream {
+            Ok(stream) => stream,
+            Err(_) => continue,
+        };
+
+        let shared = shared.clone();
+        let control = control.clone();
+        thread::spawn(move || {
+            // Errors only mean the viewer went away, so there's nothing to report
+            let _ = serve_viewer(stream, shared, control);
+        });
+    }
+}
+
+/// Format of the pixels sent to a viewer. Only true colour formats are supported.
+#[derive(Copy, Clone)]
+struct PixelFormat {
+    bits_per_pixel: u8,
+    big_endian: bool,
+    max: [u16; 3],
+    shift: [u8; 3],
+}
+
+impl PixelFormat {
+    /// 32-bit little-endian xRGB, which every viewer understands.
+    const DEFAULT: PixelFormat = PixelFormat {
+        bits_per_pixel: 32,
+        big_endian: false,
+        max: [255, 255, 255],
+        shift: [16, 8, 0],
+    };
+
+    fn to_bytes(self) -> [u8; 16] {
+        let mut bytes = [0; 16];
+        bytes[0] = self.bits_per_pixel;
+        bytes[1] = 24; // depth
+        bytes[2] = self.big_endian as u8;
+        bytes[3] = 1; // true colour
+        bytes[4..6].copy_from_slice(&self.max[0].to_be_bytes());
+        bytes[6..8].copy_from_slice(&self.max[1].to_be_bytes());
+        bytes[8..10].copy_from_slice(&self.max[2].to_be_bytes());
+        bytes[10..13].copy_from_slice(&self.shift);
+        bytes
+    }
+
+    /// Parses a pixel format sent by a viewer, returning `None` for formats that use a colour map
+    /// or shift colours out of a 32-bit pixel.
+    fn from_bytes(bytes: &[u8; 16]) -> Option<PixelFormat> {
+        let true_colour = bytes[3] != 0;
+        let bits_per_pixel = bytes[0];
+        if !true_colour || ![8, 16, 32].contains(&bits_per_pixel) {
+            return None;
+        }
+        if bytes[10..13].iter().any(|&shift| shift >= 32) {
+            return None;
+        }
+
+        Some(PixelFormat {
+            bits_per_pixel,
+            big_endian: bytes[2] != 0,
+            max: [
+                u16::from_be_bytes([bytes[4], bytes[5]]),
+                u16::from_be_bytes([bytes[6], bytes[7]]),
+                u16::from_be_bytes([bytes[8], bytes[9]]),
+            ],
+            shift: [bytes[10], bytes[11], bytes[12]],
+        })
+    }
+
+    fn encode(self, Rgb(r, g, b): Rgb, out: &mut Vec<u8>) {
+        let mut value = 0u32;
+        for (i, &component) in [r, g, b].iter().enumerate() {
+            let scaled = component as u32 * self.max[i] as u32 / 255;
+            value |= scaled << self.shift[i];
+        }
+
+        let bytes = self.bits_per_pixel as usize / 8;
+        if self.big_endian {
+            out.extend_from_slice(&value.to_be_bytes()[4 - bytes..]);
+        } else {
+            out.extend_from_slice(&value.to_le_bytes()[..bytes]);
+        }
+    }
+}
+
+/// Per-viewer state shared between the thread reading its messages and the thread sending it
+/// updates.
+struct Viewer {
+    pixel_format: Mutex<PixelFormat>,
+    update_request: AtomicU8,
+    disconnected: AtomicBool,
+}
+
+fn serve_viewer(
+    mut stream: TcpStream,
+    shared: Arc<Shared>,
+    control: Option<Control>,
+) -> std::io::Result<()> {
+    stream.set_nodelay(true)?;
+    handshake(&mut stream, &shared)?;
+
+    let viewer = Arc::new(Viewer {
+        pixel_format: Mutex::new(PixelFormat::DEFAULT),
+        update_request: AtomicU8::new(NO_REQUEST),
+        disconnected: AtomicBool::new(false),
+    });
+
+    let writer_stream = stream.try_clone()?;
+    let writer_viewer = viewer.clone();
+    let writer_shared = shared.clone();
+    let writer = thread::spawn(move || {
+        let _ = send_updates(writer_stream, &writer_shared, &writer_viewer);
+        writer_viewer.disconnected.store(true, Ordering::SeqCst);
+    });
+
+    let mut held = [false; 16];
+    let result = read_messages(&mut stream, &shared, &viewer, control.as_ref(), &mut held);
+
+    // Let go of any keys the viewer was holding, so they don't stay pressed forever
+    if let Some(control) = &control {
+        for key in 0..16 {
+            control.set_held(Key(key), false, &mut held);
+        }
+    }
+    viewer.disconnected.store(true, Ordering::SeqCst);
+    shared.frame_changed.notify_all();
+    let _ = writer.join();
+    result
+}
+
+fn handshake(stream: &mut TcpStream, shared: &Shared) -> std::io::Result<()> {
+    stream.write_all(PROTOCOL_VERSION)?;
+    let mut client_version = [0; 12];
+    stream.read_exact(&mut client_version)?;
+    let minor = &client_version[8..11];
+
+    if minor == b"003" {
+        // Version 3.3 has the server pick the security type
+        stream.write_all(&(SECURITY_NONE as u32).to_be_bytes())?;
+    } else {
+        stream.write_all(&[1, SECURITY_NONE])?;
+        let mut security_type = [0; 1];
+        stream.read_exact(&mut security_type)?;
+        if security_type[0] != SECURITY_NONE {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                "unsupported security type",
+            ));
+        }
+
+        // Only version 3.8 reports the result when no authentication is used
+        if minor != b"007" {
+            stream.write_all(&0u32.to_be_bytes())?;
+        }
+    }
+
+    // The shared flag can be ignored since every viewer is allowed to stay connected
+    let mut client_init = [0; 1];
+    stream.read_exact(&mut client_init)?;
+
+    let name = b"Chemu";
+    let mut server_init = Vec::new();
+    server_init.extend_from_slice(&((WIDTH * shared.scale) as u16).to_be_bytes());
+    server_init.extend_from_slice(&((HEIGHT * shared.scale) as u16).to_be_bytes());
+    server_init.extend_from_slice(&PixelFormat::DEFAULT.to_bytes());
+    server_init.extend_from_slice(&(name.len() as u32).to_be_bytes());
+    server_init.extend_from_slice(name);
+    stream.write_all(&server_init)
+}
+
+fn read_messages(
+    stream: &mut TcpStream,
+    shared: &Shared,
+    viewer: &Viewer,
+    control: Option<&Control>,
+    held: &mut [bool; 16],
+) -> std::io::Result<()> {
+    loop {
+        let mut message_type = [0; 1];
+        stream.read_exact(&mut message_type)?;
+
+        match message_type[0] {
+            SET_PIXEL_FORMAT => {
+                let mut message = [0; 19];
+                stream.read_exact(&mut message)?;
+                let format: &[u8; 16] = message[3..].try_into().unwrap();
+                // Keep sending the default format if the viewer asks for one that isn't supported
+                if let Some(format) = PixelFormat::from_bytes(format) {
+                    *viewer.pixel_format.lock().unwrap() = format;
+                }
+            }
+            SET_ENCODINGS => {
+                // Raw is always supported, so the viewer's preferences don't matter
+                let mut header = [0; 3];
+                stream.read_exact(&mut header)?;
+                let count = u16::from_be_bytes([header[1], header[2]]) as usize;
+                let mut encodings = vec![0; count * 4];
+                stream.read_exact(&mut encodings)?;
+            }
+            FRAMEBUFFER_UPDATE_REQUEST => {
+                let mut message = [0; 9];
+                stream.read_exact(&mut message)?;
+                let request = if message[0] != 0 {
+                    INCREMENTAL_REQUEST
+                } else {
+                    FULL_REQUEST
+                };
+                // A full request takes priority over an incremental one
+                viewer.update_request.fetch_max(request, Ordering::SeqCst);
+                shared.frame_changed.notify_all();
+            }
+            KEY_EVENT => {
+                let mut message = [0; 7];
+                stream.read_exact(&mut message)?;
+                let down = message[0] != 0;
+                let keysym = u32::from_be_bytes(message[3..7].try_into().unwrap());
+
+                if let Some(control) = control {
+                    let key = key_name(keysym).and_then(|name| control.bindings.get(&name));
+                    if let Some(&key) = key {
+                        if !control.set_held(key, down, held) {
+                            return Ok(());
+                        }
+                    }
+                }
+            }
+            POINTER_EVENT => {
+                let mut message = [0; 5];
+                stream.read_exact(&mut message)?;
+            }
+            CLIENT_CUT_TEXT => {
+                let mut header = [0; 7];
+                stream.read_exact(&mut header)?;
+                let length = u32::from_be_bytes(header[3..7].try_into().unwrap()) as u64;
+                std::io::copy(&mut (&mut *stream).take(length), &mut std::io::sink())?;
+            }
+            _ => {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::InvalidData,
+                    "unknown message type",
+                ))
+            }
+        }
+    }
+}
+
+/// Translates an X11 keysym into the name SDL gives the key, so the same keymap can be used as in
+/// the SDL frontend. Shifted symbols are translated to the key they're on in the US layout, since
+/// viewers send the shifted keysym when a key is released while Shift is held.
+fn key_name(keysym: u32) -> Option<String> {
+    let name = match keysym {
+        0x20 => "Space",
+        0xFF08 => "Backspace",
+        0xFF09 => "Tab",
+        0xFF0D => "Return",
+        0xFF1B => "Escape",
+        0xFF50 => "Home",
+        0xFF51 => "Left",
+        0xFF52 => "Up",
+        0xFF53 => "Right",
+        0xFF54 => "Down",
+        0xFF55 => "PageUp",
+        0xFF56 => "PageDown",
+        0xFF57 => "End",
+        0xFF63 => "Insert",
+        0xFFFF => "Delete",
+        0xFFE1 => "Left Shift",
+        0xFFE2 => "Right Shift",
+        0xFFE3 => "Left Ctrl",
+        0xFFE4 => "Right Ctrl",
+        0xFFE9 => "Left Alt",
+        0xFFEA => "Right Alt",
+        0xFF8D => "Keypad Enter",
+        0xFFAA => "Keypad *",
+        0xFFAB => "Keypad +",
+        0xFFAD => "Keypad -",
+        0xFFAE | 0xFF9F => "Keypad .",
+        0xFFAF => "Keypad /",
+        // The keypad sends navigation keysyms rather than digits while Num Lock is off
+        0xFF9E => "Keypad 0",
+        0xFF9C => "Keypad 1",
+        0xFF99 => "Keypad 2",
+        0xFF9B => "Keypad 3",
+        0xFF96 => "Keypad 4",
+        0xFF9D => "Keypad 5",
+        0xFF98 => "Keypad 6",
+        0xFF95 => "Keypad 7",
+        0xFF97 => "Keypad 8",
+        0xFF9A => "Keypad 9",
+        0xFFB0..=0xFFB9 => return Some(format!("Keypad {}", keysym - 0xFFB0)),
+        0xFFBE..=0xFFC9 => return Some(format!("F{}", keysym - 0xFFBE + 1)),
+        // Printable Latin-1 keysyms match their character codes
+        0x21..=0x7E => {
+            let character = keysym as u8 as char;
+            let unshifted = match character {
+                '!' => '1',
+                '@' => '2',
+                '#' => '3',
+                '$' => '4',
+                '%' => '5',
+                '^' => '6',
+                '&' => '7',
+                '*' => '8',
+                '(' => '9',
+                ')' => '0',
+                '_' => '-',
+                '+' => '=',
+                '{' => '[',
+                '}' => ']',
+                ':' => ';',
+                '"' => '\'',
+                '<' => ',',
+                '>' => '.',
+                '?' => '/',
+                '~' => '`',
+                '|' => '\\',
+                _ => character.to_ascii_uppercase(),
+            };
+            return Some(unshifted.to_string());
+        }
+        _ => return None,
+    };
+
+    Some(name.to_string())
+}
+
+/// Sends the viewer a framebuffer update whenever it has asked for one and there's something new
+/// to show.
+fn send_updates(mut stream: TcpStream, shared: &Shared, viewer: &Viewer) -> std::io::Result<()> {
+    let mut last_sent: Option<Frame> = None;
+
+    loop {
+        let frame = {
+            let mut frame = shared.frame.lock().unwrap();
+            loop {
+                if viewer.disconnected.load(Ordering::SeqCst) {
+                    return Ok(());
+                }
+
+                let request = viewer.update_request.load(Ordering::SeqCst);
+                let changed = match &last_sent {
+                    Some(sent) => sent.version != frame.version,
+                    None => true,
+                };
+                if request == FULL_REQUEST || (request == INCREMENTAL_REQUEST && changed) {
+                    break;
+                }
+
+                frame = shared
+                    .frame_changed
+                    .wait_timeout(frame, UPDATE_POLL)
+                    .unwrap()
+                    .0;
+            }
+            frame.clone()
+        };
+
+        let request = viewer.update_request.swap(NO_REQUEST, Ordering::SeqCst);
+        let region = match &last_sent {
+            Some(sent) if request == INCREMENTAL_REQUEST => changed_region(&sent.pixels, &frame),
+            _ => Some((0, 0, WIDTH, HEIGHT)),
+        };
+
+        // Nothing visible changed, so the request stays open for the next change
+        let region = match region {
+            Some(region) => region,
+            None => {
+                viewer
+                    .update_request
+                    .fetch_max(INCREMENTAL_REQUEST, Ordering::SeqCst);
+                last_sent = Some(frame);
+                continue;
+            }
+        };
+
+        let pixel_format = *viewer.pixel_format.lock().unwrap();
+        stream.write_all(&encode_update(shared, &frame, region, pixel_format))?;
+        last_sent = Some(frame);
+    }
+}
+
+/// Finds the smallest rectangle, in display pixels, that contains every pixel that differs between
+/// the two frames. Returns `None` if they're identical.
+fn changed_region(
+    previous: &[[bool; WIDTH]; HEIGHT],
+    frame: &Frame,
+) -> Option<(usize, usize, usize, usize)> {
+    let mut bounds: Option<(usize, usize, usize, usize)> = None;
+    for (y, (old_row, new_row)) in previous.iter().zip(frame.pixels.iter()).enumerate() {
+        for (x, (old, new)) in old_row.iter().zip(new_row.iter()).enumerate() {
+            if old != new {
+                bounds = Some(match bounds {
+                    Some((left, top, right, bottom)) => {
+                        (left.min(x), top.min(y), right.max(x), bottom.max(y))
+                    }
+                    None => (x, y, x, y),
+                });
+            }
+        }
+    }
+
+    bounds.map(|(left, top, right, bottom)| (left, top, right - left + 1, bottom - top + 1))
+}
+
+fn encode_update(
+    shared: &Shared,
+    frame: &Frame,
+    (x, y, width, height): (usize, usize, usize, usize),
+    pixel_format: PixelFormat,
+) -> Vec<u8> {
+    let scale = shared.scale;
+    let mut message = vec![0, 0];
+    message.extend_from_slice(&1u16.to_be_bytes());
+    for value in [x * scale, y * scale, width * scale, height * scale].iter() {
+        message.extend_from_slice(&(*value as u16).to_be_bytes());
+    }
+    message.extend_from_slice(&ENCODING_RAW.to_be_bytes());
+
+    for row in &frame.pixels[y..y + height] {
+        for _ in 0..scale {
+            for &pixel in &row[x..x + width] {
+                let colour = shared.palette.colour(pixel);
+                for _ in 0..scale {
+                    pixel_format.encode(colour, &mut message);
+                }
+            }
+        }
+    }
+
+    message
+}
@@ -0,0 +1,521 @@
+//! A web server, so a running machine can be watched and played from a browser. `GET /` serves a
+//! page that draws the display on a canvas, and `GET /watch` serves the same page for spectators.
+//! The page then opens a WebSocket to the same path, and only sockets opened on `/` can press
+//! keys.
+//!
+//! Every message on the socket is binary, starting with a byte giving its type. The server sends:
+//!
+//! - `PALETTE`, followed by the palette's colours as RGB triples, starting with the colour of
+//!   pixels that are off, once after connecting.
+//! - `FULL_FRAME`, followed by the whole display, once after connecting. Each row is packed into
+//!   8 bytes with the leftmost pixel in the most significant bit.
+//! - `CHANGED_ROWS`, followed by a row index and its 8 packed bytes for each row that changed.
+//! - `SOUND`, followed by 1 when the sound timer starts and 0 when it stops.
+//!
+//! Players send `PRESS` or `RELEASE` followed by the hex key.
+
+use crate::display::{HEIGHT, WIDTH};
+use crate::frontend::Frontend;
+use crate::keyboard::{Key, Keyboard};
+use crate::machine::Machine;
+use crate::palette::Palette;
+use crate::sha1;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{Shutdown, SocketAddr, TcpListener, TcpStream};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+use std::time::Duration;
+
+pub const PALETTE: u8 = 0x00;
+pub const FULL_FRAME: u8 = 0x01;
+pub const CHANGED_ROWS: u8 = 0x02;
+pub const SOUND: u8 = 0x03;
+pub const RELEASE: u8 = 0x10;
+pub const PRESS: u8 = 0x11;
+
+/// Bytes taken by each packed row of the display.
+pub const ROW_BYTES: usize = WIDTH / 8;
+
+const PAGE: &str = include_str!("web.html");
+
+/// Appended to the client's key to work out the accept key, as given in RFC 6455.
+const WEBSOCKET_GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+const OPCODE_BINARY: u8 = 0x2;
+const OPCODE_CLOSE: u8 = 0x8;
+const OPCODE_PING: u8 = 0x9;
+const OPCODE_PONG: u8 = 0xA;
+
+/// Players only ever send two byte messages, so anything much longer is a misbehaving client.
+const MAX_MESSAGE: u64 = 1024;
+/// Requests longer than this many bytes are refused.
+const MAX_REQUEST: u64 = 8192;
+
+/// How long a viewer's writer waits for the display to change before checking whether the viewer
+/// has disconnected.
+const UPDATE_POLL: Duration = Duration::from_millis(100);
+
+/// Serves the display to browsers over HTTP and WebSockets.
+pub struct WebFrontend {
+    shared: Arc<Shared>,
+    key_events: Receiver<(Key, bool)>,
+    local_address: SocketAddr,
+}
+
+/// State shared between the emulator and the threads serving each viewer.
+struct Shared {
+    frame: Mutex<Frame>,
+    frame_changed: Condvar,
+    palette: Palette,
+}
+
+#[derive(Clone)]
+struct Frame {
+    pixels: [[bool; WIDTH]; HEIGHT],
+    sound: bool,
+    /// Incremented every time the pixels or sound change, so viewers can tell whether they're up
+    /// to date.
+    version: u64,
+}
+
+impl WebFrontend {
+    /// Starts listening for browsers on the given address.
+    pub fn new(address: SocketAddr, palette: Palette) -> std::io::Result<WebFrontend> {
+        let shared = Arc::new(Shared {
+            frame: Mutex::new(Frame {
+                pixels: [[false; WIDTH]; HEIGHT],
+                sound: false,
+                version: 0,
+            }),
+            frame_changed: Condvar::new(),
+            palette,
+        });
+        let (key_sender, key_events) = channel();
+
+        let listener = TcpListener::bind(address)?;
+        let local_address = listener.local_addr()?;
+        let accept_shared = shared.clone();
+        thread::spawn(move || accept_connections(listener, accept_shared, key_sender));
+
+        Ok(WebFrontend {
+            shared,
+            key_events,
+            local_address,
+        })
+    }
+
+    /// The address the server is listening on, which is useful when it was asked for port 0.
+    pub fn local_address(&self) -> SocketAddr {
+        self.local_address
+    }
+}
+
+impl Frontend for WebFrontend {
+    fn process_events(&mut self, keyboard: &mut Keyboard) {
+        for (key, down) in self.key_events.try_iter() {
+            if down {
+                keyboard.press(key);
+            } else {
+                keyboard.release(key);
+            }
+        }
+    }
+
+    fn render(&mut self, machine: &Machine) {
+        let display = machine.display();
+        let sound = machine.sound_active();
+
+        let mut frame = self.shared.frame.lock().unwrap();
+        if !display.update_pending() && frame.sound == sound {
+            return;
+        }
+
+        frame.pixels = *display.pixels();
+        frame.sound = sound;
+        frame.version += 1;
+        self.shared.frame_changed.notify_all();
+    }
+}
+
+/// Packs a row of pixels into bytes, with the leftmost pixel in the most significant bit.
+pub fn pack_row(row: &[bool; WIDTH]) -> [u8; ROW_BYTES] {
+    let mut packed = [0; ROW_BYTES];
+    for (x, &pixel) in row.iter().enumerate() {
+        if pixel {
+            packed[x / 8] |= 0x80 >> (x % 8);
+        }
+    }
+    packed
+}
+
+fn accept_connections(listener: TcpListener, shared: Arc<Shared>, key_events: Sender<(Key, bool)>) {
+    for stream in listener.incoming() {
+        let stream = match stream {
+            Ok(stream) => stream,
+            Err(_) => continue,
+        };
+
+        let shared = shared.clone();
+        let key_events = key_events.clone();
+        thread::spawn(move || {
+            // Errors only mean the browser went away, so there's nothing to report
+            let _ = handle_connection(stream, shared, key_events);
+        });
+    }
+}
+
+/// The parts of an HTTP request the server cares about.
+struct Request {
+    method: String,
+    path: String,
+    /// Header names are lowercased, since they're case-insensitive.
+    headers: Vec<(String, String)>,
+}
+
+impl Request {
+    fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(header, _)| header == name)
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+fn read_request(reader: &mut impl BufRead) -> std::io::Result<Request> {
+    let invalid = || std::io::Error::new(std::io::ErrorKind::InvalidData, "invalid HTTP request");
+
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line)?;
+    let mut parts = request_line.split_whitespace();
+    let method = parts.next().ok_or_else(invalid)?.to_string();
+    let target = parts.next().ok_or_else(invalid)?;
+    // The page has no use for query strings, so they're ignored
+    let path = target.split('?').next().unwrap_or_default().to_string();
+
+    let mut headers = Vec::new();
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line)? == 0 {
+            return Err(invalid());
+        }
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+
+        let mut header = line.splitn(2, ':');
+        let name = header
+            .next()
+            .unwrap_or_default()
+            .trim()
+            .to_ascii_lowercase();
+        let value = header.next().ok_or_else(invalid)?.trim().to_string();
+        headers.push((name, value));
+    }
+
+    Ok(Request {
+        method,
+        path,
+        headers,
+    })
+}
+
+fn handle_connection(
+    stream: TcpStream,
+    shared: Arc<Shared>,
+    key_events: Sender<(Key, bool)>,
+) -> std::io::Result<()> {
+    stream.set_nodelay(true)?;
+    let mut writer = stream.try_clone()?;
+    let mut reader = BufReader::new(stream);
+    let request = read_request(&mut (&mut reader).take(MAX_REQUEST))?;
+
+    let control = match request.path.as_str() {
+        "/" => true,
+        "/watch" => false,
+        _ => return write_response(&mut writer, "404 Not Found", "text/plain", b"Not found"),
+    };
+    if request.method != "GET" {
+        return write_response(
+            &mut writer,
+            "405 Method Not Allowed",
+            "text/plain",
+            b"Method not allowed",
+        );
+    }
+
+    let upgrade = request.header("upgrade").unwrap_or_default();
+    if !upgrade.eq_ignore_ascii_case("websocket") {
+        return write_response(
+            &mut writer,
+            "200 OK",
+            "text/html; charset=utf-8",
+            PAGE.as_bytes(),
+        );
+    }
+
+    let key = match request.header("sec-websocket-key") {
+        Some(key) => key,
+        None => {
+            return write_response(
+                &mut writer,
+                "400 Bad Request",
+                "text/plain",
+                b"Missing WebSocket key",
+            )
+        }
+    };
+    let response = format!(
+        "HTTP/1.1 101 Switching Protocols\r\n\
+         Upgrade: websocket\r\n\
+         Connection: Upgrade\r\n\
+         Sec-WebSocket-Accept: {}\r\n\r\n",
+        accept_key(key)
+    );
+    writer.write_all(response.as_bytes())?;
+
+    serve_viewer(
+        reader,
+        writer,
+        shared,
+        if control { Some(key_events) } else { None },
+    )
+}
+
+fn write_response(
+    stream: &mut TcpStream,
+    status: &str,
+    content_type: &str,
+    body: &[u8],
+) -> std::io::Result<()> {
+    let header = format!(
+        "HTTP/1.1 {}\r\n\
+         Content-Type: {}\r\n\
+         Content-Length: {}\r\n\
+         Connection: close\r\n\r\n",
+        status,
+        content_type,
+        body.len()
+    );
+    stream.write_all(header.as_bytes())?;
+    stream.write_all(body)?;
+    stream.shutdown(Shutdown::Both)
+}
+
+/// Works out the `Sec-WebSocket-Accept` value that proves to the browser the server understood its
+/// handshake.
+pub fn accept_key(key: &str) -> String {
+    let digest = sha1::digest(format!("{}{}", key, WEBSOCKET_GUID).as_bytes());
+    base64::encode(digest)
+}
+
+/// Per-viewer state shared between the thread reading its messages and the thread sending it
+/// updates.
+struct Viewer {
+    /// Both threads send frames, so writes are serialised to keep them from interleaving.
+    stream: Mutex<TcpStream>,
+    disconnected: AtomicBool,
+}
+
+fn serve_viewer(
+    mut reader: BufReader<TcpStream>,
+    writer: TcpStream,
+    shared: Arc<Shared>,
+    key_events: Option<Sender<(Key, bool)>>,
+) -> std::io::Result<()> {
+    let viewer = Arc::new(Viewer {
+        stream: Mutex::new(writer),
+        disconnected: AtomicBool::new(false),
+    });
+
+    let writer_viewer = viewer.clone();
+    let writer_shared = shared.clone();
+    let writer = thread::spawn(move || {
+        let _ = send_updates(&writer_shared, &writer_viewer);
+        writer_viewer.disconnected.store(true, Ordering::SeqCst);
+    });
+
+    let mut held = [false; 16];
+    let result = read_messages(&mut reader, &viewer, key_events.as_ref(), &mut held);
+
+    // Let go of any keys the player was holding, so they don't stay pressed forever
+    if let Some(key_events) = &key_events {
+        for (key, _) in held.iter().enumerate().filter(|(_, &held)| held) {
+            let _ = key_events.send((Key(key as u8), false));
+        }
+    }
+
+    viewer.disconnected.store(true, Ordering::SeqCst);
+    shared.frame_changed.notify_all();
+    let _ = writer.join();
+    let _ = viewer.stream.lock().unwrap().shutdown(Shutdown::Both);
+    result
+}
+
+fn read_messages(
+    reader: &mut impl Read,
+    viewer: &Viewer,
+    key_events: Option<&Sender<(Key, bool)>>,
+    held: &mut [bool; 16],
+) -> std::io::Result<()> {
+    loop {
+        let (opcode, payload) = read_frame(reader)?;
+
+        match opcode {
+            OPCODE_CLOSE => {
+                let mut stream = viewer.stream.lock().unwrap();
+                return write_frame(&mut *stream, OPCODE_CLOSE, &[]);
+            }
+            OPCODE_PING => {
+                let mut stream = viewer.stream.lock().unwrap();
+                write_frame(&mut *stream, OPCODE_PONG, &payload)?;
+            }
+            OPCODE_BINARY => {
+                // Spectators' key presses are ignored
+                let key_events = match key_events {
+                    Some(key_events) => key_events,
+                    None => continue,
+                };
+
+                if let [message_type, key] = payload[..] {
+                    let down = match message_type {
+                        PRESS => true,
+                        RELEASE => false,
+                        _ => continue,
+                    };
+                    if key as usize >= held.len() {
+                        continue;
+                    }
+
+                    held[key as usize] = down;
+                    if key_events.send((Key(key), down)).is_err() {
+                        return Ok(());
+                    }
+                }
+            }
+            // Text messages, pongs and anything else aren't part of the protocol
+            _ => {}
+        }
+    }
+}
+
+/// Reads one frame sent by a client, returning its opcode and unmasked payload. Fragmented
+/// messages aren't reassembled, since the messages clients send fit in a single frame.
+pub fn read_frame(reader: &mut impl Read) -> std::io::Result<(u8, Vec<u8>)> {
+    let mut header = [0; 2];
+    reader.read_exact(&mut header)?;
+    let opcode = header[0] & 0x0F;
+    let masked = header[1] & 0x80 != 0;
+
+    let length = match header[1] & 0x7F {
+        126 => {
+            let mut length = [0; 2];
+            reader.read_exact(&mut length)?;
+            u16::from_be_bytes(length) as u64
+        }
+        127 => {
+            let mut length = [0; 8];
+            reader.read_exact(&mut length)?;
+            u64::from_be_bytes(length)
+        }
+        length => length as u64,
+    };
+    if length > MAX_MESSAGE {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            "WebSocket message too long",
+        ));
+    }
+
+    let mut mask = [0; 4];
+    if masked {
+        reader.read_exact(&mut mask)?;
+    }
+    let mut payload = vec![0; length as usize];
+    reader.read_exact(&mut payload)?;
+    for (i, byte) in payload.iter_mut().enumerate() {
+        *byte ^= mask[i % 4];
+    }
+
+    Ok((opcode, payload))
+}
+
+/// Sends a single unmasked frame, as servers do.
+pub fn write_frame(stream: &mut impl Write, opcode: u8, payload: &[u8]) -> std::io::Result<()> {
+    let mut frame = vec![0x80 | opcode];
+    match payload.len() {
+        length @ 0..=125 => frame.push(length as u8),
+        length @ 126..=0xFFFF => {
+            frame.push(126);
+            frame.extend_from_slice(&(length as u16).to_be_bytes());
+        }
+        length => {
+            frame.push(127);
+            frame.extend_from_slice(&(length as u64).to_be_bytes());
+        }
+    }
+    frame.extend_from_slice(payload);
+    stream.write_all(&frame)
+}
+
+/// Sends the viewer the palette and the whole display, then the rows that change every time the
+/// display is updated.
+fn send_updates(shared: &Shared, viewer: &Viewer) -> std::io::Result<()> {
+    let mut last_sent = shared.frame.lock().unwrap().clone();
+    {
+        let palette = &shared.palette;
+        let mut message = vec![PALETTE];
+        for colour in palette.colours.iter() {
+            message.extend_from_slice(&[colour.0, colour.1, colour.2]);
+        }
+
+        let mut full_frame = vec![FULL_FRAME];
+        for row in last_sent.pixels.iter() {
+            full_frame.extend_from_slice(&pack_row(row));
+        }
+
+        let mut stream = viewer.stream.lock().unwrap();
+        write_frame(&mut *stream, OPCODE_BINARY, &message)?;
+        write_frame(&mut *stream, OPCODE_BINARY, &full_frame)?;
+        write_frame(&mut *stream, OPCODE_BINARY, &[SOUND, last_sent.sound as u8])?;
+    }
+
+    loop {
+        let frame = {
+            let mut frame = shared.frame.lock().unwrap();
+            loop {
+                if viewer.disconnected.load(Ordering::SeqCst) {
+                    return Ok(());
+                }
+                if frame.version != last_sent.version {
+                    break;
+                }
+
+                frame = shared
+                    .frame_changed
+                    .wait_timeout(frame, UPDATE_POLL)
+                    .unwrap()
+                    .0;
+            }
+            frame.clone()
+        };
+
+        let mut changed_rows = vec![CHANGED_ROWS];
+        for (y, (old, new)) in last_sent.pixels.iter().zip(frame.pixels.iter()).enumerate() {
+            if old != new {
+                changed_rows.push(y as u8);
+                changed_rows.extend_from_slice(&pack_row(new));
+            }
+        }
+
+        let mut stream = viewer.stream.lock().unwrap();
+        if changed_rows.len() > 1 {
+            write_frame(&mut *stream, OPCODE_BINARY, &changed_rows)?;
+        }
+        if frame.sound != last_sent.sound {
+            write_frame(&mut *stream, OPCODE_BINARY, &[SOUND, frame.sound as u8])?;
+        }
+        last_sent = frame;
+    }
+}
@@ -1,9 +1,10 @@
 use crate::instruction::DecodeErrorKind::IllegalOpCode;
 use crate::machine::{Register, RegisterParseError};
-use std::convert::TryInto;
+use core::convert::TryInto;
+use core::fmt;
+use core::fmt::{Display, Formatter};
+#[cfg(feature = "std")]
 use std::error::Error;
-use std::fmt;
-use std::fmt::{Display, Formatter};
 
 /// Represents all the possible instructions that can be encoded in the Chip-8 architecture.
 #[derive(Debug)]
@@ -100,6 +101,105 @@ pub enum Instruction {
     LdArray { end: Register },
 }
 
+impl Instruction {
+    /// Encodes the instruction into its 16-bit form. Decoding the result gives back the same
+    /// instruction.
+    pub fn encode(&self) -> u16 {
+        let x = |register: &Register| (*register as u16) << 8;
+        let y = |register: &Register| (*register as u16) << 4;
+        match self {
+            Instruction::Sys { addr } => addr & 0x0FFF,
+            Instruction::Clr => 0x00E0,
+            Instruction::Ret => 0x00EE,
+            Instruction::Jmp { addr } => 0x1000 | (addr & 0x0FFF),
+            Instruction::Call { addr } => 0x2000 | (addr & 0x0FFF),
+            Instruction::SeImm { register, value } => 0x3000 | x(register) | *value as u16,
+            Instruction::SneImm { register, value } => 0x4000 | x(register) | *value as u16,
+            Instruction::SeReg { reg1, reg2 } => 0x5000 | x(reg1) | y(reg2),
+            Instruction::LdImm { register, value } => 0x6000 | x(register) | *value as u16,
+            Instruction::AddImm { register, value } => 0x7000 | x(register) | *value as u16,
+            Instruction::LdReg { dest, src } => 0x8000 | x(dest) | y(src),
+            Instruction::Or { dest, src } => 0x8001 | x(dest) | y(src),
+            Instruction::And { dest, src } => 0x8002 | x(dest) | y(src),
+            Instruction::Xor { dest, src } => 0x8003 | x(dest) | y(src),
+            Instruction::AddReg { dest, src } => 0x8004 | x(dest) | y(src),
+            Instruction::Sub { dest, src } => 0x8005 | x(dest) | y(src),
+            Instruction::Shr { dest, src } => 0x8006 | x(dest) | y(src),
+            Instruction::SubNeg { dest, src } => 0x8007 | x(dest) | y(src),
+            Instruction::Shl { dest, src } => 0x800E | x(dest) | y(src),
+            Instruction::SneReg { reg1, reg2 } => 0x9000 | x(reg1) | y(reg2),
+            Instruction::LdAddr { addr } => 0xA000 | (addr & 0x0FFF),
+            Instruction::JmpOff { base_addr } => 0xB000 | (base_addr & 0x0FFF),
+            Instruction::Rnd { register, mask } => 0xC000 | x(register) | *mask as u16,
+            Instruction::Drw {
+                x: reg_x,
+                y: reg_y,
+                length,
+            } => 0xD000 | x(reg_x) | y(reg_y) | (*length as u16 & 0xF),
+            Instruction::Skp { keycode } => 0xE09E | x(keycode),
+            Instruction::SkpNeg { keycode } => 0xE0A1 | x(keycode),
+            Instruction::ReadDelay { register } => 0xF007 | x(register),
+            Instruction::LdKey { register } => 0xF00A | x(register),
+            Instruction::StrDelay { register } => 0xF015 | x(register),
+            Instruction::StrSound { register } => 0xF018 | x(register),
+            Instruction::AddAddr { register } => 0xF01E | x(register),
+            Instruction::LdDigit { register } => 0xF029 | x(register),
+            Instruction::LdBcd { register } => 0xF033 | x(register),
+            Instruction::StrArray { end } => 0xF055 | x(end),
+            Instruction::LdArray { end } => 0xF065 | x(end),
+        }
+    }
+}
+
+/// Writes the instruction in the assembly language described in Cowgod's Chip-8 technical
+/// reference, with hex numbers written after a `#`, such as `LD V1, #2A`. The assembler reads the
+/// same syntax.
+impl Display for Instruction {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Instruction::Sys { addr } => write!(f, "SYS #{:03X}", addr),
+            Instruction::Clr => write!(f, "CLS"),
+            Instruction::Ret => write!(f, "RET"),
+            Instruction::Jmp { addr } => write!(f, "JP #{:03X}", addr),
+            Instruction::Call { addr } => write!(f, "CALL #{:03X}", addr),
+            Instruction::SeImm { register, value } => write!(f, "SE {}, #{:02X}", register, value),
+            Instruction::SneImm { register, value } => {
+                write!(f, "SNE {}, #{:02X}", register, value)
+            }
+            Instruction::SeReg { reg1, reg2 } => write!(f, "SE {}, {}", reg1, reg2),
+            Instruction::LdImm { register, value } => write!(f, "LD {}, #{:02X}", register, value),
+            Instruction::AddImm { register, value } => {
+                write!(f, "ADD {}, #{:02X}", register, value)
+            }
+            Instruction::LdReg { dest, src } => write!(f, "LD {}, {}", dest, src),
+            Instruction::Or { dest, src } => write!(f, "OR {}, {}", dest, src),
+            Instruction::And { dest, src } => write!(f, "AND {}, {}", dest, src),
+            Instruction::Xor { dest, src } => write!(f, "XOR {}, {}", dest, src),
+            Instruction::AddReg { dest, src } => write!(f, "ADD {}, {}", dest, src),
+            Instruction::Sub { dest, src } => write!(f, "SUB {}, {}", dest, src),
+            Instruction::Shr { dest, src } => write!(f, "SHR {}, {}", dest, src),
+            Instruction::SubNeg { dest, src } => write!(f, "SUBN {}, {}", dest, src),
+            Instruction::Shl { dest, src } => write!(f, "SHL {}, {}", dest, src),
+            Instruction::SneReg { reg1, reg2 } => write!(f, "SNE {}, {}", reg1, reg2),
+            Instruction::LdAddr { addr } => write!(f, "LD I, #{:03X}", addr),
+            Instruction::JmpOff { base_addr } => write!(f, "JP V0, #{:03X}", base_addr),
+            Instruction::Rnd { register, mask } => write!(f, "RND {}, #{:02X}", register, mask),
+            Instruction::Drw { x, y, length } => write!(f, "DRW {}, {}, {}", x, y, length),
+            Instruction::Skp { keycode } => write!(f, "SKP {}", keycode),
+            Instruction::SkpNeg { keycode } => write!(f, "SKNP {}", keycode),
+            Instruction::ReadDelay { register } => write!(f, "LD {}, DT", register),
+            Instruction::LdKey { register } => write!(f, "LD {}, K", register),
+            Instruction::StrDelay { register } => write!(f, "LD DT, {}", register),
+            Instruction::StrSound { register } => write!(f, "LD ST, {}", register),
+            Instruction::AddAddr { register } => write!(f, "ADD I, {}", register),
+            Instruction::LdDigit { register } => write!(f, "LD F, {}", register),
+            Instruction::LdBcd { register } => write!(f, "LD B, {}", register),
+            Instruction::StrArray { end } => write!(f, "LD [I], {}", end),
+            Instruction::LdArray { end } => write!(f, "LD {}, [I]", end),
+        }
+    }
+}
+
 /// Error that occurs while decoding an instruction.
 #[derive(Debug)]
 pub struct DecodeInstructionError {
@@ -137,6 +237,7 @@ impl Display for DecodeInstructionError {
     }
 }
 
+#[cfg(feature = "std")]
 impl Error for DecodeInstructionError {}
 
 /// Decodes a 16-bit encoded instruction into the decoded format.
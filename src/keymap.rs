@@ -0,0 +1,833 @@
+use crate::config::{config_dir, RomId};
+use crate::frontend::Command;
+use crate::keyboard::Key;
+use sdl2::controller::{Axis, Button};
+use sdl2::keyboard::Scancode;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::fmt::Formatter;
+use std::hash::Hash;
+use std::path::{Path, PathBuf};
+
+/// The conventional mapping of the COSMAC VIP hex keypad onto the left-hand side of a QWERTY
+/// keyboard. Entries are listed in keypad order, so the physical grid on the keyboard matches the
+/// grid on the original hardware.
+const COSMAC_LAYOUT: [(Scancode, u8); 16] = [
+    (Scancode::Num1, 0x1),
+    (Scancode::Num2, 0x2),
+    (Scancode::Num3, 0x3),
+    (Scancode::Num4, 0xC),
+    (Scancode::Q, 0x4),
+    (Scancode::W, 0x5),
+    (Scancode::E, 0x6),
+    (Scancode::R, 0xD),
+    (Scancode::A, 0x7),
+    (Scancode::S, 0x8),
+    (Scancode::D, 0x9),
+    (Scancode::F, 0xE),
+    (Scancode::Z, 0xA),
+    (Scancode::X, 0x0),
+    (Scancode::C, 0xB),
+    (Scancode::V, 0xF),
+];
+
+/// Keys for the actions the ROM database gives hex keys for, such as moving up or firing, so games
+/// can be played with the arrow keys as well as the hex keypad.
+const ACTION_LAYOUT: [(&str, Scancode); 12] = [
+    ("up", Scancode::Up),
+    ("down", Scancode::Down),
+    ("left", Scancode::Left),
+    ("right", Scancode::Right),
+    ("a", Scancode::Space),
+    ("b", Scancode::LShift),
+    ("player2Up", Scancode::Kp8),
+    ("player2Down", Scancode::Kp2),
+    ("player2Left", Scancode::Kp4),
+    ("player2Right", Scancode::Kp6),
+    ("player2A", Scancode::Kp0),
+    ("player2B", Scancode::KpEnter),
+];
+
+/// A second copy of the keypad on the right-hand side of the keyboard, so two players can share
+/// one keyboard without reaching across each other.
+const RIGHT_HALF_LAYOUT: [(Scancode, u8); 16] = [
+    (Scancode::Num7, 0x1),
+    (Scancode::Num8, 0x2),
+    (Scancode::Num9, 0x3),
+    (Scancode::Num0, 0xC),
+    (Scancode::U, 0x4),
+    (Scancode::I, 0x5),
+    (Scancode::O, 0x6),
+    (Scancode::P, 0xD),
+    (Scancode::J, 0x7),
+    (Scancode::K, 0x8),
+    (Scancode::L, 0x9),
+    (Scancode::Semicolon, 0xE),
+    (Scancode::M, 0xA),
+    (Scancode::Comma, 0x0),
+    (Scancode::Period, 0xB),
+    (Scancode::Slash, 0xF),
+];
+
+/// Default game controller bindings. The D-pad and left stick map onto the 2/4/6/8 keys that most
+/// games use for movement, and the face buttons cover the common action keys.
+const CONTROLLER_LAYOUT: [(ControllerInput, u8); 12] = [
+    (ControllerInput::Button(Button::DPadUp), 0x2),
+    (ControllerInput::Button(Button::DPadLeft), 0x4),
+    (ControllerInput::Button(Button::DPadRight), 0x6),
+    (ControllerInput::Button(Button::DPadDown), 0x8),
+    (
+        ControllerInput::Axis(Axis::LeftY, AxisDirection::Negative),
+        0x2,
+    ),
+    (
+        ControllerInput::Axis(Axis::LeftX, AxisDirection::Negative),
+        0x4,
+    ),
+    (
+        ControllerInput::Axis(Axis::LeftX, AxisDirection::Positive),
+        0x6,
+    ),
+    (
+        ControllerInput::Axis(Axis::LeftY, AxisDirection::Positive),
+        0x8,
+    ),
+    (ControllerInput::Button(Button::A), 0x5),
+    (ControllerInput::Button(Button::B), 0x0),
+    (ControllerInput::Button(Button::X), 0xE),
+    (ControllerInput::Button(Button::Y), 0xF),
+];
+
+/// Default hotkey bindings. Function keys are used where possible, since games don't use them.
+const HOTKEY_LAYOUT: [(Chord, Hotkey); 16] = [
+    (Chord::plain(Scancode::F5), Hotkey::Pause),
+    (Chord::plain(Scancode::Pause), Hotkey::Pause),
+    (Chord::plain(Scancode::F6), Hotkey::FrameAdvance),
+    (Chord::plain(Scancode::F8), Hotkey::Reset),
+    (Chord::plain(Scancode::Minus), Hotkey::SpeedDown),
+    (Chord::plain(Scancode::Equals), Hotkey::SpeedUp),
+    (Chord::plain(Scancode::Tab), Hotkey::FastForward),
+    (Chord::plain(Scancode::Escape), Hotkey::Quit),
+    (Chord::plain(Scancode::F4), Hotkey::Quit),
+    (Chord::plain(Scancode::F11), Hotkey::Fullscreen),
+    (
+        Chord::with(Modifier::Alt, Scancode::Return),
+        Hotkey::Fullscreen,
+    ),
+    (Chord::plain(Scancode::F12), Hotkey::Screenshot),
+    (
+        Chord::with(Modifier::Shift, Scancode::F12),
+        Hotkey::BitmapScreenshot,
+    ),
+    (Chord::plain(Scancode::F9), Hotkey::Record),
+    (Chord::plain(Scancode::F1), Hotkey::Status),
+    (Chord::plain(Scancode::F2), Hotkey::Keypad),
+];
+
+/// Names of the hotkeys as written in the keymap file.
+pub const HOTKEYS: [(&str, Hotkey); 13] = [
+    ("pause", Hotkey::Pause),
+    ("frame_advance", Hotkey::FrameAdvance),
+    ("reset", Hotkey::Reset),
+    ("speed_down", Hotkey::SpeedDown),
+    ("speed_up", Hotkey::SpeedUp),
+    ("fast_forward", Hotkey::FastForward),
+    ("quit", Hotkey::Quit),
+    ("fullscreen", Hotkey::Fullscreen),
+    ("screenshot", Hotkey::Screenshot),
+    ("screenshot_pbm", Hotkey::BitmapScreenshot),
+    ("record", Hotkey::Record),
+    ("status", Hotkey::Status),
+    ("keypad", Hotkey::Keypad),
+];
+
+/// Modifiers that hotkeys can be combined with, as in `Alt+Return`.
+pub const MODIFIERS: [(&str, Modifier); 3] = [
+    ("Shift", Modifier::Shift),
+    ("Ctrl", Modifier::Ctrl),
+    ("Alt", Modifier::Alt),
+];
+
+/// Fraction of an analog axis' range that is ignored around its centre, so worn sticks don't
+/// register as held.
+const DEFAULT_DEADZONE: f32 = 0.25;
+
+/// Translates physical keys and game controller inputs into Chip-8 hex keys. Keys are identified
+/// by scancode rather than keycode so the layout stays in the same place on AZERTY, Dvorak and
+/// other keyboard layouts.
+#[derive(Clone, Debug)]
+pub struct Keymap {
+    bindings: HashMap<Scancode, Key>,
+    controller_bindings: HashMap<ControllerInput, Key>,
+    hotkeys: HashMap<Chord, Hotkey>,
+    deadzone: i16,
+}
+
+/// A modifier key held along with a hotkey. Either of the left and right keys counts.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Modifier {
+    Shift,
+    Ctrl,
+    Alt,
+}
+
+/// A key that triggers a hotkey, optionally while a modifier is held.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Chord {
+    pub modifier: Option<Modifier>,
+    pub scancode: Scancode,
+}
+
+impl Chord {
+    pub const fn plain(scancode: Scancode) -> Chord {
+        Chord {
+            modifier: None,
+            scancode,
+        }
+    }
+
+    pub const fn with(modifier: Modifier, scancode: Scancode) -> Chord {
+        Chord {
+            modifier: Some(modifier),
+            scancode,
+        }
+    }
+
+    /// Parses an SDL key name, optionally after a modifier and a plus sign, such as `Shift+F12`.
+    fn from_name(name: &str) -> Option<Chord> {
+        // Some key names hold a plus sign themselves, such as `Keypad +`
+        if let Some((prefix, rest)) = name.split_once('+') {
+            let modifier = MODIFIERS
+                .iter()
+                .find(|(modifier_name, _)| modifier_name.eq_ignore_ascii_case(prefix))
+                .map(|&(_, modifier)| modifier);
+            if let (Some(modifier), Some(scancode)) = (modifier, Scancode::from_name(rest)) {
+                return Some(Chord::with(modifier, scancode));
+            }
+        }
+        Scancode::from_name(name).map(Chord::plain)
+    }
+
+    fn name(self) -> String {
+        let scancode = self.scancode.name();
+        match MODIFIERS
+            .iter()
+            .find(|&&(_, modifier)| Some(modifier) == self.modifier)
+        {
+            Some((modifier_name, _)) => format!("{}+{}", modifier_name, scancode),
+            None => scancode.to_string(),
+        }
+    }
+}
+
+/// Keys that control the emulator rather than pressing a hex key.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Hotkey {
+    /// Pauses the machine, or resumes it if it's paused.
+    Pause,
+    /// Runs a single frame while paused.
+    FrameAdvance,
+    /// Restarts the program from scratch.
+    Reset,
+    SpeedDown,
+    SpeedUp,
+    /// Runs the machine faster for as long as it's held.
+    FastForward,
+    Quit,
+    /// Switches between a window and fullscreen.
+    Fullscreen,
+    /// Saves a PNG of the window's contents.
+    Screenshot,
+    /// Saves a 1-bit PBM of the display at its own size.
+    BitmapScreenshot,
+    /// Starts recording, or stops and saves the recording.
+    Record,
+    /// Shows or hides the status line.
+    Status,
+    /// Shows or hides the keypad overlay.
+    Keypad,
+}
+
+/// Direction an analog axis is pushed in.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum AxisDirection {
+    Negative,
+    Positive,
+}
+
+/// A game controller button, or an analog axis pushed past the deadzone in one direction.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum ControllerInput {
+    Button(Button),
+    Axis(Axis, AxisDirection),
+}
+
+impl ControllerInput {
+    /// Parses SDL's name for a button (e.g. `a` or `dpup`), or an axis name followed by the
+    /// direction it's pushed in (e.g. `leftx-`). Triggers only move in one direction, so their
+    /// names can be given without a sign.
+    fn from_name(name: &str) -> Option<ControllerInput> {
+        if let Some(axis) = name.strip_suffix('+') {
+            return Axis::from_string(axis)
+                .map(|axis| ControllerInput::Axis(axis, AxisDirection::Positive));
+        }
+
+        if let Some(axis) = name.strip_suffix('-') {
+            return Axis::from_string(axis)
+                .map(|axis| ControllerInput::Axis(axis, AxisDirection::Negative));
+        }
+
+        Button::from_string(name)
+            .map(ControllerInput::Button)
+            .or_else(|| {
+                Axis::from_string(name)
+                    .map(|axis| ControllerInput::Axis(axis, AxisDirection::Positive))
+            })
+    }
+}
+
+impl From<Hotkey> for Command {
+    /// The command a hotkey gives when pressed.
+    fn from(hotkey: Hotkey) -> Command {
+        match hotkey {
+            Hotkey::Pause => Command::TogglePause,
+            Hotkey::FrameAdvance => Command::FrameAdvance,
+            Hotkey::Reset => Command::Reset,
+            Hotkey::SpeedDown => Command::SpeedDown,
+            Hotkey::SpeedUp => Command::SpeedUp,
+            Hotkey::FastForward => Command::FastForward(true),
+            Hotkey::Quit => Command::Quit,
+            Hotkey::Fullscreen => Command::ToggleFullscreen,
+            Hotkey::Screenshot => Command::Screenshot,
+            Hotkey::BitmapScreenshot => Command::BitmapScreenshot,
+            Hotkey::Record => Command::ToggleRecording,
+            Hotkey::Status => Command::ToggleStatus,
+            Hotkey::Keypad => Command::ToggleKeypad,
+        }
+    }
+}
+
+/// Built-in layouts that a keymap can start from before any individual keys are rebound.
+#[derive(Copy, Clone, Debug, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Layout {
+    /// The 1234/QWER/ASDF/ZXCV grid.
+    Cosmac,
+    /// The COSMAC grid on the left half of the keyboard, mirrored by 7890/UIOP/JKL;/M,./ on the
+    /// right half for two-player games.
+    Split,
+    /// No keys bound at all.
+    Empty,
+}
+
+/// Names of the layouts, as written in the keymap and config files.
+pub const LAYOUTS: [(&str, Layout); 3] = [
+    ("cosmac", Layout::Cosmac),
+    ("split", Layout::Split),
+    ("empty", Layout::Empty),
+];
+
+impl Layout {
+    pub fn parse(name: &str) -> Result<Layout, KeymapError> {
+        LAYOUTS
+            .iter()
+            .find(|(layout, _)| layout.eq_ignore_ascii_case(name))
+            .map(|&(_, layout)| layout)
+            .ok_or_else(|| KeymapError::UnknownLayout(name.to_string()))
+    }
+}
+
+impl Keymap {
+    pub fn new(layout: Layout) -> Keymap {
+        let mut keymap = Keymap {
+            bindings: HashMap::new(),
+            controller_bindings: HashMap::new(),
+            hotkeys: HOTKEY_LAYOUT.iter().copied().collect(),
+            deadzone: deadzone_from_fraction(DEFAULT_DEADZONE),
+        };
+
+        match layout {
+            Layout::Cosmac => keymap.bind_all(&COSMAC_LAYOUT),
+            Layout::Split => {
+                keymap.bind_all(&COSMAC_LAYOUT);
+                keymap.bind_all(&RIGHT_HALF_LAYOUT);
+            }
+            Layout::Empty => {}
+        }
+
+        if let Layout::Cosmac | Layout::Split = layout {
+            for &(input, key) in CONTROLLER_LAYOUT.iter() {
+                keymap.controller_bindings.insert(input, Key(key));
+            }
+        }
+
+        keymap
+    }
+
+    /// Binds the key for an action named in the ROM database, such as `up` or `player2A`, to a hex
+    /// key. Keys that are already bound to something are left alone, so the keymap file wins.
+    pub fn bind_action(&mut self, action: &str, key: Key) {
+        let scancode = ACTION_LAYOUT
+            .iter()
+            .find(|(name, _)| *name == action)
+            .map(|&(_, scancode)| scancode);
+        if let Some(scancode) = scancode {
+            if !self.hotkeys.contains_key(&Chord::plain(scancode)) {
+                self.bindings.entry(scancode).or_insert(key);
+            }
+        }
+    }
+
+    /// Returns the hex key bound to the scancode, or `None` if the key isn't mapped.
+    pub fn key_for(&self, scancode: Scancode) -> Option<Key> {
+        self.bindings.get(&scancode).copied()
+    }
+
+    /// The hex keys bound to each keyboard key, by the name SDL gives the key. Frontends that
+    /// don't get scancodes from SDL use these to share the keymap.
+    pub fn key_names(&self) -> HashMap<String, Key> {
+        self.bindings
+            .iter()
+            .map(|(scancode, &key)| (scancode.name().to_string(), key))
+            .collect()
+    }
+
+    /// Returns the hex key bound to the controller input, or `None` if it isn't mapped.
+    pub fn key_for_controller(&self, input: ControllerInput) -> Option<Key> {
+        self.controller_bindings.get(&input).copied()
+    }
+
+    /// Returns the hotkey bound to the scancode while the modifier is held, or to the scancode on
+    /// its own if nothing is bound with the modifier. Keymap files can't bind a scancode to both
+    /// a hotkey and a hex key.
+    pub fn hotkey_for(&self, scancode: Scancode, modifier: Option<Modifier>) -> Option<Hotkey> {
+        modifier
+            .and_then(|modifier| self.hotkeys.get(&Chord::with(modifier, scancode)))
+            .or_else(|| self.hotkeys.get(&Chord::plain(scancode)))
+            .copied()
+    }
+
+    /// Axis values at or below this magnitude are treated as centred.
+    pub fn deadzone(&self) -> i16 {
+        self.deadzone
+    }
+
+    /// Replaces every keyboard binding for the hex key with the given scancodes.
+    pub fn rebind(&mut self, key: Key, scancodes: &[Scancode]) {
+        rebind(&mut self.bindings, key, scancodes);
+    }
+
+    /// Replaces every controller binding for the hex key with the given inputs.
+    pub fn rebind_controller(&mut self, key: Key, inputs: &[ControllerInput]) {
+        rebind(&mut self.controller_bindings, key, inputs);
+    }
+
+    /// Replaces every binding for the hotkey with the given chords.
+    pub fn rebind_hotkey(&mut self, hotkey: Hotkey, chords: &[Chord]) {
+        rebind(&mut self.hotkeys, hotkey, chords);
+    }
+
+    fn bind_all(&mut self, layout: &[(Scancode, u8)]) {
+        for &(scancode, key) in layout {
+            self.bindings.insert(scancode, Key(key));
+        }
+    }
+}
+
+fn rebind<I: Copy + Eq + Hash, T: Copy + Eq>(
+    bindings: &mut HashMap<I, T>,
+    target: T,
+    inputs: &[I],
+) {
+    bindings.retain(|_, bound| *bound != target);
+    for &input in inputs {
+        bindings.insert(input, target);
+    }
+}
+
+fn deadzone_from_fraction(fraction: f32) -> i16 {
+    (fraction.clamp(0.0, 1.0) * i16::MAX as f32) as i16
+}
+
+impl Default for Keymap {
+    fn default() -> Self {
+        Keymap::new(Layout::Cosmac)
+    }
+}
+
+/// A set of keymap settings as written in the keymap file.
+#[derive(Debug, Default, Deserialize)]
+struct KeymapSection {
+    layout: Option<Layout>,
+    /// Maps hex digits ("0" to "F") to the SDL names of the scancodes that should press them.
+    #[serde(default)]
+    keys: HashMap<String, Vec<String>>,
+    #[serde(default)]
+    controller: ControllerSection,
+    /// Maps hotkey names to the SDL names of the scancodes that trigger them, each optionally
+    /// after a modifier, as in `Shift+F12`.
+    #[serde(default)]
+    hotkeys: HashMap<String, Vec<String>>,
+}
+
+/// Game controller settings as written in the keymap file.
+#[derive(Debug, Default, Deserialize)]
+struct ControllerSection {
+    /// Fraction of each analog axis' range, from 0 to 1, that is ignored around its centre.
+    deadzone: Option<f32>,
+    /// Maps hex digits to controller buttons, or axis names followed by `+` or `-`.
+    #[serde(default)]
+    keys: HashMap<String, Vec<String>>,
+}
+
+/// The contents of a keymap file. Settings at the top level apply to every ROM, and sections under
+/// `roms` override them for the ROM with that SHA-1 digest or file name.
+///
+/// ```toml
+/// layout = "cosmac"
+///
+/// [keys]
+/// 5 = ["W", "Up"]
+///
+/// [controller]
+/// deadzone = 0.3
+/// keys = { 5 = ["a", "righttrigger"] }
+///
+/// [hotkeys]
+/// pause = ["P"]
+///
+/// [roms.PONG]
+/// layout = "split"
+/// ```
+#[derive(Debug, Default, Deserialize)]
+pub struct KeymapFile {
+    #[serde(flatten)]
+    default: KeymapSection,
+    #[serde(default)]
+    roms: HashMap<String, KeymapSection>,
+}
+
+impl KeymapFile {
+    /// Reads a keymap file from disk. A missing file is treated as an empty one so the default
+    /// layout is used.
+    pub fn load(path: &Path) -> Result<KeymapFile, KeymapError> {
+        match std::fs::read_to_string(path) {
+            Ok(contents) => toml::from_str(&contents).map_err(KeymapError::from),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(KeymapFile::default()),
+            Err(e) => Err(KeymapError::from(e)),
+        }
+    }
+
+    /// Location of the user's keymap file.
+    pub fn default_path() -> Option<PathBuf> {
+        Some(config_dir()?.join("keymap.toml"))
+    }
+
+    /// Builds the keymap for a ROM, applying its overrides on top of the global settings. The
+    /// default layout is used when the file doesn't choose one, and the COSMAC layout when there's
+    /// no default either.
+    pub fn keymap_for(
+        &self,
+        rom: &RomId,
+        default_layout: Option<Layout>,
+    ) -> Result<Keymap, KeymapError> {
+        // Sections keyed by digest take priority over ones keyed by name
+        let rom = self
+            .roms
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(&rom.sha1))
+            .map(|(_, section)| section)
+            .or_else(|| self.roms.get(&rom.name));
+        let layout = rom
+            .and_then(|section| section.layout)
+            .or(self.default.layout)
+            .or(default_layout)
+            .unwrap_or(Layout::Cosmac);
+
+        let mut keymap = Keymap::new(layout);
+        apply_section(&mut keymap, &self.default)?;
+        if let Some(section) = rom {
+            apply_section(&mut keymap, section)?;
+        }
+
+        // A hotkey would swallow the key presses before they reached the hex key
+        let mut conflicts: Vec<_> = keymap
+            .bindings
+            .keys()
+            .filter(|&&scancode| keymap.hotkeys.contains_key(&Chord::plain(scancode)))
+            .map(|scancode| scancode.name())
+            .collect();
+        conflicts.sort_unstable();
+        if let Some(name) = conflicts.first() {
+            return Err(KeymapError::HotkeyConflict(name.to_string()));
+        }
+
+        Ok(keymap)
+    }
+}
+
+fn apply_section(keymap: &mut Keymap, section: &KeymapSection) -> Result<(), KeymapError> {
+    // Entries are applied in whatever order the table happens to be in, so a key given to two
+    // bindings would go to either of them at random. That's rejected instead.
+    let keys = section
+        .keys
+        .iter()
+        .map(|(key_name, scancode_names)| {
+            Ok((
+                key_name,
+                parse_key(key_name)?,
+                parse_scancodes(scancode_names)?,
+            ))
+        })
+        .collect::<Result<Vec<_>, KeymapError>>()?;
+    check_unique(&keys, |scancode| scancode.name().to_string())?;
+
+    let hotkeys = section
+        .hotkeys
+        .iter()
+        .map(|(hotkey_name, scancode_names)| {
+            let hotkey = HOTKEYS
+                .iter()
+                .find(|(name, _)| name == hotkey_name)
+                .map(|&(_, hotkey)| hotkey)
+                .ok_or_else(|| KeymapError::UnknownHotkey(hotkey_name.clone()))?;
+            let chords = scancode_names
+                .iter()
+                .map(|name| {
+                    Chord::from_name(name).ok_or_else(|| KeymapError::UnknownScancode(name.clone()))
+                })
+                .collect::<Result<Vec<_>, _>>()?;
+            Ok((hotkey_name, hotkey, chords))
+        })
+        .collect::<Result<Vec<_>, KeymapError>>()?;
+    check_unique(&hotkeys, Chord::name)?;
+
+    let controller_keys = section
+        .controller
+        .keys
+        .iter()
+        .map(|(key_name, input_names)| {
+            let inputs = input_names
+                .iter()
+                .map(|name| {
+                    ControllerInput::from_name(name)
+                        .ok_or_else(|| KeymapError::UnknownControllerInput(name.clone()))
+                })
+                .collect::<Result<Vec<_>, _>>()?;
+            Ok((key_name, parse_key(key_name)?, inputs))
+        })
+        .collect::<Result<Vec<_>, KeymapError>>()?;
+    check_unique(&controller_keys, |input| format!("{:?}", input))?;
+
+    for (_, key, scancodes) in &keys {
+        keymap.rebind(*key, scancodes);
+    }
+    for (_, hotkey, chords) in &hotkeys {
+        keymap.rebind_hotkey(*hotkey, chords);
+    }
+    for (_, key, inputs) in &controller_keys {
+        keymap.rebind_controller(*key, inputs);
+    }
+
+    if let Some(deadzone) = section.controller.deadzone {
+        keymap.deadzone = deadzone_from_fraction(deadzone);
+    }
+
+    Ok(())
+}
+
+/// Makes sure no target is given twice, such as `a` and `A` for the same hex key, and that no input
+/// is bound to more than one target.
+fn check_unique<T: Copy + Eq, I: Copy + Eq + Hash>(
+    entries: &[(&String, T, Vec<I>)],
+    input_name: impl Fn(I) -> String,
+) -> Result<(), KeymapError> {
+    let mut targets = HashMap::new();
+    for (index, (name, target, inputs)) in entries.iter().enumerate() {
+        if entries[..index].iter().any(|(_, other, _)| other == target) {
+            return Err(KeymapError::DuplicateTarget(name.to_string()));
+        }
+        for &input in inputs {
+            match targets.insert(input, *target) {
+                Some(other) if other != *target => {
+                    return Err(KeymapError::DuplicateBinding(input_name(input)));
+                }
+                _ => {}
+            }
+        }
+    }
+    Ok(())
+}
+
+fn parse_scancodes(names: &[String]) -> Result<Vec<Scancode>, KeymapError> {
+    names
+        .iter()
+        .map(|name| {
+            Scancode::from_name(name).ok_or_else(|| KeymapError::UnknownScancode(name.clone()))
+        })
+        .collect()
+}
+
+fn parse_key(name: &str) -> Result<Key, KeymapError> {
+    // from_str_radix accepts a leading sign, which isn't part of a key's name
+    if !name.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err(KeymapError::InvalidKey(name.to_string()));
+    }
+
+    match u8::from_str_radix(name, 16) {
+        Ok(value) if value <= 0xF => Ok(Key(value)),
+        _ => Err(KeymapError::InvalidKey(name.to_string())),
+    }
+}
+
+/// Error that occurs while loading a keymap file.
+#[derive(Debug)]
+pub enum KeymapError {
+    /// The keymap file couldn't be read.
+    Io(std::io::Error),
+    /// The keymap file isn't valid TOML or doesn't match the expected structure.
+    Parse(toml::de::Error),
+    /// A binding was given for something other than a hex digit from 0 to F.
+    InvalidKey(String),
+    /// A binding names a key that SDL doesn't recognise.
+    UnknownScancode(String),
+    /// A binding names a controller button or axis that SDL doesn't recognise.
+    UnknownControllerInput(String),
+    /// A hotkey binding was given for something that isn't a hotkey.
+    UnknownHotkey(String),
+    /// A layout was named that isn't one of the built-in layouts.
+    UnknownLayout(String),
+    /// The same hex key or hotkey was given more than one entry in a section.
+    DuplicateTarget(String),
+    /// A key or controller input was bound to more than one thing in a section.
+    DuplicateBinding(String),
+    /// A key was bound to both a hotkey and a hex key.
+    HotkeyConflict(String),
+}
+
+impl From<std::io::Error> for KeymapError {
+    fn from(error: std::io::Error) -> Self {
+        KeymapError::Io(error)
+    }
+}
+
+impl From<toml::de::Error> for KeymapError {
+    fn from(error: toml::de::Error) -> Self {
+        KeymapError::Parse(error)
+    }
+}
+
+impl fmt::Display for KeymapError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            KeymapError::Io(error) => error.fmt(f),
+            KeymapError::Parse(error) => error.fmt(f),
+            KeymapError::InvalidKey(key) => write!(f, "not a hex key: {}", key),
+            KeymapError::UnknownScancode(name) => write!(f, "unknown key name: {}", name),
+            KeymapError::UnknownControllerInput(name) => {
+                write!(f, "unknown controller input: {}", name)
+            }
+            KeymapError::UnknownHotkey(name) => write!(f, "unknown hotkey: {}", name),
+            KeymapError::UnknownLayout(name) => write!(f, "unknown layout: {}", name),
+            KeymapError::DuplicateTarget(name) => write!(f, "{} is bound more than once", name),
+            KeymapError::DuplicateBinding(name) => {
+                write!(f, "{} is bound to more than one key", name)
+            }
+            KeymapError::HotkeyConflict(name) => write!(
+                f,
+                "{} is bound to both a hotkey and a hex key; bind the hotkey elsewhere or to []",
+                name
+            ),
+        }
+    }
+}
+
+impl Error for KeymapError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn keymap(file: &str, rom: &RomId) -> Result<Keymap, KeymapError> {
+        toml::from_str::<KeymapFile>(file)
+            .unwrap()
+            .keymap_for(rom, None)
+    }
+
+    fn rom() -> RomId {
+        RomId::new("PONG", &[0x12, 0x00])
+    }
+
+    #[test]
+    fn signed_keys_are_rejected() {
+        assert!(matches!(parse_key("+5"), Err(KeymapError::InvalidKey(_))));
+        assert!(matches!(parse_key("-1"), Err(KeymapError::InvalidKey(_))));
+        assert_eq!(parse_key("a").unwrap(), Key(0xA));
+        assert!(keymap("[keys]\n\"+5\" = [\"P\"]", &rom()).is_err());
+    }
+
+    #[test]
+    fn duplicate_bindings_are_rejected() {
+        let w = String::from("5");
+        let e = String::from("6");
+        let entries = [
+            (&w, Key(5), vec![Scancode::W]),
+            (&e, Key(6), vec![Scancode::E, Scancode::W]),
+        ];
+        assert!(matches!(
+            check_unique(&entries, |scancode| scancode.name().to_string()),
+            Err(KeymapError::DuplicateBinding(_))
+        ));
+
+        let result = keymap("[keys]\na = [\"P\"]\nA = [\"O\"]", &rom());
+        assert!(matches!(result, Err(KeymapError::DuplicateTarget(_))));
+    }
+
+    #[test]
+    fn digest_sections_win_over_names() {
+        let rom = rom();
+        let file = format!(
+            "[roms.PONG.keys]\n5 = [\"P\"]\n\n[roms.{}.keys]\n5 = [\"O\"]",
+            rom.sha1.to_uppercase()
+        );
+        let keymap = keymap(&file, &rom).unwrap();
+        assert_eq!(keymap.key_for(Scancode::O), Some(Key(5)));
+        assert_eq!(keymap.key_for(Scancode::P), None);
+    }
+
+    #[test]
+    fn hotkeys_cant_shadow_hex_keys() {
+        let result = keymap("[keys]\n5 = [\"F5\"]", &rom());
+        assert!(matches!(result, Err(KeymapError::HotkeyConflict(name)) if name == "F5"));
+
+        let keymap = keymap("[keys]\n5 = [\"F5\"]\n\n[hotkeys]\npause = []", &rom()).unwrap();
+        assert_eq!(keymap.key_for(Scancode::F5), Some(Key(5)));
+    }
+
+    #[test]
+    fn hotkeys_with_modifiers() {
+        let keymap = keymap("[hotkeys]\nscreenshot_pbm = [\"Ctrl+F12\"]", &rom()).unwrap();
+        let hotkey = |modifier| keymap.hotkey_for(Scancode::F12, modifier);
+        assert_eq!(hotkey(Some(Modifier::Ctrl)), Some(Hotkey::BitmapScreenshot));
+        // Without a binding of its own, Shift+F12 falls back to F12
+        assert_eq!(hotkey(Some(Modifier::Shift)), Some(Hotkey::Screenshot));
+        assert_eq!(hotkey(None), Some(Hotkey::Screenshot));
+        assert_eq!(
+            keymap.hotkey_for(Scancode::Return, Some(Modifier::Alt)),
+            Some(Hotkey::Fullscreen)
+        );
+        assert_eq!(keymap.hotkey_for(Scancode::Return, None), None);
+    }
+
+    #[test]
+    fn window_hotkeys_can_be_rebound() {
+        let keymap = keymap("[hotkeys]\nfullscreen = [\"Keypad +\"]", &rom()).unwrap();
+        assert_eq!(
+            keymap.hotkey_for(Scancode::KpPlus, None),
+            Some(Hotkey::Fullscreen)
+        );
+        assert_eq!(keymap.hotkey_for(Scancode::F11, None), None);
+    }
+}
@@ -0,0 +1,51 @@
+//! A CHIP-8 emulator. The emulation core (`machine`, `instruction`, `quirks`, `display`, `keyboard`,
+//! `audio`, `palette` and `persistence`) doesn't need the standard library, so it can run on
+//! microcontrollers when the default `std` feature is turned off.
+
+#![cfg_attr(not(feature = "std"), no_std)]
+
+#[cfg(feature = "std")]
+pub mod assembler;
+pub mod audio;
+#[cfg(feature = "std")]
+pub mod automation;
+#[cfg(feature = "std")]
+pub mod config;
+#[cfg(feature = "std")]
+pub mod database;
+#[cfg(feature = "std")]
+pub mod detect;
+pub mod display;
+#[cfg(feature = "std")]
+pub mod env;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+#[cfg(feature = "std")]
+pub mod filter;
+#[cfg(feature = "std")]
+pub mod font;
+#[cfg(feature = "std")]
+pub mod frontend;
+pub mod instruction;
+pub mod keyboard;
+#[cfg(feature = "sdl")]
+pub mod keymap;
+#[cfg(feature = "libretro")]
+pub mod libretro;
+pub mod machine;
+#[cfg(feature = "std")]
+pub mod osd;
+pub mod palette;
+pub mod persistence;
+#[cfg(feature = "std")]
+mod png;
+pub mod quirks;
+mod random;
+#[cfg(feature = "std")]
+pub mod recording;
+#[cfg(feature = "std")]
+pub mod replay;
+#[cfg(feature = "std")]
+pub mod screenshot;
+#[cfg(feature = "std")]
+pub mod sha1;
@@ -0,0 +1,419 @@
+//! An implementation of the libretro API, so chemu can be loaded as a core by RetroArch and other
+//! libretro frontends. Build it with `cargo build --release --no-default-features --features
+//! libretro` and load the resulting shared library.
+
+use crate::audio::Beeper;
+use crate::display::{HEIGHT, WIDTH};
+use crate::keyboard::Key;
+use crate::machine::{Machine, INSTRUCTIONS_PER_FRAME, STATE_SIZE};
+use crate::palette::{Palette, Rgb};
+use std::cell::RefCell;
+use std::ffi::c_void;
+use std::os::raw::{c_char, c_uint};
+
+pub const RETRO_API_VERSION: c_uint = 1;
+
+pub const RETRO_DEVICE_JOYPAD: c_uint = 1;
+pub const RETRO_DEVICE_KEYBOARD: c_uint = 3;
+
+pub const RETRO_DEVICE_ID_JOYPAD_B: c_uint = 0;
+pub const RETRO_DEVICE_ID_JOYPAD_Y: c_uint = 1;
+pub const RETRO_DEVICE_ID_JOYPAD_UP: c_uint = 4;
+pub const RETRO_DEVICE_ID_JOYPAD_DOWN: c_uint = 5;
+pub const RETRO_DEVICE_ID_JOYPAD_LEFT: c_uint = 6;
+pub const RETRO_DEVICE_ID_JOYPAD_RIGHT: c_uint = 7;
+pub const RETRO_DEVICE_ID_JOYPAD_A: c_uint = 8;
+pub const RETRO_DEVICE_ID_JOYPAD_X: c_uint = 9;
+
+pub const RETRO_ENVIRONMENT_SET_PIXEL_FORMAT: c_uint = 10;
+pub const RETRO_PIXEL_FORMAT_XRGB8888: c_uint = 1;
+
+pub const RETRO_REGION_NTSC: c_uint = 0;
+pub const RETRO_MEMORY_SYSTEM_RAM: c_uint = 2;
+
+const FRAMES_PER_SECOND: f64 = 60.0;
+const SAMPLE_RATE: u32 = 44100;
+const AUDIO_FRAMES_PER_FRAME: usize = SAMPLE_RATE as usize / FRAMES_PER_SECOND as usize;
+
+/// Joypad buttons and the hex keys they press, matching the default game controller layout.
+const JOYPAD_LAYOUT: [(c_uint, u8); 8] = [
+    (RETRO_DEVICE_ID_JOYPAD_UP, 0x2),
+    (RETRO_DEVICE_ID_JOYPAD_LEFT, 0x4),
+    (RETRO_DEVICE_ID_JOYPAD_RIGHT, 0x6),
+    (RETRO_DEVICE_ID_JOYPAD_DOWN, 0x8),
+    (RETRO_DEVICE_ID_JOYPAD_A, 0x5),
+    (RETRO_DEVICE_ID_JOYPAD_B, 0x0),
+    (RETRO_DEVICE_ID_JOYPAD_X, 0xE),
+    (RETRO_DEVICE_ID_JOYPAD_Y, 0xF),
+];
+
+/// The COSMAC VIP keypad layout on the keyboard. libretro key ids for letters and digits are their
+/// lowercase ASCII codes.
+const KEYBOARD_LAYOUT: [(u8, u8); 16] = [
+    (b'1', 0x1),
+    (b'2', 0x2),
+    (b'3', 0x3),
+    (b'4', 0xC),
+    (b'q', 0x4),
+    (b'w', 0x5),
+    (b'e', 0x6),
+    (b'r', 0xD),
+    (b'a', 0x7),
+    (b's', 0x8),
+    (b'd', 0x9),
+    (b'f', 0xE),
+    (b'z', 0xA),
+    (b'x', 0x0),
+    (b'c', 0xB),
+    (b'v', 0xF),
+];
+
+#[repr(C)]
+pub struct RetroSystemInfo {
+    pub library_name: *const c_char,
+    pub library_version: *const c_char,
+    pub valid_extensions: *const c_char,
+    pub need_fullpath: bool,
+    pub block_extract: bool,
+}
+
+#[repr(C)]
+pub struct RetroGameGeometry {
+    pub base_width: c_uint,
+    pub base_height: c_uint,
+    pub max_width: c_uint,
+    pub max_height: c_uint,
+    pub aspect_ratio: f32,
+}
+
+#[repr(C)]
+pub struct RetroSystemTiming {
+    pub fps: f64,
+    pub sample_rate: f64,
+}
+
+#[repr(C)]
+pub struct RetroSystemAvInfo {
+    pub geometry: RetroGameGeometry,
+    pub timing: RetroSystemTiming,
+}
+
+#[repr(C)]
+pub struct RetroGameInfo {
+    pub path: *const c_char,
+    pub data: *const c_void,
+    pub size: usize,
+    pub meta: *const c_char,
+}
+
+pub type RetroEnvironment = extern "C" fn(cmd: c_uint, data: *mut c_void) -> bool;
+pub type RetroVideoRefresh =
+    extern "C" fn(data: *const c_void, width: c_uint, height: c_uint, pitch: usize);
+pub type RetroAudioSample = extern "C" fn(left: i16, right: i16);
+pub type RetroAudioSampleBatch = extern "C" fn(data: *const i16, frames: usize) -> usize;
+pub type RetroInputPoll = extern "C" fn();
+pub type RetroInputState =
+    extern "C" fn(port: c_uint, device: c_uint, index: c_uint, id: c_uint) -> i16;
+
+/// The callbacks the frontend has handed to the core.
+#[derive(Copy, Clone, Default)]
+struct Callbacks {
+    environment: Option<RetroEnvironment>,
+    video_refresh: Option<RetroVideoRefresh>,
+    audio_sample_batch: Option<RetroAudioSampleBatch>,
+    input_poll: Option<RetroInputPoll>,
+    input_state: Option<RetroInputState>,
+}
+
+/// A loaded game.
+struct Core {
+    /// The ROM is kept around so the game can be reset.
+    rom: Vec<u8>,
+    machine: Machine,
+    beeper: Beeper,
+    palette: Palette,
+    frame: [u32; WIDTH * HEIGHT],
+}
+
+// libretro frontends call into the core from a single thread.
+thread_local! {
+    static CALLBACKS: RefCell<Callbacks> = RefCell::new(Callbacks::default());
+    static CORE: RefCell<Option<Core>> = const { RefCell::new(None) };
+}
+
+impl Core {
+    fn new(rom: Vec<u8>) -> Core {
+        Core {
+            machine: Machine::from_rom(&rom),
+            rom,
+            beeper: Beeper::new(SAMPLE_RATE),
+            palette: Palette::default(),
+            frame: [0; WIDTH * HEIGHT],
+        }
+    }
+
+    fn run_frame(&mut self, callbacks: &Callbacks) {
+        if let Some(input_state) = callbacks.input_state {
+            self.read_input(input_state);
+        }
+
+        self.machine.run_frame(INSTRUCTIONS_PER_FRAME);
+
+        // The frame is only redrawn when the display changes, but it's always handed to the
+        // frontend since not every frontend can repeat the previous frame by itself
+        if self.machine.display().update_pending() {
+            for (pixel, &lit) in self
+                .frame
+                .iter_mut()
+                .zip(self.machine.display().pixels().iter().flatten())
+            {
+                let Rgb(r, g, b) = self.palette.colour(lit);
+                *pixel = u32::from_be_bytes([0, r, g, b]);
+            }
+            self.machine.display_mut().mark_presented();
+        }
+
+        if let Some(video_refresh) = callbacks.video_refresh {
+            video_refresh(
+                self.frame.as_ptr() as *const c_void,
+                WIDTH as c_uint,
+                HEIGHT as c_uint,
+                WIDTH * std::mem::size_of::<u32>(),
+            );
+        }
+
+        if let Some(audio_sample_batch) = callbacks.audio_sample_batch {
+            let mut samples = [0; AUDIO_FRAMES_PER_FRAME * 2];
+            self.beeper
+                .fill(self.machine.sound_active(), 2, &mut samples);
+            audio_sample_batch(samples.as_ptr(), AUDIO_FRAMES_PER_FRAME);
+        }
+    }
+
+    fn read_input(&mut self, input_state: RetroInputState) {
+        let mut held = [false; 16];
+        for &(id, key) in JOYPAD_LAYOUT.iter() {
+            if input_state(0, RETRO_DEVICE_JOYPAD, 0, id) != 0 {
+                held[key as usize] = true;
+            }
+        }
+        for &(id, key) in KEYBOARD_LAYOUT.iter() {
+            if input_state(0, RETRO_DEVICE_KEYBOARD, 0, id as c_uint) != 0 {
+                held[key as usize] = true;
+            }
+        }
+
+        let keyboard = self.machine.keyboard_mut();
+        for (key, &held) in held.iter().enumerate() {
+            if held {
+                keyboard.press(Key(key as u8));
+            } else {
+                keyboard.release(Key(key as u8));
+            }
+        }
+    }
+}
+
+fn with_core<T>(default: T, f: impl FnOnce(&mut Core) -> T) -> T {
+    CORE.with(|core| core.borrow_mut().as_mut().map_or(default, f))
+}
+
+#[no_mangle]
+pub extern "C" fn retro_api_version() -> c_uint {
+    RETRO_API_VERSION
+}
+
+#[no_mangle]
+pub extern "C" fn retro_set_environment(environment: RetroEnvironment) {
+    CALLBACKS.with(|callbacks| callbacks.borrow_mut().environment = Some(environment));
+}
+
+#[no_mangle]
+pub extern "C" fn retro_set_video_refresh(video_refresh: RetroVideoRefresh) {
+    CALLBACKS.with(|callbacks| callbacks.borrow_mut().video_refresh = Some(video_refresh));
+}
+
+#[no_mangle]
+pub extern "C" fn retro_set_audio_sample(_audio_sample: RetroAudioSample) {
+    // Audio is always sent a frame at a time through the batch callback
+}
+
+#[no_mangle]
+pub extern "C" fn retro_set_audio_sample_batch(audio_sample_batch: RetroAudioSampleBatch) {
+    CALLBACKS
+        .with(|callbacks| callbacks.borrow_mut().audio_sample_batch = Some(audio_sample_batch));
+}
+
+#[no_mangle]
+pub extern "C" fn retro_set_input_poll(input_poll: RetroInputPoll) {
+    CALLBACKS.with(|callbacks| callbacks.borrow_mut().input_poll = Some(input_poll));
+}
+
+#[no_mangle]
+pub extern "C" fn retro_set_input_state(input_state: RetroInputState) {
+    CALLBACKS.with(|callbacks| callbacks.borrow_mut().input_state = Some(input_state));
+}
+
+#[no_mangle]
+pub extern "C" fn retro_init() {}
+
+#[no_mangle]
+pub extern "C" fn retro_deinit() {
+    CORE.with(|core| core.borrow_mut().take());
+}
+
+/// # Safety
+/// `info` must point to a `retro_system_info` struct.
+#[no_mangle]
+pub unsafe extern "C" fn retro_get_system_info(info: *mut RetroSystemInfo) {
+    *info = RetroSystemInfo {
+        library_name: b"Chemu\0".as_ptr() as *const c_char,
+        library_version: concat!(env!("CARGO_PKG_VERSION"), "\0").as_ptr() as *const c_char,
+        valid_extensions: b"ch8|c8|rom\0".as_ptr() as *const c_char,
+        need_fullpath: false,
+        block_extract: false,
+    };
+}
+
+/// # Safety
+/// `info` must point to a `retro_system_av_info` struct.
+#[no_mangle]
+pub unsafe extern "C" fn retro_get_system_av_info(info: *mut RetroSystemAvInfo) {
+    *info = RetroSystemAvInfo {
+        geometry: RetroGameGeometry {
+            base_width: WIDTH as c_uint,
+            base_height: HEIGHT as c_uint,
+            max_width: WIDTH as c_uint,
+            max_height: HEIGHT as c_uint,
+            aspect_ratio: WIDTH as f32 / HEIGHT as f32,
+        },
+        timing: RetroSystemTiming {
+            fps: FRAMES_PER_SECOND,
+            sample_rate: SAMPLE_RATE as f64,
+        },
+    };
+}
+
+#[no_mangle]
+pub extern "C" fn retro_set_controller_port_device(_port: c_uint, _device: c_uint) {}
+
+#[no_mangle]
+pub extern "C" fn retro_reset() {
+    with_core((), |core| core.machine = Machine::from_rom(&core.rom));
+}
+
+#[no_mangle]
+pub extern "C" fn retro_run() {
+    let callbacks = CALLBACKS.with(|callbacks| *callbacks.borrow());
+    if let Some(input_poll) = callbacks.input_poll {
+        input_poll();
+    }
+
+    with_core((), |core| core.run_frame(&callbacks));
+}
+
+#[no_mangle]
+pub extern "C" fn retro_serialize_size() -> usize {
+    STATE_SIZE
+}
+
+/// # Safety
+/// `data` must be null or point to at least `size` writable bytes.
+#[no_mangle]
+pub unsafe extern "C" fn retro_serialize(data: *mut c_void, size: usize) -> bool {
+    if data.is_null() {
+        return false;
+    }
+
+    with_core(false, |core| {
+        let state = core.machine.save_state();
+        if size < state.len() {
+            return false;
+        }
+
+        std::ptr::copy_nonoverlapping(state.as_ptr(), data as *mut u8, state.len());
+        true
+    })
+}
+
+/// # Safety
+/// `data` must be null or point to at least `size` readable bytes.
+#[no_mangle]
+pub unsafe extern "C" fn retro_unserialize(data: *const c_void, size: usize) -> bool {
+    if data.is_null() {
+        return false;
+    }
+
+    let state = std::slice::from_raw_parts(data as *const u8, size);
+    with_core(false, |core| core.machine.load_state(state).is_ok())
+}
+
+#[no_mangle]
+pub extern "C" fn retro_cheat_reset() {}
+
+#[no_mangle]
+pub extern "C" fn retro_cheat_set(_index: c_uint, _enabled: bool, _code: *const c_char) {}
+
+/// # Safety
+/// `game` must be null or point to a `retro_game_info` struct whose data points to `size` bytes.
+#[no_mangle]
+pub unsafe extern "C" fn retro_load_game(game: *const RetroGameInfo) -> bool {
+    if game.is_null() || (*game).data.is_null() {
+        return false;
+    }
+
+    let environment = match CALLBACKS.with(|callbacks| callbacks.borrow().environment) {
+        Some(environment) => environment,
+        None => return false,
+    };
+    let mut pixel_format = RETRO_PIXEL_FORMAT_XRGB8888;
+    if !environment(
+        RETRO_ENVIRONMENT_SET_PIXEL_FORMAT,
+        &mut pixel_format as *mut c_uint as *mut c_void,
+    ) {
+        return false;
+    }
+
+    let rom = std::slice::from_raw_parts((*game).data as *const u8, (*game).size).to_vec();
+    CORE.with(|core| *core.borrow_mut() = Some(Core::new(rom)));
+    true
+}
+
+#[no_mangle]
+pub extern "C" fn retro_load_game_special(
+    _game_type: c_uint,
+    _info: *const RetroGameInfo,
+    _num_info: usize,
+) -> bool {
+    false
+}
+
+#[no_mangle]
+pub extern "C" fn retro_unload_game() {
+    CORE.with(|core| core.borrow_mut().take());
+}
+
+#[no_mangle]
+pub extern "C" fn retro_get_region() -> c_uint {
+    RETRO_REGION_NTSC
+}
+
+#[no_mangle]
+pub extern "C" fn retro_get_memory_data(id: c_uint) -> *mut c_void {
+    if id != RETRO_MEMORY_SYSTEM_RAM {
+        return std::ptr::null_mut();
+    }
+
+    with_core(std::ptr::null_mut(), |core| {
+        core.machine.memory_mut().as_mut_ptr() as *mut c_void
+    })
+}
+
+#[no_mangle]
+pub extern "C" fn retro_get_memory_size(id: c_uint) -> usize {
+    if id != RETRO_MEMORY_SYSTEM_RAM {
+        return 0;
+    }
+
+    with_core(0, |core| core.machine.memory_mut().len())
+}
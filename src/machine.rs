@@ -1,14 +1,18 @@
-use crate::display::Display;
+use crate::display::{Display, HEIGHT, WIDTH};
 use crate::instruction::Instruction;
 use crate::keyboard::{Key, Keyboard};
-use rand::prelude::ThreadRng;
-use rand::Rng;
+use crate::quirks::Quirks;
+use crate::random::Random;
 
-use std::convert::{TryFrom, TryInto};
+use core::convert::{TryFrom, TryInto};
+use core::fmt;
+use core::fmt::Formatter;
+use core::ops::Range;
+#[cfg(feature = "std")]
 use std::error::Error;
-use std::fmt;
-use std::fmt::Formatter;
+#[cfg(feature = "std")]
 use std::fs::File;
+#[cfg(feature = "std")]
 use std::io::Read;
 
 const DIGITS: [u8; 80] = [
@@ -33,62 +37,209 @@ const DIGITS: [u8; 80] = [
 const PROGRAM_START: usize = 512;
 const MEMORY_SIZE: usize = 4096;
 const STACK_START: usize = DIGITS.len();
+/// The stack fills the memory between the digits and the program.
+const STACK_END: usize = PROGRAM_START;
 const ADDR_SIZE: usize = 2;
 const OPCODE_SIZE: usize = 2;
+/// I is 16 bits wide, so it can point past the end of memory but wraps at 0x10000.
+const ADDRESS_REGISTER_MASK: usize = 0xFFFF;
+
+/// Number of instructions run in each 60 Hz frame by frontends that step the machine a frame at a
+/// time, giving roughly 500 instructions per second.
+pub const INSTRUCTIONS_PER_FRAME: usize = 8;
 
 pub struct Machine {
-    registers: Vec<u8>,
+    registers: [u8; 16],
     address_register: usize,
     program_counter: usize,
     stack_pointer: usize,
     delay_timer: u8,
     sound_timer: u8,
-    memory: Vec<u8>,
-    random: ThreadRng,
+    memory: [u8; MEMORY_SIZE],
+    random: Random,
     display: Display,
     keyboard: Keyboard,
+    /// Set while an FX0A instruction is waiting for a key, holding the register the key goes into.
+    key_wait: Option<Register>,
+    instructions_executed: u64,
+    quirks: Quirks,
+    fault_policy: FaultPolicy,
+    /// Set when the machine has halted because of a fault.
+    fault: Option<Fault>,
+}
+
+/// Something a program did that the machine can't carry out. Each fault gives the address of the
+/// instruction that caused it.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Fault {
+    /// The instruction isn't one the machine knows, or calls a machine code routine.
+    InvalidInstruction { address: usize, opcode: u16 },
+    /// A subroutine was called with the stack already full.
+    StackOverflow { address: usize },
+    /// A subroutine returned with nothing on the stack.
+    StackUnderflow { address: usize },
+    /// The instruction reads or writes past the end of memory, or is itself past the end.
+    OutOfBounds { address: usize },
+}
+
+impl fmt::Display for Fault {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Fault::InvalidInstruction { address, opcode } => {
+                write!(f, "invalid instruction {:04X} at {:03X}", opcode, address)
+            }
+            Fault::StackOverflow { address } => write!(f, "stack overflow at {:03X}", address),
+            Fault::StackUnderflow { address } => {
+                write!(f, "return with an empty stack at {:03X}", address)
+            }
+            Fault::OutOfBounds { address } => {
+                write!(f, "memory access out of bounds at {:03X}", address)
+            }
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl Error for Fault {}
+
+/// What the machine does when a program faults.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum FaultPolicy {
+    /// Stops running instructions, leaving the fault to be reported with `Machine::fault`.
+    #[default]
+    Halt,
+    /// Skips the instruction and carries on, as many interpreters do.
+    Skip,
+    /// Panics with a description of the fault, for debugging.
+    Panic,
+}
+
+/// Names of the fault policies, as accepted by `FaultPolicy::parse`.
+pub const FAULT_POLICIES: [(&str, FaultPolicy); 3] = [
+    ("halt", FaultPolicy::Halt),
+    ("skip", FaultPolicy::Skip),
+    ("panic", FaultPolicy::Panic),
+];
+
+impl FaultPolicy {
+    pub fn parse(name: &str) -> Result<FaultPolicy, FaultPolicyError> {
+        FAULT_POLICIES
+            .iter()
+            .find(|(policy, _)| policy.eq_ignore_ascii_case(name))
+            .map(|&(_, policy)| policy)
+            .ok_or(FaultPolicyError)
+    }
 }
 
 impl Machine {
+    #[cfg(feature = "std")]
     pub fn from_file(file: &mut File) -> Result<Machine, std::io::Error> {
-        let sdl_context = sdl2::init().unwrap();
-        let event_pump = sdl_context.event_pump().unwrap();
-        let keyboard = Keyboard::new(event_pump);
+        let mut rom = Vec::new();
+        file.read_to_end(&mut rom)?;
+        Ok(Machine::from_rom(&rom))
+    }
 
-        let mut memory = vec![0; MEMORY_SIZE];
+    /// Creates a machine with the program loaded into memory. Anything past the end of memory is
+    /// ignored.
+    pub fn from_rom(rom: &[u8]) -> Machine {
+        let mut memory = [0; MEMORY_SIZE];
 
         // Copy program data into memory
-        if let Err(e) = file.read_exact(&mut memory[PROGRAM_START..]) {
-            if e.kind() != std::io::ErrorKind::UnexpectedEof {
-                return Err(e);
-            }
-        };
+        let length = rom.len().min(MEMORY_SIZE - PROGRAM_START);
+        memory[PROGRAM_START..PROGRAM_START + length].copy_from_slice(&rom[..length]);
 
         // Copy digit layouts into memory
         memory[0..DIGITS.len()].copy_from_slice(&DIGITS);
 
-        Ok(Machine {
-            registers: vec![0; 16],
+        Machine {
+            registers: [0; 16],
             address_register: 0,
             program_counter: PROGRAM_START,
             stack_pointer: STACK_START,
             delay_timer: 0,
             sound_timer: 0,
             memory,
-            random: rand::thread_rng(),
-            display: Display::new(sdl_context, 640, 320),
-            keyboard,
-        })
+            random: Random::from_entropy(),
+            display: Display::new(),
+            keyboard: Keyboard::new(),
+            key_wait: None,
+            instructions_executed: 0,
+            quirks: Quirks::default(),
+            fault_policy: FaultPolicy::default(),
+            fault: None,
+        }
     }
 
+    /// Executes the instruction at the program counter, unless the machine is waiting for a key or
+    /// has halted.
     pub fn exec_next(&mut self) {
-        let encoded = &self.memory[self.program_counter..self.program_counter + OPCODE_SIZE];
-        let instr =
-            crate::instruction::decode(u16::from_be_bytes(encoded.try_into().unwrap())).unwrap();
-        self.exec_instr(instr);
+        if self.fault.is_some() {
+            return;
+        }
+
+        if let Some(register) = self.key_wait {
+            // Like the VIP, FX0A completes when a key is released rather than when it's pressed.
+            // Until then the program counter stays on the instruction.
+            if let Some(Key(key)) = self.keyboard.take_released() {
+                self.registers[register as usize] = key;
+                self.key_wait = None;
+                self.program_counter += OPCODE_SIZE;
+            }
+            return;
+        }
+
+        let address = self.program_counter;
+        if let Err(fault) = self.step() {
+            match self.fault_policy {
+                FaultPolicy::Halt => self.fault = Some(fault),
+                // Addresses are 12 bits, so skipping off the end of memory wraps around
+                FaultPolicy::Skip => self.program_counter = (address + OPCODE_SIZE) % MEMORY_SIZE,
+                FaultPolicy::Panic => panic!("{}", fault),
+            }
+        }
+    }
+
+    fn step(&mut self) -> Result<(), Fault> {
+        self.instructions_executed += 1;
+        let address = self.program_counter;
+        let encoded = self
+            .memory
+            .get(address..address + OPCODE_SIZE)
+            .ok_or(Fault::OutOfBounds { address })?;
+        let opcode = u16::from_be_bytes(encoded.try_into().unwrap());
+        let invalid = Fault::InvalidInstruction { address, opcode };
+        match crate::instruction::decode(opcode) {
+            // There's no CPU to run machine code routines on
+            Ok(Instruction::Sys { .. }) | Err(_) => Err(invalid),
+            Ok(instr) => self.exec_instr(instr),
+        }
+    }
+
+    /// The range of memory an instruction accesses from the address register onwards.
+    fn memory_range(&self, length: usize) -> Result<Range<usize>, Fault> {
+        let range = self.address_register..self.address_register + length;
+        if range.end > MEMORY_SIZE {
+            return Err(Fault::OutOfBounds {
+                address: self.program_counter,
+            });
+        }
+        Ok(range)
+    }
+
+    /// Moves the address register on after FX55 or FX65 stores or loads `count` registers, if the
+    /// quirks say it should move.
+    fn advance_past_array(&mut self, count: usize) {
+        if self.quirks.load_store_increments_i {
+            let advance = if self.quirks.load_store_increments_by_x {
+                count - 1
+            } else {
+                count
+            };
+            self.address_register = (self.address_register + advance) & ADDRESS_REGISTER_MASK;
+        }
     }
 
-    fn exec_instr(&mut self, instr: Instruction) {
+    fn exec_instr(&mut self, instr: Instruction) -> Result<(), Fault> {
         // Instructions that don't alter control-flow go here
         match &instr {
             Instruction::LdImm { register, value } => self.registers[*register as usize] = *value,
@@ -101,12 +252,15 @@ impl Machine {
             }
             Instruction::Or { dest, src } => {
                 self.registers[*dest as usize] |= self.registers[*src as usize];
+                self.reset_flag_after_logic();
             }
             Instruction::And { dest, src } => {
                 self.registers[*dest as usize] &= self.registers[*src as usize];
+                self.reset_flag_after_logic();
             }
             Instruction::Xor { dest, src } => {
                 self.registers[*dest as usize] ^= self.registers[*src as usize];
+                self.reset_flag_after_logic();
             }
             Instruction::AddReg { dest, src } => {
                 let (result, overflow) =
@@ -121,7 +275,7 @@ impl Machine {
                 self.registers[Register::VF as usize] = if overflow { 1 } else { 0 };
             }
             Instruction::Shr { dest, src } => {
-                let value = self.registers[*src as usize];
+                let value = self.registers[self.shift_source(*dest, *src) as usize];
                 let bit = value & 0x1;
                 self.registers[*dest as usize] = value >> 1;
                 self.registers[Register::VF as usize] = bit;
@@ -133,7 +287,7 @@ impl Machine {
                 self.registers[Register::VF as usize] = if overflow { 1 } else { 0 };
             }
             Instruction::Shl { dest, src } => {
-                let value = self.registers[*src as usize];
+                let value = self.registers[self.shift_source(*dest, *src) as usize];
                 let bit = value & 0x80;
                 self.registers[*dest as usize] = value << 1;
                 self.registers[Register::VF as usize] = bit;
@@ -142,7 +296,7 @@ impl Machine {
                 self.address_register = *addr as usize;
             }
             Instruction::Rnd { register, mask } => {
-                let val: u8 = self.random.gen();
+                let val = self.random.next_u8();
                 self.registers[*register as usize] = val & *mask;
             }
             Instruction::ReadDelay { register } => {
@@ -155,34 +309,43 @@ impl Machine {
                 self.sound_timer = self.registers[*register as usize];
             }
             Instruction::AddAddr { register } => {
-                self.address_register += self.registers[*register as usize] as usize;
+                self.address_register = (self.address_register
+                    + self.registers[*register as usize] as usize)
+                    & ADDRESS_REGISTER_MASK;
             }
             Instruction::LdDigit { register } => {
-                self.address_register = (self.registers[*register as usize] * 5) as usize;
+                // Only the low digit picks a sprite
+                self.address_register = (self.registers[*register as usize] & 0xF) as usize * 5;
             }
             Instruction::LdBcd { register } => {
                 let value = self.registers[*register as usize];
-                self.memory[self.address_register] = value / 100;
-                self.memory[self.address_register + 1] = (value / 10) % 10;
-                self.memory[self.address_register + 2] = (value % 100) % 10;
+                let range = self.memory_range(3)?;
+                self.memory[range].copy_from_slice(&[value / 100, (value / 10) % 10, value % 10]);
             }
             Instruction::StrArray { end } => {
-                for i in 0..=*end as usize {
-                    self.memory[self.address_register + i] = self.registers[i];
-                }
+                let count = *end as usize + 1;
+                let range = self.memory_range(count)?;
+                self.memory[range].copy_from_slice(&self.registers[..count]);
+                self.advance_past_array(count);
             }
             Instruction::LdArray { end } => {
-                for i in 0..=*end as usize {
-                    self.registers[i] = self.memory[self.address_register + i];
-                }
+                let count = *end as usize + 1;
+                let range = self.memory_range(count)?;
+                self.registers[..count].copy_from_slice(&self.memory[range]);
+                self.advance_past_array(count);
             }
             Instruction::Clr => self.display.clear(),
             Instruction::Drw { x, y, length } => {
-                let overwritten = self.display.draw(
+                let (x, y) = (
                     self.registers[*x as usize] as usize,
                     self.registers[*y as usize] as usize,
-                    &self.memory[self.address_register..self.address_register + *length as usize],
                 );
+                let sprite = &self.memory[self.memory_range(*length as usize)?];
+                let overwritten = if self.quirks.clip_sprites {
+                    self.display.draw_clipped(x, y, sprite)
+                } else {
+                    self.display.draw(x, y, sprite)
+                };
 
                 if overwritten {
                     self.registers[Register::VF as usize] = 1;
@@ -190,10 +353,11 @@ impl Machine {
                     self.registers[Register::VF as usize] = 0;
                 }
             }
-            Instruction::LdKey { register } => loop {
-                let Key(key) = self.keyboard.next_key();
-                self.registers[*register as usize] = key;
-            },
+            Instruction::LdKey { register } => {
+                // Only count keys released from now on
+                self.keyboard.take_released();
+                self.key_wait = Some(*register);
+            }
             Instruction::Ret => {}
             Instruction::Jmp { .. } => {}
             Instruction::Call { .. } => {}
@@ -204,13 +368,18 @@ impl Machine {
             Instruction::JmpOff { .. } => {}
             Instruction::Skp { .. } => {}
             Instruction::SkpNeg { .. } => {}
-            _ => unimplemented!(),
+            Instruction::Sys { .. } => {}
         }
 
         // Instructions that modify the program counter go here
         match instr {
             Instruction::Jmp { addr } => self.program_counter = addr as usize,
             Instruction::Call { addr } => {
+                if self.stack_pointer + ADDR_SIZE > STACK_END {
+                    return Err(Fault::StackOverflow {
+                        address: self.program_counter,
+                    });
+                }
                 let ret_addr = (self.program_counter + OPCODE_SIZE) as u16;
                 self.memory[self.stack_pointer..self.stack_pointer + ADDR_SIZE]
                     .copy_from_slice(&ret_addr.to_be_bytes());
@@ -218,6 +387,11 @@ impl Machine {
                 self.program_counter = addr as usize;
             }
             Instruction::Ret => {
+                if self.stack_pointer < STACK_START + ADDR_SIZE {
+                    return Err(Fault::StackUnderflow {
+                        address: self.program_counter,
+                    });
+                }
                 self.stack_pointer -= ADDR_SIZE;
                 self.program_counter = self.read_address(self.stack_pointer) as usize;
             }
@@ -250,8 +424,12 @@ impl Machine {
                 }
             }
             Instruction::JmpOff { base_addr } => {
-                self.program_counter =
-                    (base_addr + self.registers[Register::V0 as usize] as u16) as usize;
+                let register = if self.quirks.jump_uses_vx {
+                    (base_addr >> 8) as usize & 0xF
+                } else {
+                    Register::V0 as usize
+                };
+                self.program_counter = base_addr as usize + self.registers[register] as usize;
             }
             Instruction::Skp { keycode } => {
                 if self
@@ -263,6 +441,7 @@ impl Machine {
                     self.program_counter += OPCODE_SIZE;
                 }
             }
+            Instruction::LdKey { .. } => {}
             Instruction::SkpNeg { keycode } => {
                 if !self
                     .keyboard
@@ -275,6 +454,23 @@ impl Machine {
             }
             _ => self.program_counter += OPCODE_SIZE,
         }
+
+        Ok(())
+    }
+
+    fn reset_flag_after_logic(&mut self) {
+        if self.quirks.logic_resets_vf {
+            self.registers[Register::VF as usize] = 0;
+        }
+    }
+
+    /// The register a shift instruction reads from.
+    fn shift_source(&self, dest: Register, src: Register) -> Register {
+        if self.quirks.shift_reads_vy {
+            src
+        } else {
+            dest
+        }
     }
 
     fn read_address(&self, address: usize) -> u16 {
@@ -285,6 +481,21 @@ impl Machine {
         )
     }
 
+    /// Runs one 60 Hz frame: executes the given number of instructions, then ticks the timers.
+    pub fn run_frame(&mut self, instructions: usize) {
+        for _ in 0..instructions {
+            self.exec_next();
+        }
+
+        self.decrement_timers();
+    }
+
+    /// Restarts the random number generator from a seed, so that runs given the same input
+    /// behave identically.
+    pub fn seed_random(&mut self, seed: u64) {
+        self.random = Random::new(seed);
+    }
+
     pub fn decrement_timers(&mut self) {
         if self.delay_timer > 0 {
             self.delay_timer -= 1;
@@ -295,15 +506,234 @@ impl Machine {
         }
     }
 
-    pub fn process_key_events(&mut self) {
-        self.keyboard.process_events();
+    /// Whether the machine is making a sound. The buzzer sounds for as long as the sound timer is
+    /// counting down.
+    pub fn sound_active(&self) -> bool {
+        self.sound_timer > 0
+    }
+
+    pub fn sound_timer(&self) -> u8 {
+        self.sound_timer
+    }
+
+    pub fn keyboard(&self) -> &Keyboard {
+        &self.keyboard
+    }
+
+    pub fn keyboard_mut(&mut self) -> &mut Keyboard {
+        &mut self.keyboard
+    }
+
+    pub fn display(&self) -> &Display {
+        &self.display
     }
 
-    pub fn update_display(&mut self) {
-        self.display.update();
+    pub fn display_mut(&mut self) -> &mut Display {
+        &mut self.display
+    }
+
+    pub fn memory(&self) -> &[u8] {
+        &self.memory
+    }
+
+    pub fn memory_mut(&mut self) -> &mut [u8] {
+        &mut self.memory
+    }
+
+    pub fn registers(&self) -> &[u8] {
+        &self.registers
+    }
+
+    pub fn registers_mut(&mut self) -> &mut [u8] {
+        &mut self.registers
+    }
+
+    pub fn address_register(&self) -> usize {
+        self.address_register
+    }
+
+    /// Points the address register somewhere in memory. Addresses past the end of memory wrap
+    /// around.
+    pub fn set_address_register(&mut self, address: usize) {
+        self.address_register = address % MEMORY_SIZE;
+    }
+
+    pub fn program_counter(&self) -> usize {
+        self.program_counter
+    }
+
+    /// Jumps to an address in memory. Addresses past the end of memory wrap around.
+    pub fn set_program_counter(&mut self, address: usize) {
+        self.program_counter = address % MEMORY_SIZE;
+    }
+
+    /// Total number of instructions executed since the machine started. Time spent waiting for a
+    /// key isn't counted.
+    pub fn instructions_executed(&self) -> u64 {
+        self.instructions_executed
+    }
+
+    pub fn quirks(&self) -> Quirks {
+        self.quirks
+    }
+
+    pub fn set_quirks(&mut self, quirks: Quirks) {
+        self.quirks = quirks;
+    }
+
+    pub fn set_fault_policy(&mut self, policy: FaultPolicy) {
+        self.fault_policy = policy;
+    }
+
+    /// The fault that halted the machine, if it has halted.
+    pub fn fault(&self) -> Option<Fault> {
+        self.fault
     }
 }
 
+const STATE_MAGIC: &[u8; 4] = b"C8ST";
+const STATE_VERSION: u8 = 1;
+/// Size of a saved state: the header, registers, address register, program counter, stack
+/// pointer, timers, FX0A state, memory and one bit per display pixel.
+pub const STATE_SIZE: usize =
+    STATE_MAGIC.len() + 1 + 16 + 2 + 2 + 2 + 1 + 1 + 1 + MEMORY_SIZE + WIDTH * HEIGHT / 8;
+
+impl Machine {
+    /// Captures everything needed to resume the machine later. The random number generator's state
+    /// isn't included.
+    pub fn save_state(&self) -> [u8; STATE_SIZE] {
+        let mut state = [0; STATE_SIZE];
+        let mut rest = &mut state[..];
+        let mut put = |bytes: &[u8]| {
+            let (taken, remaining) = core::mem::take(&mut rest).split_at_mut(bytes.len());
+            taken.copy_from_slice(bytes);
+            rest = remaining;
+        };
+
+        put(STATE_MAGIC);
+        put(&[STATE_VERSION]);
+        put(&self.registers);
+        put(&(self.address_register as u16).to_be_bytes());
+        put(&(self.program_counter as u16).to_be_bytes());
+        put(&(self.stack_pointer as u16).to_be_bytes());
+        put(&[self.delay_timer, self.sound_timer]);
+        // 0xFF marks that no FX0A instruction is waiting
+        put(&[self.key_wait.map_or(0xFF, |register| register as u8)]);
+        put(&self.memory);
+
+        for row in self.display.pixels().iter() {
+            for byte in row.chunks(8) {
+                let packed = byte
+                    .iter()
+                    .fold(0, |packed, &pixel| (packed << 1) | pixel as u8);
+                put(&[packed]);
+            }
+        }
+
+        state
+    }
+
+    /// Restores a state captured by `save_state`. The machine is left untouched if the state isn't
+    /// valid.
+    pub fn load_state(&mut self, state: &[u8]) -> Result<(), StateError> {
+        if state.len() != STATE_SIZE || &state[0..STATE_MAGIC.len()] != STATE_MAGIC {
+            return Err(StateError::Invalid);
+        }
+
+        let mut state = &state[STATE_MAGIC.len()..];
+        let mut take = |length: usize| {
+            let (taken, rest) = state.split_at(length);
+            state = rest;
+            taken
+        };
+        let read_u16 = |bytes: &[u8]| u16::from_be_bytes(bytes.try_into().unwrap()) as usize;
+
+        if take(1)[0] != STATE_VERSION {
+            return Err(StateError::UnsupportedVersion);
+        }
+
+        let registers = take(16).try_into().unwrap();
+        let address_register = read_u16(take(2));
+        let program_counter = read_u16(take(2));
+        let stack_pointer = read_u16(take(2));
+        let timers = take(2);
+        let key_wait = match take(1)[0] {
+            0xFF => None,
+            register => Some(Register::try_from(register as u16).map_err(|_| StateError::Invalid)?),
+        };
+
+        // I can legitimately point past the end of memory, as long as nothing reads through it
+        if program_counter > MEMORY_SIZE - OPCODE_SIZE
+            || !(STACK_START..=PROGRAM_START).contains(&stack_pointer)
+        {
+            return Err(StateError::Invalid);
+        }
+
+        self.registers = registers;
+        self.address_register = address_register;
+        self.program_counter = program_counter;
+        self.stack_pointer = stack_pointer;
+        self.delay_timer = timers[0];
+        self.sound_timer = timers[1];
+        self.key_wait = key_wait;
+        self.memory.copy_from_slice(take(MEMORY_SIZE));
+
+        let mut pixels = [[false; WIDTH]; HEIGHT];
+        for (row, packed) in pixels
+            .iter_mut()
+            .zip(take(WIDTH * HEIGHT / 8).chunks(WIDTH / 8))
+        {
+            for (i, pixel) in row.iter_mut().enumerate() {
+                *pixel = packed[i / 8] & (0x80 >> (i % 8)) != 0;
+            }
+        }
+        self.display.set_pixels(pixels);
+        // A fault is cleared along with whatever caused it
+        self.fault = None;
+
+        Ok(())
+    }
+}
+
+/// Error that occurs when a fault policy name isn't recognised.
+#[derive(Debug)]
+pub struct FaultPolicyError;
+
+impl fmt::Display for FaultPolicyError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "unknown fault policy, expected one of")?;
+        for (i, (name, _)) in FAULT_POLICIES.iter().enumerate() {
+            let separator = if i == 0 { " " } else { ", " };
+            write!(f, "{}{}", separator, name)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(feature = "std")]
+impl Error for FaultPolicyError {}
+
+/// Error that occurs while restoring a saved state.
+#[derive(Debug)]
+pub enum StateError {
+    /// The data isn't a saved state, or is corrupted.
+    Invalid,
+    /// The state was saved by a version of chemu that uses a different format.
+    UnsupportedVersion,
+}
+
+impl fmt::Display for StateError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            StateError::Invalid => write!(f, "not a valid saved state"),
+            StateError::UnsupportedVersion => write!(f, "unsupported saved state version"),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl Error for StateError {}
+
 /// Represents all the registers directly available to programs in the Chip-8 architecture. Each
 /// stores a byte of information.
 #[derive(Copy, Clone, Debug)]
@@ -328,11 +758,18 @@ pub enum Register {
     VF,
 }
 
+impl fmt::Display for Register {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "V{:X}", *self as u8)
+    }
+}
+
 #[derive(Debug)]
 pub struct RegisterParseError {
     value: u16,
 }
 
+#[cfg(feature = "std")]
 impl Error for RegisterParseError {}
 
 impl fmt::Display for RegisterParseError {
@@ -366,3 +803,39 @@ impl TryFrom<u16> for Register {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn state_round_trips_with_i_past_memory() {
+        // LD I,#FFF; LD V0,2; ADD I,V0
+        let mut machine = Machine::from_rom(&[0xAF, 0xFF, 0x60, 0x02, 0xF0, 0x1E]);
+        for _ in 0..3 {
+            machine.exec_next();
+        }
+        assert_eq!(machine.address_register(), 0x1001);
+
+        let state = machine.save_state();
+        let mut restored = Machine::from_rom(&[]);
+        restored.load_state(&state).unwrap();
+        assert_eq!(restored.address_register(), 0x1001);
+        assert_eq!(restored.save_state()[..], state[..]);
+    }
+
+    #[test]
+    fn i_wraps_at_16_bits() {
+        // LD I,#FFF; LD V0,#FF; ADD I,V0, repeated until I passes 0xFFFF
+        let mut rom = vec![0xAF, 0xFF, 0x60, 0xFF];
+        for _ in 0..252 {
+            rom.extend_from_slice(&[0xF0, 0x1E]);
+        }
+        let mut machine = Machine::from_rom(&rom);
+        for _ in 0..254 {
+            machine.exec_next();
+        }
+        assert_eq!(machine.address_register(), (0xFFF + 252 * 0xFF) & 0xFFFF);
+        assert!(machine.load_state(&machine.save_state()).is_ok());
+    }
+}
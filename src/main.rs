@@ -1,63 +1,1190 @@
-use crate::machine::Machine;
-use std::fs::File;
+use chemu::assembler;
+use chemu::audio::DEFAULT_TONE;
+use chemu::automation::Session;
+use chemu::config::{config_dir, ConfigError, ConfigFile, RomId};
+use chemu::database::{Database, DatabaseError};
+use chemu::detect;
+use chemu::detect::{Confidence, Detection};
+use chemu::display::{HEIGHT, WIDTH};
+use chemu::filter::{FilterChain, Image};
+use chemu::frontend::sdl::{KeypadMode, Scaling, SdlFrontend};
+use chemu::frontend::vnc::VncFrontend;
+use chemu::frontend::web::WebFrontend;
+use chemu::frontend::{Command, Frontend, TerminalGraphics};
+use chemu::instruction;
+use chemu::keyboard::Key;
+use chemu::keymap::{Keymap, KeymapError, KeymapFile, Layout};
+use chemu::machine::{FaultPolicy, Machine, INSTRUCTIONS_PER_FRAME};
+use chemu::palette::Palette;
+use chemu::persistence::Persistence;
+use chemu::quirks::Quirks;
+use chemu::recording;
+use chemu::recording::Recorder;
+use chemu::replay::Replay;
+use chemu::screenshot;
+use chemu::screenshot::Format;
+use std::fmt::Display;
+use std::io::{BufWriter, Write};
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
 use std::time::{Duration, Instant};
 
-mod display;
-mod instruction;
-mod keyboard;
-mod machine;
+/// Size in terminal pixels of each display pixel when the terminal frontend draws bitmaps.
+const TERMINAL_SCALE: usize = 8;
+/// VNC is served on the loopback interface unless another address is given. Viewers connecting
+/// to the next port up are view-only.
+const DEFAULT_VNC_ADDRESS: ([u8; 4], u16) = ([127, 0, 0, 1], 5900);
+/// Size in pixels of each display pixel sent to VNC viewers.
+const VNC_SCALE: usize = 10;
+/// `chemu serve` listens on every interface unless told otherwise, so anyone on the local network
+/// can watch or play.
+const DEFAULT_SERVE_ADDRESS: ([u8; 4], u16) = ([0, 0, 0, 0], 8080);
+/// Size in pixels of each display pixel in screenshots taken from the command line, before any
+/// filters. Matches the size of the window.
+const SCREENSHOT_SCALE: usize = 10;
 
-fn main() {
-    ctrlc::set_handler(move || {
-        std::process::exit(1);
-    })
-    .unwrap();
+/// Speeds the machine can run at, as multiples of normal speed.
+const SPEEDS: [f64; 6] = [0.25, 0.5, 1.0, 2.0, 4.0, 8.0];
+const NORMAL_SPEED: usize = 2;
+/// How many times faster than the chosen speed fast-forward runs.
+const FAST_FORWARD_SPEED: u32 = 4;
+/// The most real time the machine catches up on at once, so it doesn't race after a stall.
+const MAX_CATCH_UP: Duration = Duration::from_millis(100);
+/// Size in window pixels of each display pixel unless `--scale=N` is given.
+const DEFAULT_WINDOW_SCALE: usize = 10;
+/// Instructions traced unless `--steps` or `--frames` is given.
+const DEFAULT_TRACE_STEPS: u64 = 1000;
+/// Frames benchmarked unless `--frames` is given, which is 100 seconds of emulated time.
+const DEFAULT_BENCH_FRAMES: u64 = 6000;
 
-    let mut args = std::env::args();
+/// Exit status for errors while running, including programs that halt on a fault.
+const EXIT_FAILURE: u8 = 1;
+/// Exit status for invalid command lines.
+const EXIT_USAGE: u8 = 2;
+
+const HELP: &str = "\
+Usage: chemu [run] [OPTIONS] ROM
+       chemu serve [OPTIONS] ROM
+       chemu rpc [--socket=PATH]
+       chemu disasm ROM
+       chemu detect ROM
+       chemu asm SOURCE [-o OUTPUT]
+       chemu trace [OPTIONS] ROM
+       chemu bench [OPTIONS] ROM
+
+Commands:
+  run      Play a ROM in a window, the terminal or over VNC (the default)
+  serve    Serve a ROM to web browsers
+  rpc      Drive machines with JSON-RPC requests from stdin or a Unix socket
+  disasm   Print a ROM as assembly that assembles back into the same bytes
+  detect   Guess which platform a ROM was written for from its code
+  asm      Assemble a program, saving it next to the source as .ch8 by default
+  trace    Print each instruction and the registers as the ROM runs
+  bench    Run a ROM as fast as possible and report how fast it ran
+
+Machine options (run, serve, trace and bench):
+  --ipf=N                  Instructions per 60 Hz frame [default: 8]
+  --quirks=SPEC            Behave like another interpreter: chemu, vip, schip or xochip,
+                           optionally followed by +QUIRK or -QUIRK to turn single quirks on
+                           or off (shift, loadstore, loadstorex, jump, vfreset, clip), such
+                           as vip,-clip
+  --seed=N                 Seed the random number generator [default: 0 when headless]
+  --fault-policy=POLICY    What to do when the program faults: halt, skip or panic
+                           [default: halt]
+
+Run options:
+  --config=PATH            Read settings from this config file instead of the user's
+  --speed=X                Start at X times normal speed: 0.25, 0.5, 1, 2, 4 or 8 [default: 1]
+  --volume=V               Buzzer volume from 0 to 1 [default: 1]
+  --mute                   Silence the buzzer
+  --no-database            Don't look the ROM up in the ROM database
+  --no-detect              Don't guess the platform of ROMs that aren't in the database
+  --scale=N                Open the window N times the display's size [default: 10]
+  --scale=MODE             Fit the display to the window: fit, integer or stretch [default: fit]
+  --fullscreen             Start in fullscreen
+  --tui[=GRAPHICS]         Play in the terminal: blocks, sixel or kitty [default: blocks]
+  --vnc[=[ADDRESS:]PORT]   Serve to VNC viewers [default: 127.0.0.1:5900]
+  --palette=PALETTE        A theme name or two to four colours, such as #000000,#33FF33
+  --persistence=MODE       Smooth flicker over: max, blend[=FRAMES], decay[=MS] or off
+  --filter=LIST            Upscaling filters: scale2x, scale3x, smooth and crt
+  --osd                    Show the status line and keypad over the game
+  --keypad[=MODE]          Add a clickable keypad: momentary or latched [default: momentary]
+  --screenshot-at N        Take a screenshot at frame N; can be repeated
+  --screenshot-format=FMT  png or pbm [default: png]
+  --record-format=FMT      Recording format for F9: gif or apng [default: gif]
+  --headless               Run without a window as fast as possible
+  --frames=N               Frames to run headlessly
+  --record=PATH            Record a headless run to a .gif, .png or .apng file
+  --replay=PATH            Press keys from a replay file during a headless run
+
+Serve options:
+  --listen=[ADDRESS:]PORT  Where to listen [default: 0.0.0.0:8080]
+
+Trace options:
+  --steps=N                Instructions to trace [default: 1000]
+  --frames=N               Frames to trace instead
+
+Bench options:
+  --frames=N               Frames to run [default: 6000]
+
+Exit status is 0 on success, 1 on errors or when the program halts on a fault, and 2 for
+invalid command lines.
+";
+
+/// Where the machine is shown and played.
+enum FrontendKind {
+    Sdl,
+    Terminal(TerminalGraphics),
+    Vnc(SocketAddr),
+    Web(SocketAddr),
+}
+
+/// Options for setting up the machine, which are shared by every command that runs one.
+struct MachineOptions {
+    instructions_per_frame: Option<usize>,
+    quirks: Option<Quirks>,
+    seed: Option<u64>,
+    fault_policy: FaultPolicy,
+}
+
+impl MachineOptions {
+    fn new() -> MachineOptions {
+        MachineOptions {
+            instructions_per_frame: None,
+            quirks: None,
+            seed: None,
+            fault_policy: FaultPolicy::default(),
+        }
+    }
+
+    /// Applies an argument if it's a machine option. Returns whether it was one, or a description
+    /// of the problem if its value is invalid.
+    fn parse_arg(&mut self, arg: &str) -> Result<bool, String> {
+        if let Some(count) = arg.strip_prefix("--ipf=") {
+            match count.parse::<usize>() {
+                Ok(count) if count > 0 => self.instructions_per_frame = Some(count),
+                _ => return Err(format!("Invalid instructions per frame: {}", count)),
+            }
+        } else if let Some(spec) = arg.strip_prefix("--quirks=") {
+            let quirks = Quirks::parse(spec).map_err(|e| format!("Invalid quirks: {}", e))?;
+            self.quirks = Some(quirks);
+        } else if let Some(seed) = arg.strip_prefix("--seed=") {
+            let seed = seed
+                .parse()
+                .map_err(|_| format!("Invalid seed: {}", seed))?;
+            self.seed = Some(seed);
+        } else if let Some(policy) = arg.strip_prefix("--fault-policy=") {
+            self.fault_policy =
+                FaultPolicy::parse(policy).map_err(|e| format!("Invalid fault policy: {}", e))?;
+        } else {
+            return Ok(false);
+        }
+        Ok(true)
+    }
+
+    fn instructions_per_frame(&self) -> usize {
+        self.instructions_per_frame
+            .unwrap_or(INSTRUCTIONS_PER_FRAME)
+    }
+
+    fn quirks(&self) -> Quirks {
+        self.quirks.unwrap_or_default()
+    }
+
+    fn machine(&self, rom: &[u8]) -> Machine {
+        let mut machine = Machine::from_rom(rom);
+        machine.set_quirks(self.quirks());
+        machine.set_fault_policy(self.fault_policy);
+        if let Some(seed) = self.seed {
+            machine.seed_random(seed);
+        }
+        machine
+    }
+}
+
+fn main() -> ExitCode {
+    let mut args = std::env::args().peekable();
     args.next().unwrap(); // Skip first argument (executable name)
 
-    let file_path = match args.next() {
-        Some(path) => path,
-        None => {
-            eprintln!("No CHIP-8 program passed in");
-            return;
+    let command = args.peek().cloned();
+    match command.as_deref() {
+        Some("help") | Some("--help") | Some("-h") => {
+            print!("{}", HELP);
+            return ExitCode::SUCCESS;
+        }
+        Some("--version") | Some("-V") => {
+            println!("chemu {}", env!("CARGO_PKG_VERSION"));
+            return ExitCode::SUCCESS;
+        }
+        Some("run") | Some("serve") | Some("rpc") | Some("disasm") | Some("detect")
+        | Some("asm") | Some("trace") | Some("bench") => {
+            args.next();
+        }
+        _ => {}
+    }
+
+    let args: Vec<String> = args.collect();
+    if args.iter().any(|arg| arg == "--help" || arg == "-h") {
+        print!("{}", HELP);
+        return ExitCode::SUCCESS;
+    }
+
+    match command.as_deref() {
+        Some("serve") => run(args, true),
+        Some("rpc") => run_rpc(args),
+        Some("disasm") => run_disasm(args),
+        Some("detect") => run_detect(args),
+        Some("asm") => run_asm(args),
+        Some("trace") => run_trace(args),
+        Some("bench") => run_bench(args),
+        _ => run(args, false),
+    }
+}
+
+/// Prints a problem with the command line and returns the exit status for it.
+fn usage_error(message: &str) -> ExitCode {
+    eprintln!("{}", message);
+    eprintln!("Run `chemu --help` for usage");
+    ExitCode::from(EXIT_USAGE)
+}
+
+/// Prints an error along with its cause and returns the exit status for it.
+fn failure(message: &str, cause: &dyn Display) -> ExitCode {
+    eprintln!("{}", message);
+    eprintln!("Cause: {}", cause);
+    ExitCode::from(EXIT_FAILURE)
+}
+
+/// Plays a ROM, or serves it to web browsers when `serve` is set.
+fn run(args: Vec<String>, serve: bool) -> ExitCode {
+    let mut args = args.into_iter();
+    let mut frontend_kind = FrontendKind::Sdl;
+    let mut serve_address = SocketAddr::from(DEFAULT_SERVE_ADDRESS);
+    let mut options = MachineOptions::new();
+    let mut config_path = None;
+    let mut scaling = Scaling::Fit;
+    let mut window_scale = None;
+    let mut speed = None;
+    let mut volume = None;
+    let mut use_database = true;
+    let mut use_detection = true;
+    let mut fullscreen = false;
+    let mut osd = false;
+    let mut keypad_panel = None;
+    let mut palette = None;
+    let mut persistence = None;
+    let mut filters = None;
+    let mut screenshot_frames = Vec::new();
+    let mut screenshot_format = Format::Png;
+    let mut headless = false;
+    let mut frames = None;
+    let mut recording_path = None;
+    let mut recording_format = recording::Format::Gif;
+    let mut replay_path = None;
+    let mut file_path = None;
+    while let Some(arg) = args.next() {
+        match options.parse_arg(&arg) {
+            Ok(true) => continue,
+            Ok(false) => {}
+            Err(message) => return usage_error(&message),
         }
+        match arg.as_str() {
+            "--tui" | "--tui=blocks" => {
+                frontend_kind = FrontendKind::Terminal(TerminalGraphics::Blocks)
+            }
+            "--tui=sixel" => frontend_kind = FrontendKind::Terminal(TerminalGraphics::Sixel),
+            "--tui=kitty" => frontend_kind = FrontendKind::Terminal(TerminalGraphics::Kitty),
+            "--scale=fit" => scaling = Scaling::Fit,
+            "--scale=integer" => scaling = Scaling::Integer,
+            "--scale=stretch" => scaling = Scaling::Stretch,
+            _ if arg.starts_with("--scale=") => match arg["--scale=".len()..].parse::<usize>() {
+                Ok(scale) if scale > 0 => window_scale = Some(scale),
+                _ => return usage_error(&format!("Invalid scale: {}", &arg["--scale=".len()..])),
+            },
+            _ if arg.starts_with("--speed=") => {
+                match arg["--speed=".len()..].parse().ok().and_then(speed_index) {
+                    Some(index) => speed = Some(index),
+                    None => {
+                        return usage_error(&format!(
+                            "Invalid speed: {}, expected one of {}",
+                            &arg["--speed=".len()..],
+                            speed_list()
+                        ))
+                    }
+                }
+            }
+            _ if arg.starts_with("--volume=") => match arg["--volume=".len()..].parse::<f32>() {
+                Ok(level) if (0.0..=1.0).contains(&level) => volume = Some(level),
+                _ => return usage_error(&format!("Invalid volume: {}", &arg["--volume=".len()..])),
+            },
+            "--mute" => volume = Some(0.0),
+            "--no-database" => use_database = false,
+            "--no-detect" => use_detection = false,
+            _ if arg.starts_with("--config=") => {
+                config_path = Some(PathBuf::from(&arg["--config=".len()..]))
+            }
+            "--fullscreen" => fullscreen = true,
+            "--osd" => osd = true,
+            "--keypad" | "--keypad=momentary" => keypad_panel = Some(KeypadMode::Momentary),
+            "--keypad=latched" => keypad_panel = Some(KeypadMode::Latched),
+            "--headless" => headless = true,
+            "--screenshot-format=png" => screenshot_format = Format::Png,
+            "--screenshot-format=pbm" => screenshot_format = Format::Pbm,
+            "--record-format=gif" => recording_format = recording::Format::Gif,
+            "--record-format=apng" => recording_format = recording::Format::Apng,
+            _ if arg.starts_with("--record=") => {
+                let path = &arg["--record=".len()..];
+                match recording::Format::from_path(Path::new(path)) {
+                    Some(format) => recording_path = Some((path.to_string(), format)),
+                    None => {
+                        return usage_error("Recordings must be saved as .gif, .png or .apng files")
+                    }
+                }
+            }
+            _ if arg.starts_with("--replay=") => {
+                replay_path = Some(arg["--replay=".len()..].to_string())
+            }
+            _ if arg.starts_with("--frames=") => match arg["--frames=".len()..].parse::<u64>() {
+                Ok(count) => frames = Some(count),
+                Err(_) => {
+                    return usage_error(&format!(
+                        "Invalid frame count: {}",
+                        &arg["--frames=".len()..]
+                    ))
+                }
+            },
+            _ if arg == "--screenshot-at" || arg.starts_with("--screenshot-at=") => {
+                let frame = match arg.strip_prefix("--screenshot-at=") {
+                    Some(frame) => Some(frame.to_string()),
+                    None => args.next(),
+                };
+                match frame.and_then(|frame| frame.parse::<u64>().ok()) {
+                    Some(frame) => screenshot_frames.push(frame),
+                    None => return usage_error("--screenshot-at needs a frame number"),
+                }
+            }
+            "--vnc" => frontend_kind = FrontendKind::Vnc(DEFAULT_VNC_ADDRESS.into()),
+            _ if arg.starts_with("--vnc=") => {
+                match parse_address(&arg["--vnc=".len()..], DEFAULT_VNC_ADDRESS.into()) {
+                    Some(address) => frontend_kind = FrontendKind::Vnc(address),
+                    None => {
+                        return usage_error(&format!(
+                            "Invalid VNC address: {}",
+                            &arg["--vnc=".len()..]
+                        ))
+                    }
+                }
+            }
+            _ if arg.starts_with("--palette=") => {
+                match Palette::parse(&arg["--palette=".len()..]) {
+                    Ok(spec) => palette = Some(spec),
+                    Err(e) => return usage_error(&format!("Invalid palette: {}", e)),
+                }
+            }
+            _ if arg.starts_with("--persistence=") => {
+                match Persistence::parse(&arg["--persistence=".len()..]) {
+                    Ok(mode) => persistence = Some(mode),
+                    Err(e) => return usage_error(&format!("Invalid persistence: {}", e)),
+                }
+            }
+            _ if arg.starts_with("--filter=") => {
+                match FilterChain::parse(&arg["--filter=".len()..]) {
+                    Ok(chain) => filters = Some(chain),
+                    Err(e) => return usage_error(&format!("Invalid filter: {}", e)),
+                }
+            }
+            _ if serve && arg.starts_with("--listen=") => {
+                match parse_address(&arg["--listen=".len()..], DEFAULT_SERVE_ADDRESS.into()) {
+                    Some(address) => serve_address = address,
+                    None => {
+                        return usage_error(&format!(
+                            "Invalid listen address: {}",
+                            &arg["--listen=".len()..]
+                        ))
+                    }
+                }
+            }
+            _ if arg.starts_with('-') => {
+                return usage_error(&format!("Unknown option: {}", arg));
+            }
+            _ => file_path = Some(arg),
+        }
+    }
+    if serve {
+        frontend_kind = FrontendKind::Web(serve_address);
+    }
+
+    let file_path = match file_path {
+        Some(path) => path,
+        None => return usage_error("No CHIP-8 program passed in"),
     };
 
-    let mut file = match File::open(file_path) {
-        Ok(file) => file,
-        Err(e) => {
-            eprintln!("Could not open file");
-            eprintln!("Cause: {}", e);
-            return;
+    if recording_path.is_some() && !headless {
+        return usage_error("--record needs --headless; press F9 to record from the window");
+    }
+    if replay_path.is_some() && !headless {
+        return usage_error("--replay needs --headless");
+    }
+
+    // The program is kept so the machine can be reset
+    let rom = match std::fs::read(&file_path) {
+        Ok(rom) => rom,
+        Err(e) => return failure("Couldn't read file", &e),
+    };
+    let rom_name = rom_name(Path::new(&file_path));
+    let rom_id = RomId::new(&rom_name, &rom);
+
+    // Settings given on the command line take priority over the config file
+    let mut config = match load_config(config_path.as_deref()) {
+        Ok(config) => config,
+        Err(e) => return failure("Couldn't load config", &e),
+    };
+
+    // ROMs in the database are set up to run the way they were written to, unless the config file
+    // says otherwise for them
+    let database = if use_database {
+        match load_database() {
+            Ok(database) => Some(database),
+            Err(e) => return failure("Couldn't load ROM database", &e),
         }
+    } else {
+        None
+    };
+    let found = database
+        .as_ref()
+        .and_then(|database| database.lookup(&rom_id.sha1));
+    if let Some(found) = &found {
+        config.detected.quirks = found.quirks().map(|quirks| quirks.to_string());
+        config.detected.ipf = found.tickrate();
+        config.detected.palette = found.palette();
+    }
+    // Other ROMs get a guess from their code, which is only used if it found anything to go on
+    let detection = if found.is_none() && use_detection {
+        Some(detect::detect(&rom)).filter(|detection| detection.confidence > Confidence::Low)
+    } else {
+        None
+    };
+    if let Some(detection) = &detection {
+        config.detected.quirks = detection.quirks.map(|quirks| quirks.to_string());
+        println!("{}", detection_summary(detection));
+    }
+    let settings = (|| -> Result<_, ConfigError> {
+        options.instructions_per_frame = match options.instructions_per_frame {
+            Some(count) => Some(count),
+            None => config.ipf_for(&rom_id)?,
+        };
+        options.quirks = match options.quirks {
+            Some(quirks) => Some(quirks),
+            None => config.quirks_for(&rom_id)?,
+        };
+        Ok((
+            palette.map_or_else(|| config.palette_for(&rom_id), Ok)?,
+            persistence.map_or_else(|| config.persistence_for(&rom_id), Ok)?,
+            filters.map_or_else(|| config.filters_for(&rom_id), Ok)?,
+            window_scale.map_or_else(|| config.scale_for(&rom_id), |scale| Ok(Some(scale)))?,
+            config.tone_for(&rom_id)?,
+        ))
+    })();
+    let (palette, persistence, filters, window_scale, tone) = match settings {
+        Ok(settings) => settings,
+        Err(e) => return failure("Couldn't load config", &e),
+    };
+    let window_scale = window_scale.unwrap_or(DEFAULT_WINDOW_SCALE);
+    let volume = volume.or_else(|| config.volume_for(&rom_id)).unwrap_or(1.0);
+    let tone = tone.unwrap_or(DEFAULT_TONE);
+    let speed = match speed {
+        Some(speed) => speed,
+        None => match config.speed_for(&rom_id) {
+            Some(speed) => match speed_index(speed) {
+                Some(index) => index,
+                None => {
+                    eprintln!("Couldn't load config");
+                    eprintln!("Cause: speed must be one of {}", speed_list());
+                    return ExitCode::from(EXIT_FAILURE);
+                }
+            },
+            None => NORMAL_SPEED,
+        },
     };
 
-    let mut machine = match Machine::from_file(&mut file) {
-        Ok(machine) => machine,
-        Err(e) => {
-            eprintln!("Couldn't read file");
-            eprintln!("Cause: {}", e);
-            return;
+    let layout = match config.keymap_for(&rom_id).map(Layout::parse).transpose() {
+        Ok(layout) => layout,
+        Err(e) => return failure("Couldn't load config", &e),
+    };
+    let mut keymap = match load_keymap(&rom_id, layout) {
+        Ok(keymap) => keymap,
+        Err(e) => return failure("Couldn't load keymap", &e),
+    };
+    if let Some(found) = &found {
+        for (action, &key) in &found.rom.keys {
+            if key <= 0xF {
+                keymap.bind_action(action, Key(key));
+            }
+        }
+    }
+
+    if headless {
+        let replay = match replay_path.map(|path| Replay::load(Path::new(&path))) {
+            Some(Ok(replay)) => replay,
+            Some(Err(e)) => return failure("Couldn't load the replay", &e),
+            None => Replay::default(),
+        };
+
+        // Run until the last screenshot or key press, or for as long as asked if that's longer
+        let last_frame = screenshot_frames
+            .iter()
+            .copied()
+            .max()
+            .max(replay.last_frame())
+            .max(frames);
+        let last_frame = match last_frame {
+            Some(frame) => frame,
+            None => return usage_error("--headless needs --frames, --screenshot-at or --replay"),
+        };
+
+        // Headless runs are usually checked against earlier ones, so they're made repeatable
+        options.seed = options.seed.or(Some(0));
+        let mut machine = options.machine(&rom);
+        let mut recorder = recording_path
+            .as_ref()
+            .map(|_| Recorder::new(palette, SCREENSHOT_SCALE));
+        for frame in 1..=last_frame {
+            replay.apply(frame, machine.keyboard_mut());
+            machine.run_frame(options.instructions_per_frame());
+            if let Some(recorder) = &mut recorder {
+                recorder.capture(machine.display().pixels());
+            }
+            if screenshot_frames.contains(&frame) {
+                take_screenshot(&machine, screenshot_format, &palette, &filters, &rom_name);
+            }
+            if let Some(fault) = machine.fault() {
+                eprintln!("Halted: {}", fault);
+                break;
+            }
         }
+
+        if let (Some(recorder), Some((path, format))) = (recorder, recording_path) {
+            match recorder.save(Path::new(&path), format) {
+                Ok(()) => println!("Saved recording to {}", path),
+                Err(e) => return failure("Couldn't save recording", &e),
+            }
+        }
+        return exit_status(&machine);
+    }
+
+    let mut machine = options.machine(&rom);
+    let mut frontend: Box<dyn Frontend> = match frontend_kind {
+        FrontendKind::Sdl => {
+            let sdl_context = sdl2::init().unwrap();
+            let mut frontend = SdlFrontend::new(
+                &sdl_context,
+                (WIDTH * window_scale) as u32,
+                (HEIGHT * window_scale) as u32,
+                scaling,
+                palette,
+                persistence,
+                keymap,
+            );
+            frontend.set_filters(filters.clone());
+            frontend.set_sound(volume, tone);
+            frontend.set_rom_name(&rom_name);
+            if let Some(found) = &found {
+                frontend.set_title(&format!("{} - Chemu", found.window_title()));
+            }
+            frontend.set_recording_format(recording_format);
+            frontend.set_keypad_panel(keypad_panel);
+            frontend.set_fullscreen(fullscreen);
+            frontend.osd_mut().set_status_visible(osd);
+            frontend.osd_mut().set_keypad_visible(osd);
+            let profile = options.quirks().to_string();
+            frontend.osd_mut().set_profile(Some(&profile));
+            Box::new(frontend)
+        }
+        FrontendKind::Terminal(graphics) => match open_tui(graphics, palette, keymap) {
+            Ok(frontend) => frontend,
+            Err(e) => return failure("Couldn't set up the terminal", &e),
+        },
+        FrontendKind::Vnc(address) => {
+            let mut view_only_address = address;
+            view_only_address.set_port(address.port().wrapping_add(1));
+            match VncFrontend::new(
+                address,
+                view_only_address,
+                VNC_SCALE,
+                palette,
+                keymap.key_names(),
+            ) {
+                Ok(frontend) => {
+                    let (address, view_only_address) = frontend.local_addresses();
+                    println!(
+                        "Serving VNC on {} (view only on {})",
+                        address, view_only_address
+                    );
+                    Box::new(frontend)
+                }
+                Err(e) => return failure("Couldn't start the VNC server", &e),
+            }
+        }
+        FrontendKind::Web(address) => match WebFrontend::new(address, palette) {
+            Ok(frontend) => {
+                let address = frontend.local_address();
+                println!("Serving on http://{} (spectate at /watch)", address);
+                Box::new(frontend)
+            }
+            Err(e) => return failure("Couldn't start the web server", &e),
+        },
     };
 
+    if let Some(found) = &found {
+        let platform = found
+            .platform
+            .map_or("unknown platform", |platform| &platform.name);
+        frontend.show_message(&format!("{} ({})", found.program.title, platform));
+    } else if let Some(detection) = &detection {
+        frontend.show_message(&detection_summary(detection));
+    }
+
+    // Ctrl-C quits cleanly, so frontends can restore the terminal and save recordings
+    let quit = Arc::new(AtomicBool::new(false));
+    let handler_quit = Arc::clone(&quit);
+    ctrlc::set_handler(move || handler_quit.store(true, Ordering::SeqCst)).unwrap();
+
     let cpu_delta = Duration::from_secs_f64(1.0 / 100.0);
     let timer_delta = Duration::from_secs_f64(1.0 / 60.0);
-    let mut tick_deadline = Instant::now();
-    loop {
-        while tick_deadline.elapsed() >= timer_delta {
-            machine.decrement_timers();
-            tick_deadline += timer_delta;
+    let instructions_per_frame = options.instructions_per_frame();
+    let instruction_delta = timer_delta / instructions_per_frame as u32;
+    let mut last_step = Instant::now();
+    // Emulated time owed to the processor, which runs faster or slower than real time when the
+    // speed is changed, and how many instructions have run since the timers last ticked
+    let mut cpu_time = Duration::ZERO;
+    let mut frame_instructions = 0;
+    let mut paused = false;
+    let mut speed = speed;
+    let mut fast_forward = false;
+    let mut frame = 0;
+    // Faults are reported once, when the machine halts
+    let mut fault_reported = false;
+    let take_due_screenshot = |machine: &Machine, frame: u64| {
+        if screenshot_frames.contains(&frame) {
+            take_screenshot(machine, screenshot_format, &palette, &filters, &rom_name);
+        }
+    };
+    while !quit.load(Ordering::SeqCst) {
+        frontend.process_events(machine.keyboard_mut());
+
+        let mut advance = false;
+        while let Some(command) = frontend.poll_command() {
+            match command {
+                Command::TogglePause => {
+                    paused = !paused;
+                    frontend.show_message(if paused { "Paused" } else { "Resumed" });
+                }
+                Command::FrameAdvance if paused => advance = true,
+                Command::FrameAdvance => {
+                    paused = true;
+                    frontend.show_message("Paused");
+                }
+                Command::Reset => {
+                    // Keys the player is still holding stay held in the new machine, since the
+                    // frontend won't see them pressed again until they're released
+                    let held: Vec<Key> = (0..16)
+                        .map(Key)
+                        .filter(|&key| machine.keyboard().is_pressed(key))
+                        .collect();
+                    machine = options.machine(&rom);
+                    for key in held {
+                        machine.keyboard_mut().press(key);
+                    }
+                    fault_reported = false;
+                    frontend.machine_replaced();
+                    frontend.show_message("Reset");
+                }
+                Command::SpeedDown | Command::SpeedUp => {
+                    speed = if command == Command::SpeedDown {
+                        speed.saturating_sub(1)
+                    } else {
+                        (speed + 1).min(SPEEDS.len() - 1)
+                    };
+                    frontend.show_message(&format!("Speed x{}", SPEEDS[speed]));
+                }
+                Command::FastForward(held) => {
+                    fast_forward = held;
+                    if held {
+                        frontend.show_message(&format!("Fast-forward x{}", FAST_FORWARD_SPEED));
+                    }
+                }
+                Command::Quit => quit.store(true, Ordering::SeqCst),
+                // The window's own controls are handled by the frontends that have them
+                Command::ToggleFullscreen
+                | Command::Screenshot
+                | Command::BitmapScreenshot
+                | Command::ToggleRecording
+                | Command::ToggleStatus
+                | Command::ToggleKeypad => {}
+            }
+        }
+
+        // Time spent paused or stalled isn't caught up on
+        let now = Instant::now();
+        let elapsed = now.duration_since(last_step).min(MAX_CATCH_UP);
+        last_step = now;
+
+        if advance {
+            machine.run_frame(instructions_per_frame);
+            frame += 1;
+            frontend.tick(&machine);
+            take_due_screenshot(&machine, frame);
         }
 
-        machine.process_key_events();
+        if !paused {
+            let multiplier = if fast_forward {
+                SPEEDS[speed] * FAST_FORWARD_SPEED as f64
+            } else {
+                SPEEDS[speed]
+            };
+            let elapsed = elapsed.mul_f64(multiplier);
+
+            // Each frame's instructions run before its timer tick, so frontends see every frame
+            // as it was at the tick, however many frames are caught up on at once
+            cpu_time += elapsed;
+            while cpu_time >= instruction_delta {
+                machine.exec_next();
+                cpu_time -= instruction_delta;
+                frame_instructions += 1;
+                if frame_instructions == instructions_per_frame {
+                    frame_instructions = 0;
+                    machine.decrement_timers();
+                    frame += 1;
+                    frontend.tick(&machine);
+                    take_due_screenshot(&machine, frame);
+                }
+            }
+        }
 
-        for _ in 0..5 {
-            machine.exec_next();
+        if let (Some(fault), false) = (machine.fault(), fault_reported) {
+            eprintln!("Halted: {}", fault);
+            frontend.show_message(&format!("Halted: {}", fault));
+            fault_reported = true;
         }
 
-        machine.update_display();
+        frontend.render(&machine);
+        machine.display_mut().mark_presented();
         std::thread::sleep(cpu_delta);
     }
+
+    exit_status(&machine)
+}
+
+/// The position of a speed in `SPEEDS`, if it's one of them.
+fn speed_index(speed: f64) -> Option<usize> {
+    SPEEDS.iter().position(|&step| step == speed)
+}
+
+/// The speeds the machine can run at, for error messages.
+fn speed_list() -> String {
+    let speeds: Vec<String> = SPEEDS.iter().map(f64::to_string).collect();
+    speeds.join(", ")
+}
+
+/// The exit status for a finished run, which is a failure if the program halted on a fault.
+fn exit_status(machine: &Machine) -> ExitCode {
+    if machine.fault().is_some() {
+        ExitCode::from(EXIT_FAILURE)
+    } else {
+        ExitCode::SUCCESS
+    }
+}
+
+/// Prints a ROM as assembly.
+fn run_disasm(args: Vec<String>) -> ExitCode {
+    let file_path = match args.as_slice() {
+        [path] if !path.starts_with('-') => path,
+        _ => return usage_error("disasm takes the path of a ROM"),
+    };
+
+    match std::fs::read(file_path) {
+        Ok(rom) => {
+            print!("{}", assembler::disassemble(&rom));
+            ExitCode::SUCCESS
+        }
+        Err(e) => failure("Couldn't read file", &e),
+    }
+}
+
+/// Prints what a ROM's code suggests about the platform it was written for.
+fn run_detect(args: Vec<String>) -> ExitCode {
+    let file_path = match args.as_slice() {
+        [path] if !path.starts_with('-') => path,
+        _ => return usage_error("detect takes the path of a ROM"),
+    };
+
+    let rom = match std::fs::read(file_path) {
+        Ok(rom) => rom,
+        Err(e) => return failure("Couldn't read file", &e),
+    };
+    let detection = detect::detect(&rom);
+    println!("Platform:     {}", detection.platform.name());
+    println!(
+        "Supported:    {}",
+        if detection.platform.is_supported() {
+            "yes"
+        } else {
+            "no"
+        }
+    );
+    match detection.quirks {
+        Some(quirks) => println!("Quirks:       {}", quirks),
+        None => println!("Quirks:       unchanged"),
+    }
+    println!("Confidence:   {}", detection.confidence);
+    println!("Instructions: {}", detection.instructions);
+    for evidence in &detection.evidence {
+        println!(
+            "  {:03X}: {} ({}x)",
+            evidence.address, evidence.description, evidence.count
+        );
+    }
+    ExitCode::SUCCESS
+}
+
+/// A one-line description of a guessed platform.
+fn detection_summary(detection: &Detection) -> String {
+    let summary = format!(
+        "Detected {} ({} confidence)",
+        detection.platform.name(),
+        detection.confidence
+    );
+    if detection.platform.is_supported() {
+        summary
+    } else {
+        format!(
+            "{}, which Chemu can't run: it will fault at the first {} instruction",
+            summary,
+            detection.platform.name()
+        )
+    }
+}
+
+/// Assembles a program into a ROM, saved next to the source unless `-o` is given.
+fn run_asm(args: Vec<String>) -> ExitCode {
+    let mut args = args.into_iter();
+    let mut source_path = None;
+    let mut output_path = None;
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-o" => match args.next() {
+                Some(path) => output_path = Some(PathBuf::from(path)),
+                None => return usage_error("-o needs an output path"),
+            },
+            _ if arg.starts_with('-') => return usage_error(&format!("Unknown option: {}", arg)),
+            _ => source_path = Some(PathBuf::from(arg)),
+        }
+    }
+    let source_path = match source_path {
+        Some(path) => path,
+        None => return usage_error("No source file passed in"),
+    };
+    let output_path = output_path.unwrap_or_else(|| source_path.with_extension("ch8"));
+
+    let source = match std::fs::read_to_string(&source_path) {
+        Ok(source) => source,
+        Err(e) => return failure("Couldn't read file", &e),
+    };
+    let rom = match assembler::assemble(&source) {
+        Ok(rom) => rom,
+        Err(e) => {
+            eprintln!("{}: {}", source_path.display(), e);
+            return ExitCode::from(EXIT_FAILURE);
+        }
+    };
+    match std::fs::write(&output_path, &rom) {
+        Ok(()) => {
+            println!("Assembled {} bytes to {}", rom.len(), output_path.display());
+            ExitCode::SUCCESS
+        }
+        Err(e) => failure("Couldn't save ROM", &e),
+    }
+}
+
+/// Parses the arguments shared by `trace` and `bench`: machine options, `--frames=N` and the ROM.
+/// `extra` is given any other arguments, and returns whether it used them.
+fn parse_run_args(
+    args: Vec<String>,
+    options: &mut MachineOptions,
+    frames: &mut Option<u64>,
+    mut extra: impl FnMut(&str) -> Result<bool, String>,
+) -> Result<Vec<u8>, ExitCode> {
+    let mut file_path = None;
+    for arg in args {
+        let used = options
+            .parse_arg(&arg)
+            .and_then(|used| if used { Ok(true) } else { extra(&arg) })
+            .map_err(|message| usage_error(&message))?;
+        if used {
+            continue;
+        }
+        if let Some(count) = arg.strip_prefix("--frames=") {
+            match count.parse() {
+                Ok(count) => *frames = Some(count),
+                Err(_) => return Err(usage_error(&format!("Invalid frame count: {}", count))),
+            }
+        } else if arg.starts_with('-') {
+            return Err(usage_error(&format!("Unknown option: {}", arg)));
+        } else {
+            file_path = Some(arg);
+        }
+    }
+
+    let file_path = file_path.ok_or_else(|| usage_error("No CHIP-8 program passed in"))?;
+    std::fs::read(file_path).map_err(|e| failure("Couldn't read file", &e))
+}
+
+/// Runs a ROM without input, printing each instruction along with the registers before it runs.
+fn run_trace(args: Vec<String>) -> ExitCode {
+    let mut options = MachineOptions::new();
+    let mut frames = None;
+    let mut steps = None;
+    let rom = match parse_run_args(args, &mut options, &mut frames, |arg| {
+        match arg.strip_prefix("--steps=") {
+            Some(count) => match count.parse() {
+                Ok(count) => steps = Some(count),
+                Err(_) => return Err(format!("Invalid step count: {}", count)),
+            },
+            None => return Ok(false),
+        }
+        Ok(true)
+    }) {
+        Ok(rom) => rom,
+        Err(status) => return status,
+    };
+    let steps = match frames {
+        Some(frames) => frames * options.instructions_per_frame() as u64,
+        None => steps.unwrap_or(DEFAULT_TRACE_STEPS),
+    };
+
+    // Traces are compared between runs, so they're made repeatable
+    options.seed = options.seed.or(Some(0));
+    let mut machine = options.machine(&rom);
+    let stdout = std::io::stdout();
+    let mut out = BufWriter::new(stdout.lock());
+    for step in 0..steps {
+        let pc = machine.program_counter();
+        let memory = machine.memory();
+        let opcode = match (memory.get(pc), memory.get(pc + 1)) {
+            (Some(&high), Some(&low)) => u16::from_be_bytes([high, low]),
+            _ => 0,
+        };
+        let text = match instruction::decode(opcode) {
+            Ok(instruction) => instruction.to_string(),
+            Err(_) => format!("DW #{:04X}", opcode),
+        };
+        let mut registers = String::new();
+        for value in machine.registers() {
+            registers.push_str(&format!(" {:02X}", value));
+        }
+        let line = writeln!(
+            out,
+            "{:03X}  {:04X}  {:<16} V:{}  I:{:03X}",
+            pc,
+            opcode,
+            text,
+            registers,
+            machine.address_register()
+        );
+        // Stop quietly when the output is closed, such as when piped into `head`
+        if line.is_err() {
+            return ExitCode::SUCCESS;
+        }
+
+        machine.exec_next();
+        if (step + 1) % options.instructions_per_frame() as u64 == 0 {
+            machine.decrement_timers();
+        }
+        if let Some(fault) = machine.fault() {
+            let _ = out.flush();
+            eprintln!("Halted: {}", fault);
+            break;
+        }
+    }
+    let _ = out.flush();
+    exit_status(&machine)
+}
+
+/// Runs a ROM without input as fast as possible and reports how fast it ran.
+fn run_bench(args: Vec<String>) -> ExitCode {
+    let mut options = MachineOptions::new();
+    let mut frames = None;
+    let rom = match parse_run_args(args, &mut options, &mut frames, |_| Ok(false)) {
+        Ok(rom) => rom,
+        Err(status) => return status,
+    };
+    let frames = frames.unwrap_or(DEFAULT_BENCH_FRAMES);
+
+    options.seed = options.seed.or(Some(0));
+    let mut machine = options.machine(&rom);
+    let start = Instant::now();
+    let mut frames_run = 0;
+    while frames_run < frames && machine.fault().is_none() {
+        machine.run_frame(options.instructions_per_frame());
+        frames_run += 1;
+    }
+    let seconds = start.elapsed().as_secs_f64().max(f64::EPSILON);
+
+    if let Some(fault) = machine.fault() {
+        eprintln!("Halted: {}", fault);
+    }
+    let instructions = machine.instructions_executed();
+    println!(
+        "Ran {} frames ({} instructions) in {:.3} s",
+        frames_run, instructions, seconds
+    );
+    println!(
+        "{:.0} frames/s, {:.0} instructions/s, {:.0}x real time",
+        frames_run as f64 / seconds,
+        instructions as f64 / seconds,
+        frames_run as f64 / 60.0 / seconds
+    );
+    exit_status(&machine)
+}
+
+/// Drives a machine with JSON-RPC requests read from stdin, or from a Unix socket when
+/// `--socket=PATH` is given.
+fn run_rpc(args: Vec<String>) -> ExitCode {
+    let mut socket_path = None;
+    for arg in args {
+        if let Some(path) = arg.strip_prefix("--socket=") {
+            socket_path = Some(path.to_string());
+        } else {
+            return usage_error(&format!("Unknown argument: {}", arg));
+        }
+    }
+
+    let mut session = Session::new();
+    let result = match socket_path {
+        Some(path) => serve_rpc_socket(&mut session, Path::new(&path)),
+        None => {
+            let stdin = std::io::stdin();
+            session.serve(stdin.lock(), std::io::stdout())
+        }
+    };
+
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => failure("Couldn't serve automation requests", &e),
+    }
+}
+
+#[cfg(unix)]
+fn serve_rpc_socket(session: &mut Session, path: &Path) -> std::io::Result<()> {
+    chemu::automation::serve_socket(session, path)
+}
+
+#[cfg(not(unix))]
+fn serve_rpc_socket(_session: &mut Session, _path: &Path) -> std::io::Result<()> {
+    Err(std::io::Error::new(
+        std::io::ErrorKind::Other,
+        "Unix sockets are only supported on Unix",
+    ))
+}
+
+/// Parses an address given on the command line, which is either a port on the default address's
+/// interface or a full socket address.
+fn parse_address(address: &str, mut default: SocketAddr) -> Option<SocketAddr> {
+    if let Ok(port) = address.parse::<u16>() {
+        default.set_port(port);
+        return Some(default);
+    }
+
+    address.parse().ok()
+}
+
+/// The name a ROM is configured by and saved under, which is its file name without the extension.
+fn rom_name(rom_path: &Path) -> String {
+    rom_path
+        .file_stem()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default()
+}
+
+/// Loads the user's keymap, using any overrides set for the ROM, starting from the given layout
+/// where the keymap file doesn't choose one.
+fn load_keymap(rom: &RomId, layout: Option<Layout>) -> Result<Keymap, KeymapError> {
+    let keymap_file = match KeymapFile::default_path() {
+        Some(path) => KeymapFile::load(&path)?,
+        None => KeymapFile::default(),
+    };
+
+    keymap_file.keymap_for(rom, layout)
+}
+
+/// Loads the config file at the given path, or the user's config file if there isn't one. A
+/// config file that was asked for has to exist.
+fn load_config(path: Option<&Path>) -> Result<ConfigFile, ConfigError> {
+    if let Some(path) = path {
+        std::fs::metadata(path)?;
+        return ConfigFile::load(path);
+    }
+
+    match ConfigFile::default_path() {
+        Some(path) => ConfigFile::load(&path),
+        None => Ok(ConfigFile::default()),
+    }
+}
+
+/// Loads the ROM database from the config directory if the user has put a copy there, and uses
+/// the built-in one otherwise.
+fn load_database() -> Result<Database, DatabaseError> {
+    match config_dir().map(|dir| dir.join("chip-8-database")) {
+        Some(dir) if dir.is_dir() => Database::load(&dir),
+        _ => Ok(Database::bundled()),
+    }
+}
+
+/// Saves a screenshot to the current directory. PNG screenshots are drawn with the filters and
+/// scaled up to about the size of the window.
+fn take_screenshot(
+    machine: &Machine,
+    format: Format,
+    palette: &Palette,
+    filters: &FilterChain,
+    rom_name: &str,
+) {
+    let image = filters.apply(Image::from_pixels(machine.display().pixels(), palette));
+    let image = image.scaled((SCREENSHOT_SCALE / filters.scale()).max(1));
+    match screenshot::save_image(machine.display(), &image, format, Path::new("."), rom_name) {
+        Ok(path) => println!("Saved screenshot to {}", path.display()),
+        Err(e) => {
+            eprintln!("Couldn't save screenshot");
+            eprintln!("Cause: {}", e);
+        }
+    }
+}
+
+#[cfg(unix)]
+fn open_tui(
+    graphics: TerminalGraphics,
+    palette: Palette,
+    keymap: Keymap,
+) -> std::io::Result<Box<dyn Frontend>> {
+    Ok(Box::new(chemu::frontend::tui::TuiFrontend::new(
+        graphics,
+        TERMINAL_SCALE,
+        palette,
+        keymap,
+    )?))
+}
+
+#[cfg(not(unix))]
+fn open_tui(
+    _graphics: TerminalGraphics,
+    _palette: Palette,
+    _keymap: Keymap,
+) -> std::io::Result<Box<dyn Frontend>> {
+    Err(std::io::Error::new(
+        std::io::ErrorKind::Other,
+        "the terminal frontend is only supported on Unix",
+    ))
 }
@@ -0,0 +1,343 @@
+//! An on-screen display drawn over the game: a status line with the frame rate, instructions per
+//! second and quirk profile, short messages that fade away on their own, and a keypad showing
+//! which hex keys are held. Text is drawn with the built-in bitmap font.
+
+use crate::filter::Image;
+use crate::font;
+use crate::font::{GLYPH_HEIGHT, GLYPH_SPACING, GLYPH_WIDTH};
+use crate::keyboard::{Key, Keyboard};
+use crate::palette::Rgb;
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+/// How long messages stay on screen.
+const MESSAGE_DURATION: Duration = Duration::from_secs(2);
+/// Older messages are dropped once there are more than this many.
+const MAX_MESSAGES: usize = 3;
+/// How often the frame rate and instructions per second are recalculated.
+const SAMPLE_INTERVAL: Duration = Duration::from_secs(1);
+
+/// The hex keys in the order they're laid out on the COSMAC VIP's keypad, by rows.
+pub const KEYPAD_LAYOUT: [u8; 16] = [
+    0x1, 0x2, 0x3, 0xC, 0x4, 0x5, 0x6, 0xD, 0x7, 0x8, 0x9, 0xE, 0xA, 0x0, 0xB, 0xF,
+];
+
+const TEXT_COLOUR: Rgb = Rgb(0xFF, 0xFF, 0xFF);
+const BACKGROUND: Rgb = Rgb(0, 0, 0);
+/// How opaque the boxes behind text are, out of 255.
+const BACKGROUND_ALPHA: u8 = 160;
+const KEY_COLOUR: Rgb = Rgb(0x80, 0x80, 0x80);
+const KEY_BACKGROUND: Rgb = Rgb(0x30, 0x30, 0x30);
+const PRESSED_KEY_COLOUR: Rgb = Rgb(0xFF, 0xC0, 0x40);
+
+/// Something the on-screen display can be drawn onto.
+pub trait Surface {
+    /// Fills a rectangle with a colour, blended over what's already there with an opacity from 0
+    /// for invisible to 255 for solid. Parts outside the surface are ignored.
+    fn fill(&mut self, x: usize, y: usize, width: usize, height: usize, colour: Rgb, alpha: u8);
+}
+
+impl Surface for Image {
+    fn fill(&mut self, x: usize, y: usize, width: usize, height: usize, colour: Rgb, alpha: u8) {
+        let right = (x + width).min(self.width());
+        let bottom = (y + height).min(self.height());
+        let alpha = alpha as u32;
+        let blend = |under: u8, over: u8| {
+            ((under as u32 * (255 - alpha) + over as u32 * alpha + 127) / 255) as u8
+        };
+
+        for y in y..bottom {
+            for x in x..right {
+                let Rgb(r, g, b) = self.get(x, y);
+                self.set(
+                    x,
+                    y,
+                    Rgb(blend(r, colour.0), blend(g, colour.1), blend(b, colour.2)),
+                );
+            }
+        }
+    }
+}
+
+/// Everything the on-screen display shows, so frontends can tell when it needs drawing again.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+struct Contents {
+    status: Option<String>,
+    messages: Vec<String>,
+    keys: Option<[bool; 16]>,
+}
+
+/// The on-screen display's state. Frontends call `frame` for every 60 Hz frame the machine runs
+/// and `update` before drawing, then `draw` the overlay on top of the game whenever `update`
+/// reports a change.
+pub struct Osd {
+    show_status: bool,
+    show_keypad: bool,
+    profile: Option<String>,
+    /// Messages along with when they disappear, oldest first.
+    messages: VecDeque<(String, Instant)>,
+    frames: u32,
+    sample: (Instant, u64),
+    frames_per_second: u32,
+    instructions_per_second: u64,
+    contents: Contents,
+    drawn: Option<Contents>,
+}
+
+impl Osd {
+    /// Creates an on-screen display with the status line and keypad hidden. Messages are always
+    /// shown.
+    pub fn new() -> Osd {
+        Osd {
+            show_status: false,
+            show_keypad: false,
+            profile: None,
+            messages: VecDeque::new(),
+            frames: 0,
+            sample: (Instant::now(), 0),
+            frames_per_second: 0,
+            instructions_per_second: 0,
+            contents: Contents::default(),
+            drawn: None,
+        }
+    }
+
+    pub fn status_visible(&self) -> bool {
+        self.show_status
+    }
+
+    pub fn set_status_visible(&mut self, visible: bool) {
+        self.show_status = visible;
+    }
+
+    pub fn keypad_visible(&self) -> bool {
+        self.show_keypad
+    }
+
+    pub fn set_keypad_visible(&mut self, visible: bool) {
+        self.show_keypad = visible;
+    }
+
+    /// Sets the name of the quirk profile shown on the status line, or hides it when `None`.
+    pub fn set_profile(&mut self, profile: Option<&str>) {
+        self.profile = profile.map(str::to_string);
+    }
+
+    /// Shows a message for a couple of seconds, below any that are already showing.
+    pub fn message(&mut self, text: &str) {
+        self.messages
+            .push_back((text.to_string(), Instant::now() + MESSAGE_DURATION));
+        while self.messages.len() > MAX_MESSAGES {
+            self.messages.pop_front();
+        }
+    }
+
+    /// Counts an emulated frame towards the frame rate.
+    pub fn frame(&mut self) {
+        self.frames += 1;
+    }
+
+    /// Brings the display up to date with the machine's speed and keypad, and drops expired
+    /// messages. Returns whether it looks different from when it was last drawn.
+    pub fn update(&mut self, instructions_executed: u64, keyboard: &Keyboard) -> bool {
+        let now = Instant::now();
+        let (sampled_at, sampled_count) = self.sample;
+        let elapsed = now.duration_since(sampled_at);
+        if elapsed >= SAMPLE_INTERVAL {
+            let millis = elapsed.as_millis() as u64;
+            self.frames_per_second = ((self.frames as u64 * 1000 + millis / 2) / millis) as u32;
+            self.instructions_per_second =
+                (instructions_executed.saturating_sub(sampled_count) * 1000 + millis / 2) / millis;
+            self.frames = 0;
+            self.sample = (now, instructions_executed);
+        }
+
+        while let Some(&(_, expires)) = self.messages.front() {
+            if expires > now {
+                break;
+            }
+            self.messages.pop_front();
+        }
+
+        let mut keys = [false; 16];
+        for (key, pressed) in keys.iter_mut().enumerate() {
+            *pressed = keyboard.is_pressed(Key(key as u8));
+        }
+
+        self.contents = Contents {
+            status: if self.show_status {
+                Some(self.status_line())
+            } else {
+                None
+            },
+            messages: self.messages.iter().map(|(text, _)| text.clone()).collect(),
+            keys: if self.show_keypad { Some(keys) } else { None },
+        };
+        self.drawn.as_ref() != Some(&self.contents)
+    }
+
+    fn status_line(&self) -> String {
+        let mut status = format!(
+            "{} FPS  {} IPS",
+            self.frames_per_second, self.instructions_per_second
+        );
+        if let Some(profile) = &self.profile {
+            status.push_str("  ");
+            status.push_str(profile);
+        }
+        status
+    }
+
+    /// Draws the display as of the last `update` onto a surface of the given size. Text is scaled
+    /// up with the surface, so it stays readable in large windows.
+    pub fn draw(&mut self, surface: &mut impl Surface, width: usize, height: usize) {
+        let scale = (height / 160).max(1);
+        let margin = 2 * scale;
+        // Labels have a pixel of padding around their text
+        let line_height = (GLYPH_HEIGHT + 2) * scale;
+
+        if let Some(status) = &self.contents.status {
+            draw_label(surface, margin, margin, scale, status);
+        }
+
+        // Messages are stacked up from the bottom left, with the newest at the bottom
+        let count = self.contents.messages.len();
+        for (i, message) in self.contents.messages.iter().enumerate() {
+            let y = height.saturating_sub((count - i) * (line_height + margin));
+            draw_label(surface, margin, y, scale, message);
+        }
+
+        if let Some(keys) = &self.contents.keys {
+            let cell_width = (GLYPH_WIDTH + 4) * scale;
+            let cell_height = (GLYPH_HEIGHT + 4) * scale;
+            let left = width.saturating_sub(4 * cell_width + margin);
+            let top = height.saturating_sub(4 * cell_height + margin);
+            draw_keypad(
+                surface,
+                left,
+                top,
+                (cell_width, cell_height),
+                keys,
+                BACKGROUND_ALPHA,
+            );
+        }
+
+        self.drawn = Some(self.contents.clone());
+    }
+}
+
+impl Default for Osd {
+    fn default() -> Self {
+        Osd::new()
+    }
+}
+
+/// Draws a 4x4 hex keypad in the COSMAC VIP layout with its top left corner at the given position,
+/// highlighting the pressed keys. Key labels are scaled up to fill the cells. The background is
+/// drawn with the given opacity, so the keypad can be laid over the game or drawn solid beside it.
+pub fn draw_keypad(
+    surface: &mut impl Surface,
+    left: usize,
+    top: usize,
+    (cell_width, cell_height): (usize, usize),
+    keys: &[bool; 16],
+    alpha: u8,
+) {
+    let scale = (cell_width / (GLYPH_WIDTH + 4))
+        .min(cell_height / (GLYPH_HEIGHT + 4))
+        .max(1);
+    surface.fill(
+        left,
+        top,
+        4 * cell_width,
+        4 * cell_height,
+        BACKGROUND,
+        alpha,
+    );
+
+    for (i, &key) in KEYPAD_LAYOUT.iter().enumerate() {
+        let x = left + i % 4 * cell_width;
+        let y = top + i / 4 * cell_height;
+        let pressed = keys[key as usize];
+        let (background, colour) = if pressed {
+            (PRESSED_KEY_COLOUR, BACKGROUND)
+        } else {
+            (KEY_BACKGROUND, KEY_COLOUR)
+        };
+        surface.fill(
+            x + scale,
+            y + scale,
+            cell_width.saturating_sub(2 * scale),
+            cell_height.saturating_sub(2 * scale),
+            background,
+            if pressed { 255 } else { alpha },
+        );
+
+        let label = format!("{:X}", key);
+        let label_x = x + cell_width.saturating_sub(GLYPH_WIDTH * scale) / 2;
+        let label_y = y + cell_height.saturating_sub(GLYPH_HEIGHT * scale) / 2;
+        draw_text(surface, label_x, label_y, scale, &label, colour);
+    }
+}
+
+/// The key under a point on a keypad drawn by `draw_keypad`, given relative to the keypad's top
+/// left corner.
+pub fn keypad_key_at(x: usize, y: usize, (cell_width, cell_height): (usize, usize)) -> Option<Key> {
+    let (column, row) = (x / cell_width.max(1), y / cell_height.max(1));
+    if column < 4 && row < 4 {
+        Some(Key(KEYPAD_LAYOUT[row * 4 + column]))
+    } else {
+        None
+    }
+}
+
+/// Draws text in a translucent box, with its top left corner at the given position.
+fn draw_label(surface: &mut impl Surface, x: usize, y: usize, scale: usize, text: &str) {
+    let padding = scale;
+    surface.fill(
+        x,
+        y,
+        font::text_width(text) * scale + 2 * padding,
+        GLYPH_HEIGHT * scale + 2 * padding,
+        BACKGROUND,
+        BACKGROUND_ALPHA,
+    );
+    draw_text(surface, x + padding, y + padding, scale, text, TEXT_COLOUR);
+}
+
+/// Draws text with each font pixel as a square `scale` pixels wide. Runs of lit pixels in a row
+/// are filled as one rectangle, to keep the number of fills down.
+fn draw_text(
+    surface: &mut impl Surface,
+    x: usize,
+    y: usize,
+    scale: usize,
+    text: &str,
+    colour: Rgb,
+) {
+    for (i, c) in text.chars().enumerate() {
+        let left = x + i * (GLYPH_WIDTH + GLYPH_SPACING) * scale;
+        for (row, &bits) in font::glyph(c).iter().enumerate() {
+            let mut column = 0;
+            while column < GLYPH_WIDTH {
+                let lit = |column: usize| bits & (1 << (GLYPH_WIDTH - 1 - column)) != 0;
+                if !lit(column) {
+                    column += 1;
+                    continue;
+                }
+                let start = column;
+                while column < GLYPH_WIDTH && lit(column) {
+                    column += 1;
+                }
+                surface.fill(
+                    left + start * scale,
+                    y + row * scale,
+                    (column - start) * scale,
+                    scale,
+                    colour,
+                    255,
+                );
+            }
+        }
+    }
+}
@@ -0,0 +1,206 @@
+use core::fmt;
+use core::fmt::Formatter;
+#[cfg(feature = "std")]
+use std::error::Error;
+
+/// Number of colours in a palette, one for each combination of the two bit planes drawn by
+/// multi-plane variants such as XO-CHIP. Programs that only use one plane only need the first two.
+pub const PALETTE_SIZE: usize = 4;
+
+/// A colour given as red, green and blue components.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Rgb(pub u8, pub u8, pub u8);
+
+/// The colours used to present the display.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Palette {
+    /// Colours indexed by the planes a pixel is lit on, with the first plane in the lowest bit. The
+    /// first colour is for pixels that are turned off.
+    pub colours: [Rgb; PALETTE_SIZE],
+}
+
+/// The built-in themes, by name. The colours in each differ in brightness as well as hue, so they
+/// can be told apart with any kind of colour vision.
+pub const THEMES: [(&str, Palette); 7] = [
+    (
+        "classic",
+        Palette::new([
+            Rgb(0x00, 0x00, 0x00),
+            Rgb(0xFF, 0xFF, 0xFF),
+            Rgb(0xAA, 0xAA, 0xAA),
+            Rgb(0x55, 0x55, 0x55),
+        ]),
+    ),
+    (
+        "amber",
+        Palette::new([
+            Rgb(0x1A, 0x0E, 0x00),
+            Rgb(0xFF, 0xB0, 0x00),
+            Rgb(0xA8, 0x6A, 0x00),
+            Rgb(0xFF, 0xE0, 0x99),
+        ]),
+    ),
+    (
+        "green",
+        Palette::new([
+            Rgb(0x00, 0x14, 0x00),
+            Rgb(0x33, 0xFF, 0x33),
+            Rgb(0x1A, 0x99, 0x1A),
+            Rgb(0xB3, 0xFF, 0xB3),
+        ]),
+    ),
+    (
+        "lcd",
+        Palette::new([
+            Rgb(0x9B, 0xBC, 0x0F),
+            Rgb(0x0F, 0x38, 0x0F),
+            Rgb(0x8B, 0xAC, 0x0F),
+            Rgb(0x30, 0x62, 0x30),
+        ]),
+    ),
+    (
+        "high-contrast",
+        Palette::new([
+            Rgb(0x00, 0x00, 0x00),
+            Rgb(0xFF, 0xFF, 0xFF),
+            Rgb(0xFF, 0xFF, 0x00),
+            Rgb(0x00, 0x80, 0xFF),
+        ]),
+    ),
+    // Colours from Okabe and Ito's palette, chosen to stay distinct with every common form of
+    // colour blindness
+    (
+        "okabe-ito",
+        Palette::new([
+            Rgb(0x00, 0x00, 0x00),
+            Rgb(0xE6, 0x9F, 0x00),
+            Rgb(0x56, 0xB4, 0xE9),
+            Rgb(0xF0, 0xE4, 0x42),
+        ]),
+    ),
+    // Octo's default XO-CHIP colours
+    (
+        "octo",
+        Palette::new([
+            Rgb(0x99, 0x66, 0x00),
+            Rgb(0xFF, 0xCC, 0x00),
+            Rgb(0xFF, 0x66, 0x00),
+            Rgb(0x66, 0x22, 0x00),
+        ]),
+    ),
+];
+
+impl Palette {
+    pub const fn new(colours: [Rgb; PALETTE_SIZE]) -> Palette {
+        Palette { colours }
+    }
+
+    /// Looks up a built-in theme by name.
+    pub fn theme(name: &str) -> Option<Palette> {
+        THEMES
+            .iter()
+            .find(|(theme, _)| theme.eq_ignore_ascii_case(name))
+            .map(|&(_, palette)| palette)
+    }
+
+    /// Parses either the name of a built-in theme or a comma-separated list of two to four colours
+    /// written as `#RRGGBB`. When fewer than four colours are given, pixels lit on more than one
+    /// plane use the last colour.
+    pub fn parse(spec: &str) -> Result<Palette, PaletteError> {
+        if !spec.contains('#') {
+            return Palette::theme(spec).ok_or(PaletteError::UnknownTheme);
+        }
+
+        let mut colours = [Rgb(0, 0, 0); PALETTE_SIZE];
+        let mut count = 0;
+        for colour in spec.split(',') {
+            if count == PALETTE_SIZE {
+                return Err(PaletteError::ColourCount);
+            }
+            colours[count] = parse_colour(colour.trim())?;
+            count += 1;
+        }
+        if count < 2 {
+            return Err(PaletteError::ColourCount);
+        }
+
+        let last = colours[count - 1];
+        for colour in colours[count..].iter_mut() {
+            *colour = last;
+        }
+
+        Ok(Palette::new(colours))
+    }
+
+    /// Colour of pixels that are turned off.
+    pub fn off(&self) -> Rgb {
+        self.colours[0]
+    }
+
+    /// Colour of pixels that are turned on, when there's only one plane.
+    pub fn on(&self) -> Rgb {
+        self.colours[1]
+    }
+
+    pub fn colour(&self, pixel: bool) -> Rgb {
+        self.colours[pixel as usize]
+    }
+
+    /// Colour part way between off and on, for pixels with a brightness from 0 to 255.
+    pub fn blend(&self, intensity: u8) -> Rgb {
+        let (Rgb(r0, g0, b0), Rgb(r1, g1, b1)) = (self.off(), self.on());
+        let mix = |off: u8, on: u8| {
+            let intensity = intensity as u32;
+            ((off as u32 * (255 - intensity) + on as u32 * intensity + 127) / 255) as u8
+        };
+        Rgb(mix(r0, r1), mix(g0, g1), mix(b0, b1))
+    }
+}
+
+impl Default for Palette {
+    fn default() -> Self {
+        THEMES[0].1
+    }
+}
+
+fn parse_colour(colour: &str) -> Result<Rgb, PaletteError> {
+    let hex = colour
+        .strip_prefix('#')
+        // from_str_radix accepts a leading sign, which isn't part of a colour
+        .filter(|hex| hex.len() == 6 && hex.chars().all(|c| c.is_ascii_hexdigit()))
+        .ok_or(PaletteError::InvalidColour)?;
+    let value = u32::from_str_radix(hex, 16).map_err(|_| PaletteError::InvalidColour)?;
+
+    Ok(Rgb((value >> 16) as u8, (value >> 8) as u8, value as u8))
+}
+
+/// Error that occurs while parsing a palette.
+#[derive(Debug)]
+pub enum PaletteError {
+    /// The palette isn't a list of colours or the name of a built-in theme.
+    UnknownTheme,
+    /// A colour isn't written as `#RRGGBB`.
+    InvalidColour,
+    /// A list of colours doesn't have between two and four entries.
+    ColourCount,
+}
+
+impl fmt::Display for PaletteError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            PaletteError::UnknownTheme => {
+                write!(f, "unknown theme, expected one of")?;
+                for (i, (name, _)) in THEMES.iter().enumerate() {
+                    let separator = if i == 0 { " " } else { ", " };
+                    write!(f, "{}{}", separator, name)?;
+                }
+                Ok(())
+            }
+            PaletteError::InvalidColour => write!(f, "colours must be written as #RRGGBB"),
+            PaletteError::ColourCount => write!(f, "palettes need between 2 and 4 colours"),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl Error for PaletteError {}
@@ -0,0 +1,196 @@
+//! Rendering modes that hide flicker. CHIP-8 programs move sprites by erasing them with XOR and
+//! drawing them again, so a sprite is often missing from the frame that happens to be presented.
+//! These modes combine recent frames into a brightness for each pixel, which frontends then draw by
+//! blending between the palette's off and on colours.
+
+use crate::display::{HEIGHT, WIDTH};
+use core::fmt;
+use core::fmt::Formatter;
+#[cfg(feature = "std")]
+use std::error::Error;
+
+/// Most frames that can be blended together.
+pub const MAX_BLEND_FRAMES: usize = 8;
+/// Frames blended together when a count isn't given.
+const DEFAULT_BLEND_FRAMES: usize = 3;
+/// How long pixels take to fade out when a time isn't given.
+const DEFAULT_DECAY_MILLISECONDS: u32 = 100;
+/// Frames are presented at the same rate the timers tick.
+const FRAMES_PER_SECOND: u32 = 60;
+
+/// How the pixels of recent frames are combined.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum Persistence {
+    /// Each frame is shown as it is.
+    #[default]
+    Off,
+    /// Pixels are as bright as the share of the last this many frames they were lit in.
+    Blend(usize),
+    /// Pixels light up fully and then fade out evenly over this many frames once turned off, like
+    /// the phosphor of a CRT.
+    Decay(u32),
+    /// Pixels are lit if they were lit in either of the last two frames.
+    MaxOfTwo,
+}
+
+impl Persistence {
+    /// Parses `off`, `max`, `blend` optionally followed by `=FRAMES`, or `decay` optionally
+    /// followed by `=MILLISECONDS`.
+    pub fn parse(spec: &str) -> Result<Persistence, PersistenceError> {
+        let (mode, value) = match spec.find('=') {
+            Some(i) => (&spec[..i], Some(&spec[i + 1..])),
+            None => (spec, None),
+        };
+
+        match (mode, value) {
+            ("off", None) => Ok(Persistence::Off),
+            ("max", None) => Ok(Persistence::MaxOfTwo),
+            ("blend", None) => Ok(Persistence::Blend(DEFAULT_BLEND_FRAMES)),
+            ("blend", Some(frames)) => match frames.parse() {
+                Ok(frames) if (1..=MAX_BLEND_FRAMES).contains(&frames) => {
+                    Ok(Persistence::Blend(frames))
+                }
+                _ => Err(PersistenceError::InvalidFrameCount),
+            },
+            ("decay", None) => Ok(Persistence::decay(DEFAULT_DECAY_MILLISECONDS)),
+            ("decay", Some(time)) => time
+                .parse()
+                .map(Persistence::decay)
+                .map_err(|_| PersistenceError::InvalidTime),
+            _ => Err(PersistenceError::UnknownMode),
+        }
+    }
+
+    /// Phosphor decay lasting roughly the given time, rounded to whole frames.
+    pub fn decay(milliseconds: u32) -> Persistence {
+        let frames = (milliseconds.saturating_mul(FRAMES_PER_SECOND) + 500) / 1000;
+        Persistence::Decay(frames.max(1))
+    }
+}
+
+/// Combines the frames it's given according to a persistence mode. `update` should be called once
+/// for every 60 Hz frame, whether or not the display changed, so that fading happens in real time.
+pub struct PersistenceFilter {
+    mode: Persistence,
+    /// The most recent frames, with each row packed into a bitmask so the leftmost pixel is the
+    /// highest bit. `history[latest]` is the newest.
+    history: [[u64; HEIGHT]; MAX_BLEND_FRAMES],
+    latest: usize,
+    intensities: [[u8; WIDTH]; HEIGHT],
+}
+
+impl PersistenceFilter {
+    pub fn new(mode: Persistence) -> PersistenceFilter {
+        PersistenceFilter {
+            mode,
+            history: [[0; HEIGHT]; MAX_BLEND_FRAMES],
+            latest: 0,
+            intensities: [[0; WIDTH]; HEIGHT],
+        }
+    }
+
+    pub fn mode(&self) -> Persistence {
+        self.mode
+    }
+
+    /// Adds a frame. Returns whether any pixel's brightness changed, so callers can skip redrawing
+    /// when nothing did.
+    pub fn update(&mut self, pixels: &[[bool; WIDTH]; HEIGHT]) -> bool {
+        self.latest = (self.latest + 1) % MAX_BLEND_FRAMES;
+        for (mask, row) in self.history[self.latest].iter_mut().zip(pixels.iter()) {
+            *mask = row
+                .iter()
+                .fold(0, |mask, &pixel| (mask << 1) | pixel as u64);
+        }
+
+        let mut changed = false;
+        for y in 0..HEIGHT {
+            for x in 0..WIDTH {
+                let intensity = self.intensity_at(x, y);
+                if intensity != self.intensities[y][x] {
+                    self.intensities[y][x] = intensity;
+                    changed = true;
+                }
+            }
+        }
+        changed
+    }
+
+    /// Brightness of each pixel, from 0 for off to 255 for fully on.
+    pub fn intensities(&self) -> &[[u8; WIDTH]; HEIGHT] {
+        &self.intensities
+    }
+
+    /// Forgets every frame, such as after the machine is reset or a state is loaded.
+    pub fn clear(&mut self) {
+        self.history = [[0; HEIGHT]; MAX_BLEND_FRAMES];
+        self.intensities = [[0; WIDTH]; HEIGHT];
+    }
+
+    fn lit(&self, frames_ago: usize, x: usize, y: usize) -> bool {
+        let frame = (self.latest + MAX_BLEND_FRAMES - frames_ago) % MAX_BLEND_FRAMES;
+        self.history[frame][y] & (1 << (WIDTH - 1 - x)) != 0
+    }
+
+    fn intensity_at(&self, x: usize, y: usize) -> u8 {
+        match self.mode {
+            Persistence::Off => full(self.lit(0, x, y)),
+            Persistence::MaxOfTwo => full(self.lit(0, x, y) || self.lit(1, x, y)),
+            Persistence::Blend(frames) => {
+                let lit = (0..frames).filter(|&i| self.lit(i, x, y)).count();
+                (lit * 255 / frames) as u8
+            }
+            Persistence::Decay(frames) => {
+                if self.lit(0, x, y) {
+                    255
+                } else {
+                    let step = 255u32.div_ceil(frames);
+                    self.intensities[y][x].saturating_sub(step.min(255) as u8)
+                }
+            }
+        }
+    }
+}
+
+fn full(lit: bool) -> u8 {
+    if lit {
+        255
+    } else {
+        0
+    }
+}
+
+/// Error that occurs while parsing a persistence mode.
+#[derive(Debug)]
+pub enum PersistenceError {
+    /// The mode isn't one of `off`, `blend`, `decay` or `max`.
+    UnknownMode,
+    /// The number of frames to blend isn't between 1 and `MAX_BLEND_FRAMES`.
+    InvalidFrameCount,
+    /// The decay time isn't a whole number of milliseconds.
+    InvalidTime,
+}
+
+impl fmt::Display for PersistenceError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            PersistenceError::UnknownMode => {
+                write!(
+                    f,
+                    "expected one of off, blend, blend=FRAMES, decay, decay=MS or max"
+                )
+            }
+            PersistenceError::InvalidFrameCount => write!(
+                f,
+                "the number of frames to blend must be between 1 and {}",
+                MAX_BLEND_FRAMES
+            ),
+            PersistenceError::InvalidTime => {
+                write!(f, "the decay time must be a whole number of milliseconds")
+            }
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl Error for PersistenceError {}
@@ -0,0 +1,229 @@
+//! A small PNG encoder for truecolour images. Image data is compressed with deflate using its fixed
+//! Huffman codes and only looks for repeats of the previous pixel or byte, which is all pixel art
+//! scaled up by whole numbers needs to compress well.
+
+use crate::filter::Image;
+use crate::palette::Rgb;
+
+pub(crate) const SIGNATURE: [u8; 8] = [0x89, b'P', b'N', b'G', b'\r', b'\n', 0x1A, b'\n'];
+/// Bytes per pixel in 8-bit RGB images.
+const PIXEL_SIZE: usize = 3;
+
+// Row filters, as described in section 9 of the PNG specification
+const FILTER_NONE: u8 = 0;
+const FILTER_UP: u8 = 2;
+
+/// Encodes an image as a PNG file.
+pub fn encode(image: &Image) -> Vec<u8> {
+    let mut png = SIGNATURE.to_vec();
+    write_header(&mut png, image.width(), image.height());
+    write_chunk(&mut png, b"IDAT", &zlib(&image_data(image)));
+    write_chunk(&mut png, b"IEND", &[]);
+    png
+}
+
+/// Writes the IHDR chunk for an 8-bit RGB image.
+pub(crate) fn write_header(png: &mut Vec<u8>, width: usize, height: usize) {
+    let mut header = Vec::with_capacity(13);
+    header.extend_from_slice(&(width as u32).to_be_bytes());
+    header.extend_from_slice(&(height as u32).to_be_bytes());
+    // Bit depth 8, truecolour, deflate compression, adaptive filtering, no interlacing
+    header.extend_from_slice(&[8, 2, 0, 0, 0]);
+    write_chunk(png, b"IHDR", &header);
+}
+
+pub(crate) fn write_chunk(png: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+    png.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    let start = png.len();
+    png.extend_from_slice(kind);
+    png.extend_from_slice(data);
+    let crc = crc32(&png[start..]);
+    png.extend_from_slice(&crc.to_be_bytes());
+}
+
+/// The filtered scanlines of an image, ready to be compressed. Rows that repeat the one above are
+/// filtered to all zeroes, which compress to almost nothing.
+pub(crate) fn image_data(image: &Image) -> Vec<u8> {
+    let row_size = image.width() * PIXEL_SIZE;
+    let mut data = Vec::with_capacity((row_size + 1) * image.height());
+    let mut previous: Option<&[Rgb]> = None;
+    for row in image.pixels().chunks(image.width()) {
+        if previous == Some(row) {
+            data.push(FILTER_UP);
+            data.extend(std::iter::repeat_n(0, row_size));
+        } else {
+            data.push(FILTER_NONE);
+            for &Rgb(r, g, b) in row {
+                data.extend_from_slice(&[r, g, b]);
+            }
+        }
+        previous = Some(row);
+    }
+    data
+}
+
+/// Wraps data compressed with deflate in the zlib format PNG uses.
+pub(crate) fn zlib(data: &[u8]) -> Vec<u8> {
+    // Deflate with a 32 KiB window and the fastest compression level
+    let mut output = vec![0x78, 0x01];
+    output.extend_from_slice(&deflate(data));
+    output.extend_from_slice(&adler32(data).to_be_bytes());
+    output
+}
+
+// Lengths and distances of repeats are written as a code plus some extra bits, as described in
+// section 3.2.5 of RFC 1951
+const LENGTH_BASES: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131,
+    163, 195, 227, 258,
+];
+const LENGTH_EXTRA_BITS: [u8; 29] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0,
+];
+const DISTANCE_BASES: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537,
+    2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577,
+];
+const DISTANCE_EXTRA_BITS: [u8; 30] = [
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13,
+    13,
+];
+const MIN_MATCH: usize = 3;
+const MAX_MATCH: usize = 258;
+/// Distances back that repeats are looked for at: the previous byte, and the previous pixel.
+const MATCH_DISTANCES: [usize; 2] = [1, PIXEL_SIZE];
+
+/// Compresses data into a single deflate block using the fixed Huffman codes.
+fn deflate(data: &[u8]) -> Vec<u8> {
+    let mut writer = BitWriter::default();
+    // Final block, compressed with fixed codes
+    writer.write(1, 1);
+    writer.write(1, 2);
+
+    let mut position = 0;
+    while position < data.len() {
+        let (length, distance) = MATCH_DISTANCES
+            .iter()
+            .filter(|&&distance| distance <= position)
+            .map(|&distance| (match_length(data, position, distance), distance))
+            .max_by_key(|&(length, _)| length)
+            .unwrap_or((0, 0));
+
+        if length >= MIN_MATCH {
+            write_length(&mut writer, length);
+            write_distance(&mut writer, distance);
+            position += length;
+        } else {
+            write_literal(&mut writer, data[position] as u16);
+            position += 1;
+        }
+    }
+
+    // End of block
+    write_literal(&mut writer, 256);
+    writer.finish()
+}
+
+/// How many bytes from `position` on repeat the bytes `distance` before them.
+fn match_length(data: &[u8], position: usize, distance: usize) -> usize {
+    data[position..]
+        .iter()
+        .take(MAX_MATCH)
+        .zip(&data[position - distance..])
+        .take_while(|(byte, earlier)| byte == earlier)
+        .count()
+}
+
+/// Writes a literal byte or length code with its fixed Huffman code.
+fn write_literal(writer: &mut BitWriter, value: u16) {
+    let (code, bits) = match value {
+        0..=143 => (0x30 + value, 8),
+        144..=255 => (0x190 + value - 144, 9),
+        256..=279 => (value - 256, 7),
+        _ => (0xC0 + value - 280, 8),
+    };
+    writer.write_huffman(code as u32, bits);
+}
+
+fn write_length(writer: &mut BitWriter, length: usize) {
+    let index = LENGTH_BASES
+        .iter()
+        .rposition(|&base| base as usize <= length)
+        .unwrap();
+    write_literal(writer, 257 + index as u16);
+    writer.write(
+        (length - LENGTH_BASES[index] as usize) as u32,
+        LENGTH_EXTRA_BITS[index],
+    );
+}
+
+fn write_distance(writer: &mut BitWriter, distance: usize) {
+    let index = DISTANCE_BASES
+        .iter()
+        .rposition(|&base| base as usize <= distance)
+        .unwrap();
+    writer.write_huffman(index as u32, 5);
+    writer.write(
+        (distance - DISTANCE_BASES[index] as usize) as u32,
+        DISTANCE_EXTRA_BITS[index],
+    );
+}
+
+/// Packs bits into bytes starting from the least significant bit, as deflate expects.
+#[derive(Default)]
+struct BitWriter {
+    bytes: Vec<u8>,
+    buffer: u32,
+    count: u8,
+}
+
+impl BitWriter {
+    /// Writes the lowest `bits` bits of a value, least significant first.
+    fn write(&mut self, value: u32, bits: u8) {
+        for i in 0..bits {
+            self.buffer |= ((value >> i) & 1) << self.count;
+            self.count += 1;
+            if self.count == 8 {
+                self.bytes.push(self.buffer as u8);
+                self.buffer = 0;
+                self.count = 0;
+            }
+        }
+    }
+
+    /// Writes a Huffman code, which deflate stores most significant bit first.
+    fn write_huffman(&mut self, code: u32, bits: u8) {
+        let reversed = code.reverse_bits() >> (32 - bits as u32);
+        self.write(reversed, bits);
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        if self.count > 0 {
+            self.bytes.push(self.buffer as u8);
+        }
+        self.bytes
+    }
+}
+
+/// The CRC-32 checksum every chunk ends with.
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &byte in data {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            let mask = (crc & 1).wrapping_neg();
+            crc = (crc >> 1) ^ (0xEDB8_8320 & mask);
+        }
+    }
+    !crc
+}
+
+fn adler32(data: &[u8]) -> u32 {
+    const MODULUS: u32 = 65521;
+    let (mut a, mut b) = (1u32, 0u32);
+    for &byte in data {
+        a = (a + byte as u32) % MODULUS;
+        b = (b + a) % MODULUS;
+    }
+    (b << 16) | a
+}
@@ -0,0 +1,194 @@
+//! Behaviours that differ between CHIP-8 interpreters. Programs written for one interpreter can
+//! misbehave on another, so the machine can be set up to match the one a program expects.
+
+use core::fmt;
+use core::fmt::Formatter;
+#[cfg(feature = "std")]
+use std::error::Error;
+
+/// Which variant of each ambiguous instruction the machine runs.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Quirks {
+    /// 8XY6 and 8XYE shift VY and store the result in VX, as on the COSMAC VIP. Otherwise VX is
+    /// shifted in place, as on the HP-48 interpreters.
+    pub shift_reads_vy: bool,
+    /// FX55 and FX65 leave I pointing just past the last register stored or loaded.
+    pub load_store_increments_i: bool,
+    /// When FX55 and FX65 advance I, they advance it by X rather than X + 1, leaving it on the
+    /// last register stored or loaded, as on CHIP-48 and SUPER-CHIP 1.0.
+    pub load_store_increments_by_x: bool,
+    /// BNNN jumps to NNN plus VX, where X is the highest digit of the address, rather than V0.
+    pub jump_uses_vx: bool,
+    /// 8XY1, 8XY2 and 8XY3 reset VF to 0.
+    pub logic_resets_vf: bool,
+    /// Sprites are cut off at the edges of the display rather than wrapping around to the other
+    /// side. Sprites always start on the display, wherever they're drawn.
+    pub clip_sprites: bool,
+}
+
+/// Chemu's own behaviour, which is what machines use unless told otherwise.
+pub const CHEMU: Quirks = Quirks {
+    shift_reads_vy: true,
+    load_store_increments_i: false,
+    load_store_increments_by_x: false,
+    jump_uses_vx: false,
+    logic_resets_vf: false,
+    clip_sprites: false,
+};
+
+/// The original COSMAC VIP interpreter.
+pub const VIP: Quirks = Quirks {
+    shift_reads_vy: true,
+    load_store_increments_i: true,
+    load_store_increments_by_x: false,
+    jump_uses_vx: false,
+    logic_resets_vf: true,
+    clip_sprites: true,
+};
+
+/// SUPER-CHIP 1.1 on the HP-48.
+pub const SCHIP: Quirks = Quirks {
+    shift_reads_vy: false,
+    load_store_increments_i: false,
+    load_store_increments_by_x: false,
+    jump_uses_vx: true,
+    logic_resets_vf: false,
+    clip_sprites: true,
+};
+
+/// XO-CHIP, as run by Octo.
+pub const XOCHIP: Quirks = Quirks {
+    shift_reads_vy: true,
+    load_store_increments_i: true,
+    load_store_increments_by_x: false,
+    jump_uses_vx: false,
+    logic_resets_vf: false,
+    clip_sprites: false,
+};
+
+/// Names of the quirk profiles, as accepted by `Quirks::parse`.
+pub const PROFILES: [(&str, Quirks); 4] = [
+    ("chemu", CHEMU),
+    ("vip", VIP),
+    ("schip", SCHIP),
+    ("xochip", XOCHIP),
+];
+
+/// Names of the individual quirks, as accepted by `Quirks::parse`.
+pub const QUIRK_NAMES: [&str; 6] = [
+    "shift",
+    "loadstore",
+    "loadstorex",
+    "jump",
+    "vfreset",
+    "clip",
+];
+
+impl Quirks {
+    /// Parses a profile name, optionally followed by quirks to turn on or off, separated by
+    /// commas: `schip,+vfreset,-clip`. A list starting with a quirk begins from `chemu`.
+    pub fn parse(spec: &str) -> Result<Quirks, QuirksError> {
+        let mut quirks = CHEMU;
+        for (i, item) in spec.split(',').map(str::trim).enumerate() {
+            let (enabled, name) = match item.as_bytes().first() {
+                Some(b'+') => (true, &item[1..]),
+                Some(b'-') => (false, &item[1..]),
+                _ if i == 0 => {
+                    quirks = PROFILES
+                        .iter()
+                        .find(|(name, _)| name.eq_ignore_ascii_case(item))
+                        .map(|&(_, profile)| profile)
+                        .ok_or(QuirksError::UnknownProfile)?;
+                    continue;
+                }
+                _ => return Err(QuirksError::UnknownQuirk),
+            };
+
+            *quirks.flag_mut(name).ok_or(QuirksError::UnknownQuirk)? = enabled;
+        }
+        Ok(quirks)
+    }
+
+    /// The name of the profile these quirks match exactly, if any.
+    pub fn profile_name(&self) -> Option<&'static str> {
+        PROFILES
+            .iter()
+            .find(|(_, profile)| profile == self)
+            .map(|&(name, _)| name)
+    }
+
+    fn flag_mut(&mut self, name: &str) -> Option<&mut bool> {
+        let index = QUIRK_NAMES
+            .iter()
+            .position(|quirk| quirk.eq_ignore_ascii_case(name))?;
+        Some(match index {
+            0 => &mut self.shift_reads_vy,
+            1 => &mut self.load_store_increments_i,
+            2 => &mut self.load_store_increments_by_x,
+            3 => &mut self.jump_uses_vx,
+            4 => &mut self.logic_resets_vf,
+            _ => &mut self.clip_sprites,
+        })
+    }
+}
+
+/// Writes the quirks as a specification `Quirks::parse` accepts: a profile name if they match one
+/// exactly, or the changes from `chemu` otherwise, such as `chemu,+clip`.
+impl fmt::Display for Quirks {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        if let Some(name) = self.profile_name() {
+            return f.write_str(name);
+        }
+
+        f.write_str("chemu")?;
+        let mut base = CHEMU;
+        for name in QUIRK_NAMES.iter() {
+            let mut quirks = *self;
+            let enabled = *quirks.flag_mut(name).expect("quirk names are known");
+            if *base.flag_mut(name).expect("quirk names are known") != enabled {
+                write!(f, ",{}{}", if enabled { '+' } else { '-' }, name)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Default for Quirks {
+    fn default() -> Self {
+        CHEMU
+    }
+}
+
+/// Error that occurs when a quirks specification can't be parsed.
+#[derive(Debug)]
+pub enum QuirksError {
+    UnknownProfile,
+    UnknownQuirk,
+}
+
+impl fmt::Display for QuirksError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            QuirksError::UnknownProfile => {
+                write_expected(f, "profile", PROFILES.iter().map(|&(name, _)| name))
+            }
+            QuirksError::UnknownQuirk => write_expected(f, "quirk", QUIRK_NAMES.iter().copied()),
+        }
+    }
+}
+
+fn write_expected<'a>(
+    f: &mut Formatter<'_>,
+    kind: &str,
+    names: impl Iterator<Item = &'a str>,
+) -> fmt::Result {
+    write!(f, "unknown {}, expected one of", kind)?;
+    for (i, name) in names.enumerate() {
+        let separator = if i == 0 { " " } else { ", " };
+        write!(f, "{}{}", separator, name)?;
+    }
+    Ok(())
+}
+
+#[cfg(feature = "std")]
+impl Error for QuirksError {}
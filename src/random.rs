@@ -0,0 +1,42 @@
+//! The random number generator behind the RND instruction. It's a SplitMix64 generator, which is
+//! small, fast and gives well-distributed output from any seed, including zero.
+
+pub struct Random {
+    state: u64,
+}
+
+impl Random {
+    pub fn new(seed: u64) -> Random {
+        Random { state: seed }
+    }
+
+    /// Creates a generator seeded differently every time. Without the standard library there's no
+    /// source of entropy, so the seed is always the same and callers should seed the machine
+    /// themselves, such as from a timer.
+    pub fn from_entropy() -> Random {
+        #[cfg(feature = "std")]
+        let seed = {
+            use std::hash::{BuildHasher, Hasher};
+            // The standard library keys every RandomState from the operating system's entropy
+            std::collections::hash_map::RandomState::new()
+                .build_hasher()
+                .finish()
+        };
+        #[cfg(not(feature = "std"))]
+        let seed = 0;
+
+        Random::new(seed)
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    pub fn next_u8(&mut self) -> u8 {
+        (self.next_u64() >> 56) as u8
+    }
+}
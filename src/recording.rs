@@ -0,0 +1,333 @@
+//! Recording the display to an animated GIF or PNG. A frame is captured for every 60 Hz tick, and
+//! runs of identical frames are stored once with a longer delay, so recordings of mostly still
+//! programs stay small.
+
+use crate::display::{HEIGHT, WIDTH};
+use crate::filter::Image;
+use crate::palette::{Palette, Rgb};
+use crate::png;
+use std::collections::HashMap;
+use std::io;
+use std::path::Path;
+
+/// Ticks per second, which frames are captured at.
+const TICKS_PER_SECOND: u32 = 60;
+/// Browsers show GIF frames with delays shorter than this many hundredths of a second for much
+/// longer than asked, so shorter frames are dropped in favour of the frame after them.
+const MIN_GIF_DELAY: u32 = 2;
+/// GIF codes can't be longer than 12 bits.
+const MAX_GIF_CODE_SIZE: u32 = 12;
+
+/// Animated image formats recordings can be saved in.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Format {
+    Gif,
+    /// Animated PNG, which keeps the exact frame timing.
+    Apng,
+}
+
+impl Format {
+    /// Picks a format from a file's extension: `.gif`, or `.png` or `.apng` for APNG.
+    pub fn from_path(path: &Path) -> Option<Format> {
+        let extension = path.extension()?.to_str()?.to_ascii_lowercase();
+        match extension.as_str() {
+            "gif" => Some(Format::Gif),
+            "png" | "apng" => Some(Format::Apng),
+            _ => None,
+        }
+    }
+
+    pub fn extension(self) -> &'static str {
+        match self {
+            Format::Gif => "gif",
+            Format::Apng => "png",
+        }
+    }
+}
+
+/// A display captured by a recorder, with each row packed into a bitmask so the leftmost pixel is
+/// the highest bit.
+type Frame = [u64; HEIGHT];
+
+/// Collects frames of the display and encodes them as an animation.
+pub struct Recorder {
+    palette: Palette,
+    scale: usize,
+    /// Each distinct frame along with how many ticks it was shown for.
+    frames: Vec<(Frame, u32)>,
+}
+
+impl Recorder {
+    /// Starts an empty recording. Each display pixel is drawn as a square `scale` pixels wide, in
+    /// the palette's off and on colours.
+    pub fn new(palette: Palette, scale: usize) -> Recorder {
+        Recorder {
+            palette,
+            scale: scale.max(1),
+            frames: Vec::new(),
+        }
+    }
+
+    /// Adds a frame. This should be called once for every 60 Hz tick.
+    pub fn capture(&mut self, pixels: &[[bool; WIDTH]; HEIGHT]) {
+        let mut frame = [0; HEIGHT];
+        for (mask, row) in frame.iter_mut().zip(pixels.iter()) {
+            *mask = row
+                .iter()
+                .fold(0, |mask, &pixel| (mask << 1) | pixel as u64);
+        }
+
+        match self.frames.last_mut() {
+            Some((last, ticks)) if *last == frame => *ticks += 1,
+            _ => self.frames.push((frame, 1)),
+        }
+    }
+
+    /// Number of ticks recorded so far.
+    pub fn ticks(&self) -> u32 {
+        self.frames.iter().map(|&(_, ticks)| ticks).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.frames.is_empty()
+    }
+
+    pub fn encode(&self, format: Format) -> Vec<u8> {
+        match format {
+            Format::Gif => self.encode_gif(),
+            Format::Apng => self.encode_apng(),
+        }
+    }
+
+    pub fn save(&self, path: &Path, format: Format) -> io::Result<()> {
+        std::fs::write(path, self.encode(format))
+    }
+
+    fn encode_apng(&self) -> Vec<u8> {
+        let (width, height) = (WIDTH * self.scale, HEIGHT * self.scale);
+        let mut apng = png::SIGNATURE.to_vec();
+        png::write_header(&mut apng, width, height);
+
+        // Animation control: the number of frames, and to loop forever
+        let mut control = (self.frames.len() as u32).to_be_bytes().to_vec();
+        control.extend_from_slice(&0u32.to_be_bytes());
+        png::write_chunk(&mut apng, b"acTL", &control);
+
+        // Frame control and frame data chunks share one sequence of numbers
+        let mut sequence = 0u32;
+        for (i, &(frame, ticks)) in self.frames.iter().enumerate() {
+            let mut control = sequence.to_be_bytes().to_vec();
+            control.extend_from_slice(&(width as u32).to_be_bytes());
+            control.extend_from_slice(&(height as u32).to_be_bytes());
+            // Offset of the frame
+            control.extend_from_slice(&[0; 8]);
+            control.extend_from_slice(&(ticks.min(u16::MAX as u32) as u16).to_be_bytes());
+            control.extend_from_slice(&(TICKS_PER_SECOND as u16).to_be_bytes());
+            // Leave the frame in place afterwards, and replace what was there before
+            control.extend_from_slice(&[0, 0]);
+            png::write_chunk(&mut apng, b"fcTL", &control);
+            sequence += 1;
+
+            let data = png::zlib(&png::image_data(&self.image(&frame)));
+            if i == 0 {
+                // The first frame doubles as the still image shown by viewers without APNG support
+                png::write_chunk(&mut apng, b"IDAT", &data);
+            } else {
+                let mut chunk = sequence.to_be_bytes().to_vec();
+                chunk.extend_from_slice(&data);
+                png::write_chunk(&mut apng, b"fdAT", &chunk);
+                sequence += 1;
+            }
+        }
+
+        png::write_chunk(&mut apng, b"IEND", &[]);
+        apng
+    }
+
+    fn image(&self, frame: &Frame) -> Image {
+        let mut pixels = [[false; WIDTH]; HEIGHT];
+        for (row, &mask) in pixels.iter_mut().zip(frame.iter()) {
+            for (x, pixel) in row.iter_mut().enumerate() {
+                *pixel = lit(mask, x);
+            }
+        }
+        Image::from_pixels(&pixels, &self.palette).scaled(self.scale)
+    }
+
+    fn encode_gif(&self) -> Vec<u8> {
+        let (width, height) = (WIDTH * self.scale, HEIGHT * self.scale);
+        let mut gif = b"GIF89a".to_vec();
+
+        // Logical screen descriptor, with a global colour table of two colours
+        gif.extend_from_slice(&(width as u16).to_le_bytes());
+        gif.extend_from_slice(&(height as u16).to_le_bytes());
+        gif.extend_from_slice(&[0x80, 0, 0]);
+        for &Rgb(r, g, b) in [self.palette.off(), self.palette.on()].iter() {
+            gif.extend_from_slice(&[r, g, b]);
+        }
+
+        // Loop forever
+        gif.extend_from_slice(b"\x21\xFF\x0BNETSCAPE2.0\x03\x01\x00\x00\x00");
+
+        let mut previous: Option<Frame> = None;
+        for (frame, delay) in self.gif_frames() {
+            // Only the part of the frame that changed is stored, drawn over the frame before
+            let (left, top, right, bottom) = match previous {
+                Some(previous) => changed_region(&previous, &frame).unwrap_or((0, 0, 1, 1)),
+                None => (0, 0, WIDTH, HEIGHT),
+            };
+            previous = Some(frame);
+
+            // Graphic control extension: leave the frame in place afterwards, with no transparency
+            gif.extend_from_slice(&[0x21, 0xF9, 0x04, 0x04]);
+            gif.extend_from_slice(&(delay.min(u16::MAX as u32) as u16).to_le_bytes());
+            gif.extend_from_slice(&[0, 0]);
+
+            // Image descriptor, using the global colour table
+            let scale = self.scale;
+            gif.push(0x2C);
+            for value in [left, top, right - left, bottom - top].iter() {
+                gif.extend_from_slice(&((value * scale) as u16).to_le_bytes());
+            }
+            gif.push(0);
+
+            let mut indices = Vec::with_capacity((right - left) * (bottom - top) * scale * scale);
+            for y in top * scale..bottom * scale {
+                for x in left * scale..right * scale {
+                    indices.push(lit(frame[y / scale], x / scale) as u8);
+                }
+            }
+
+            // Two colours need the smallest code size GIF allows
+            const MIN_CODE_SIZE: u32 = 2;
+            gif.push(MIN_CODE_SIZE as u8);
+            for block in lzw(&indices, MIN_CODE_SIZE).chunks(255) {
+                gif.push(block.len() as u8);
+                gif.extend_from_slice(block);
+            }
+            gif.push(0);
+        }
+
+        gif.push(0x3B);
+        gif
+    }
+
+    /// The frames to store in a GIF along with their delays in hundredths of a second. Frames start
+    /// at the nearest hundredth to when they were shown, so delays don't drift.
+    fn gif_frames(&self) -> Vec<(Frame, u32)> {
+        let centiseconds = |ticks: u32| (ticks * 100 + TICKS_PER_SECOND / 2) / TICKS_PER_SECOND;
+
+        let mut frames: Vec<(Frame, u32)> = Vec::new();
+        let mut start = 0;
+        for &(frame, ticks) in &self.frames {
+            let end = start + ticks;
+            let delay = centiseconds(end) - centiseconds(start);
+            match frames.last_mut() {
+                // The previous frame was too short to show, so it's replaced by this one
+                Some((last, last_delay)) if *last_delay < MIN_GIF_DELAY => {
+                    *last = frame;
+                    *last_delay += delay;
+                }
+                _ => frames.push((frame, delay)),
+            }
+            start = end;
+        }
+        frames
+    }
+}
+
+fn lit(mask: u64, x: usize) -> bool {
+    mask & (1 << (WIDTH - 1 - x)) != 0
+}
+
+/// The smallest rectangle, as left, top, right and bottom edges in display pixels, containing every
+/// pixel that differs between two frames.
+fn changed_region(previous: &Frame, frame: &Frame) -> Option<(usize, usize, usize, usize)> {
+    let changed_rows: Vec<(usize, u64)> = previous
+        .iter()
+        .zip(frame.iter())
+        .enumerate()
+        .map(|(y, (&old, &new))| (y, old ^ new))
+        .filter(|&(_, changes)| changes != 0)
+        .collect();
+
+    let top = changed_rows.first()?.0;
+    let bottom = changed_rows.last()?.0 + 1;
+    let changes = changed_rows
+        .iter()
+        .fold(0, |changes, &(_, row)| changes | row);
+    let left = changes.leading_zeros() as usize;
+    let right = WIDTH - changes.trailing_zeros() as usize;
+    Some((left, top, right, bottom))
+}
+
+/// Compresses colour indices with the variable-length LZW coding GIF uses, packed into bytes from
+/// the least significant bit.
+fn lzw(indices: &[u8], min_code_size: u32) -> Vec<u8> {
+    let clear = 1u32 << min_code_size;
+    let end = clear + 1;
+
+    let mut output = Vec::new();
+    let mut buffer = 0u32;
+    let mut buffered_bits = 0;
+    let mut write = |code: u32, size: u32, output: &mut Vec<u8>| {
+        buffer |= code << buffered_bits;
+        buffered_bits += size;
+        while buffered_bits >= 8 {
+            output.push(buffer as u8);
+            buffer >>= 8;
+            buffered_bits -= 8;
+        }
+    };
+
+    let mut table: HashMap<(u32, u8), u32> = HashMap::new();
+    let mut code_size = min_code_size + 1;
+    let mut next_code = end + 1;
+    write(clear, code_size, &mut output);
+
+    let mut current: Option<u32> = None;
+    for &index in indices {
+        let prefix = match current {
+            Some(prefix) => prefix,
+            None => {
+                current = Some(index as u32);
+                continue;
+            }
+        };
+
+        if let Some(&code) = table.get(&(prefix, index)) {
+            current = Some(code);
+            continue;
+        }
+
+        write(prefix, code_size, &mut output);
+        if next_code < 1 << MAX_GIF_CODE_SIZE {
+            table.insert((prefix, index), next_code);
+            // Decoders widen codes as soon as the next code no longer fits
+            if next_code == 1 << code_size && code_size < MAX_GIF_CODE_SIZE {
+                code_size += 1;
+            }
+            next_code += 1;
+        } else {
+            // The table is full, so start again
+            write(clear, code_size, &mut output);
+            table.clear();
+            code_size = min_code_size + 1;
+            next_code = end + 1;
+        }
+        current = Some(index as u32);
+    }
+
+    if let Some(prefix) = current {
+        write(prefix, code_size, &mut output);
+        // Decoders add an entry for the last code too, which can widen the end code
+        if next_code == 1 << code_size && code_size < MAX_GIF_CODE_SIZE {
+            code_size += 1;
+        }
+    }
+    write(end, code_size, &mut output);
+    if buffered_bits > 0 {
+        output.push(buffer as u8);
+    }
+    output
+}
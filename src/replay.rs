@@ -0,0 +1,117 @@
+//! Replays of key presses for headless runs, so recordings and screenshots can show a program being
+//! played. A replay file lists one key event per line: the frame it happens on, counted from 1 like
+//! `--screenshot-at`, then `press` or `release` and the hex key. Events happen just before their
+//! frame runs, and blank lines and lines starting with `#` are ignored.
+//!
+//! ```text
+//! # Start the game, then hold 6 to move right for half a second
+//! 60 press 5
+//! 62 release 5
+//! 120 press 6
+//! 150 release 6
+//! ```
+
+use crate::keyboard::{Key, Keyboard};
+use std::error::Error;
+use std::fmt;
+use std::fmt::Formatter;
+use std::path::Path;
+
+/// Key presses and releases to make at given frames.
+#[derive(Clone, Debug, Default)]
+pub struct Replay {
+    /// Sorted by frame, keeping events on the same frame in the order they were written.
+    events: Vec<(u64, Key, bool)>,
+}
+
+impl Replay {
+    pub fn load(path: &Path) -> Result<Replay, ReplayError> {
+        Replay::parse(&std::fs::read_to_string(path)?)
+    }
+
+    pub fn parse(text: &str) -> Result<Replay, ReplayError> {
+        let mut events = Vec::new();
+        for (number, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let invalid = |message: &str| ReplayError::Invalid {
+                line: number + 1,
+                message: message.to_string(),
+            };
+            let parts: Vec<&str> = line.split_whitespace().collect();
+            let (frame, action, key) = match parts[..] {
+                [frame, action, key] => (frame, action, key),
+                _ => return Err(invalid("expected a frame, press or release, and a key")),
+            };
+
+            let frame = frame
+                .parse::<u64>()
+                .map_err(|_| invalid("the frame isn't a whole number"))?;
+            let down = match action {
+                "press" => true,
+                "release" => false,
+                _ => return Err(invalid("expected press or release")),
+            };
+            let key = match u8::from_str_radix(key, 16) {
+                // from_str_radix accepts a leading sign, which isn't part of a key's name
+                Ok(value) if value <= 0xF && key.len() == 1 => Key(value),
+                _ => return Err(invalid("keys go from 0 to F")),
+            };
+            events.push((frame, key, down));
+        }
+
+        events.sort_by_key(|&(frame, _, _)| frame);
+        Ok(Replay { events })
+    }
+
+    /// The frame the last event happens on, if there are any events.
+    pub fn last_frame(&self) -> Option<u64> {
+        self.events.last().map(|&(frame, _, _)| frame)
+    }
+
+    /// Presses and releases the keys for a frame. Call it before running each frame.
+    pub fn apply(&self, frame: u64, keyboard: &mut Keyboard) {
+        let start = self.events.partition_point(|&(at, _, _)| at < frame);
+        for &(_, key, down) in self.events[start..]
+            .iter()
+            .take_while(|&&(at, _, _)| at == frame)
+        {
+            if down {
+                keyboard.press(key);
+            } else {
+                keyboard.release(key);
+            }
+        }
+    }
+}
+
+/// Error that occurs while loading a replay file.
+#[derive(Debug)]
+pub enum ReplayError {
+    Io(std::io::Error),
+    /// A line isn't a valid event.
+    Invalid {
+        line: usize,
+        message: String,
+    },
+}
+
+impl From<std::io::Error> for ReplayError {
+    fn from(error: std::io::Error) -> Self {
+        ReplayError::Io(error)
+    }
+}
+
+impl fmt::Display for ReplayError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            ReplayError::Io(error) => error.fmt(f),
+            ReplayError::Invalid { line, message } => write!(f, "line {}: {}", line, message),
+        }
+    }
+}
+
+impl Error for ReplayError {}
@@ -0,0 +1,124 @@
+//! Saving the display as an image file.
+
+use crate::display::{Display, HEIGHT, WIDTH};
+use crate::filter::Image;
+use crate::palette::Palette;
+use crate::png;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Image formats screenshots can be saved in.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Format {
+    /// A colour image using a palette, with each display pixel scaled up to a square.
+    Png,
+    /// A 1-bit image at the display's own resolution, where lit pixels are 1 (drawn in black by
+    /// most viewers) and the rest are 0.
+    Pbm,
+}
+
+impl Format {
+    pub fn extension(self) -> &'static str {
+        match self {
+            Format::Png => "png",
+            Format::Pbm => "pbm",
+        }
+    }
+}
+
+/// Encodes a display as a PBM image.
+pub fn encode_pbm(pixels: &[[bool; WIDTH]; HEIGHT]) -> Vec<u8> {
+    let mut pbm = format!("P4\n{} {}\n", WIDTH, HEIGHT).into_bytes();
+    for row in pixels.iter() {
+        // Rows are packed eight pixels to a byte, leftmost pixel in the highest bit
+        for pixels in row.chunks(8) {
+            pbm.push(
+                pixels
+                    .iter()
+                    .fold(0, |byte, &pixel| (byte << 1) | pixel as u8),
+            );
+        }
+    }
+    pbm
+}
+
+/// Encodes an image, which for PBM is made from the display's pixels alone.
+pub fn encode(display: &Display, image: &Image, format: Format) -> Vec<u8> {
+    match format {
+        Format::Png => png::encode(image),
+        Format::Pbm => encode_pbm(display.pixels()),
+    }
+}
+
+/// A file name for a capture of the given ROM taken now, such as `PONG2_20240131_235959_123.png`.
+/// Times are in UTC.
+pub fn file_name(rom_name: &str, extension: &str) -> String {
+    let time = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+    let seconds = time.as_secs();
+    let (year, month, day) = civil_date((seconds / 86400) as i64);
+    let second_of_day = seconds % 86400;
+
+    format!(
+        "{}_{:04}{:02}{:02}_{:02}{:02}{:02}_{:03}.{}",
+        rom_name,
+        year,
+        month,
+        day,
+        second_of_day / 3600,
+        second_of_day / 60 % 60,
+        second_of_day % 60,
+        time.subsec_millis(),
+        extension
+    )
+}
+
+/// Saves a screenshot of the display in a directory, coloured with the palette and scaled up by a
+/// whole number when saved as PNG. Returns the path of the new file.
+pub fn save(
+    display: &Display,
+    palette: &Palette,
+    scale: usize,
+    format: Format,
+    directory: &Path,
+    rom_name: &str,
+) -> io::Result<PathBuf> {
+    let image = Image::from_pixels(display.pixels(), palette).scaled(scale);
+    save_image(display, &image, format, directory, rom_name)
+}
+
+/// Saves a screenshot from an image that has already been coloured and scaled, such as the one a
+/// frontend presented. PBM screenshots still come from the display's pixels.
+pub fn save_image(
+    display: &Display,
+    image: &Image,
+    format: Format,
+    directory: &Path,
+    rom_name: &str,
+) -> io::Result<PathBuf> {
+    let path = directory.join(file_name(rom_name, format.extension()));
+    std::fs::write(&path, encode(display, image, format))?;
+    Ok(path)
+}
+
+/// Converts a count of days since 1970-01-01 into a year, month and day, using Howard Hinnant's
+/// `civil_from_days` algorithm.
+fn civil_date(days: i64) -> (i64, u32, u32) {
+    let days = days + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days.rem_euclid(146_097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = (day_of_year - (153 * month_index + 2) / 5 + 1) as u32;
+    let month = if month_index < 10 {
+        month_index + 3
+    } else {
+        month_index - 9
+    } as u32;
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
@@ -0,0 +1,86 @@
+//! Compares each filter's output with golden images in `tests/golden`, so any change to what the
+//! filters draw is caught. After an intended change, run the tests with `CHEMU_BLESS=1` to rewrite
+//! the golden images, and check the new ones by eye before committing them.
+
+use chemu::filter::{FilterChain, Image};
+use chemu::palette::{Palette, Rgb};
+use std::path::PathBuf;
+
+/// A small picture with straight edges, diagonals and single pixels for the filters to round off.
+/// The leftmost pixel of each row is the highest bit.
+const PICTURE: [u16; 8] = [
+    0b1111_0000_0001_0000,
+    0b0001_0000_0011_1000,
+    0b0010_0000_0111_1100,
+    0b0100_0000_1111_1110,
+    0b0100_0000_0111_1100,
+    0b0000_0000_0011_1000,
+    0b1010_1010_0001_0000,
+    0b0101_0101_0000_0000,
+];
+
+fn picture() -> Image {
+    let palette = Palette::default();
+    let mut image = Image::new(16, PICTURE.len(), palette.colour(false));
+    for (y, row) in PICTURE.iter().enumerate() {
+        for x in 0..16 {
+            image.set(x, y, palette.colour(row & (0x8000 >> x) != 0));
+        }
+    }
+    image
+}
+
+fn encode_ppm(image: &Image) -> Vec<u8> {
+    let mut ppm = format!("P6\n{} {}\n255\n", image.width(), image.height()).into_bytes();
+    for &Rgb(r, g, b) in image.pixels() {
+        ppm.extend_from_slice(&[r, g, b]);
+    }
+    ppm
+}
+
+/// Runs the picture through the chain and compares the result with `tests/golden/NAME.ppm`.
+fn check(chain: &str, name: &str) {
+    let output = encode_ppm(&FilterChain::parse(chain).unwrap().apply(picture()));
+    let path = PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/golden")
+        .join(format!("{}.ppm", name));
+
+    if std::env::var_os("CHEMU_BLESS").is_some() {
+        std::fs::write(&path, &output).unwrap();
+        return;
+    }
+
+    let golden =
+        std::fs::read(&path).unwrap_or_else(|e| panic!("couldn't read {}: {}", path.display(), e));
+    assert!(
+        golden == output,
+        "{} differs from {}",
+        chain,
+        path.display()
+    );
+}
+
+#[test]
+fn scale2x() {
+    check("scale2x", "scale2x");
+}
+
+#[test]
+fn scale3x() {
+    check("scale3x", "scale3x");
+}
+
+#[test]
+fn smooth() {
+    check("smooth", "smooth");
+}
+
+#[test]
+fn crt() {
+    check("crt", "crt");
+}
+
+#[test]
+fn chained_filters_run_in_order() {
+    check("scale2x,crt", "scale2x-crt");
+}
@@ -0,0 +1,124 @@
+//! Drives the libretro core through the same entry points a frontend such as RetroArch would use,
+//! and checks that save states bring back exactly the frames that followed them.
+
+use chemu::libretro::*;
+use std::cell::RefCell;
+use std::ffi::{c_void, CStr};
+use std::os::raw::c_uint;
+
+/// Draws each digit in turn, moving along the display, so every frame differs from the last.
+const ROM: [u8; 14] = [
+    0x61, 0x00, // LD V1, 0
+    0xF0, 0x29, // LD F, V0
+    0xD1, 0x15, // DRW V1, V1, 5
+    0x70, 0x01, // ADD V0, 1
+    0x71, 0x03, // ADD V1, 3
+    0x12, 0x02, // JP 202
+    0x00, 0x00,
+];
+
+thread_local! {
+    static FRAME: RefCell<Vec<u32>> = const { RefCell::new(Vec::new()) };
+}
+
+extern "C" fn environment(cmd: c_uint, data: *mut c_void) -> bool {
+    match cmd {
+        RETRO_ENVIRONMENT_SET_PIXEL_FORMAT => {
+            let format = unsafe { *(data as *const c_uint) };
+            format == RETRO_PIXEL_FORMAT_XRGB8888
+        }
+        _ => false,
+    }
+}
+
+extern "C" fn video_refresh(data: *const c_void, width: c_uint, height: c_uint, pitch: usize) {
+    if data.is_null() {
+        return;
+    }
+
+    let frame =
+        unsafe { std::slice::from_raw_parts(data as *const u32, pitch / 4 * height as usize) };
+    FRAME.with(|stored| {
+        let mut stored = stored.borrow_mut();
+        stored.clear();
+        for row in frame.chunks(pitch / 4) {
+            stored.extend_from_slice(&row[..width as usize]);
+        }
+    });
+}
+
+extern "C" fn audio_sample(_left: i16, _right: i16) {}
+
+extern "C" fn audio_sample_batch(_data: *const i16, frames: usize) -> usize {
+    frames
+}
+
+extern "C" fn input_poll() {}
+
+extern "C" fn input_state(_port: c_uint, _device: c_uint, _index: c_uint, _id: c_uint) -> i16 {
+    0
+}
+
+/// Runs a frame and returns the picture the core sent.
+fn run_frame() -> Vec<u32> {
+    retro_run();
+    FRAME.with(|frame| frame.borrow().clone())
+}
+
+fn serialize() -> Vec<u8> {
+    let mut state = vec![0; retro_serialize_size()];
+    assert!(unsafe { retro_serialize(state.as_mut_ptr() as *mut c_void, state.len()) });
+    state
+}
+
+#[test]
+fn save_states_restore_the_same_frames() {
+    assert_eq!(retro_api_version(), RETRO_API_VERSION);
+    retro_set_environment(environment);
+    retro_set_video_refresh(video_refresh);
+    retro_set_audio_sample(audio_sample);
+    retro_set_audio_sample_batch(audio_sample_batch);
+    retro_set_input_poll(input_poll);
+    retro_set_input_state(input_state);
+    retro_init();
+
+    let mut info = std::mem::MaybeUninit::<RetroSystemInfo>::uninit();
+    unsafe {
+        retro_get_system_info(info.as_mut_ptr());
+        let info = &*info.as_ptr();
+        assert_eq!(CStr::from_ptr(info.library_name).to_str(), Ok("Chemu"));
+    }
+
+    let game = RetroGameInfo {
+        path: std::ptr::null(),
+        data: ROM.as_ptr() as *const c_void,
+        size: ROM.len(),
+        meta: std::ptr::null(),
+    };
+    assert!(unsafe { retro_load_game(&game) }, "core rejected the game");
+
+    for _ in 0..3 {
+        run_frame();
+    }
+    let state = serialize();
+    let expected: Vec<Vec<u32>> = (0..3).map(|_| run_frame()).collect();
+    assert_ne!(
+        expected[0], expected[1],
+        "the ROM should change every frame"
+    );
+
+    retro_reset();
+    run_frame();
+    assert!(unsafe { retro_unserialize(state.as_ptr() as *const c_void, state.len()) });
+    let restored: Vec<Vec<u32>> = (0..3).map(|_| run_frame()).collect();
+    assert_eq!(restored, expected);
+
+    // A null state is rejected without touching the machine
+    let current = serialize();
+    assert!(!unsafe { retro_unserialize(std::ptr::null(), 0) });
+    assert!(!unsafe { retro_unserialize(std::ptr::null(), state.len()) });
+    assert_eq!(serialize(), current);
+
+    retro_unload_game();
+    retro_deinit();
+}
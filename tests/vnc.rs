@@ -0,0 +1,163 @@
+//! Checks the VNC server's protocol over the loopback interface.
+
+use chemu::display::{HEIGHT, WIDTH};
+use chemu::frontend::vnc::VncFrontend;
+use chemu::frontend::Frontend;
+use chemu::keyboard::Key;
+use chemu::machine::{Machine, INSTRUCTIONS_PER_FRAME};
+use chemu::palette::Palette;
+use std::collections::HashMap;
+use std::io::{Read, Write};
+use std::net::{SocketAddr, TcpStream};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Draws a 7 in the top left corner and loops forever.
+const ROM: [u8; 10] = [
+    0x60, 0x07, // LD V0, 7
+    0xF0, 0x29, // LD F, V0
+    0x61, 0x00, // LD V1, 0
+    0xD1, 0x15, // DRW V1, V1, 5
+    0x12, 0x08, // JP 208
+];
+
+/// The font's sprite for 7.
+const SEVEN: [u8; 5] = [0xF0, 0x10, 0x20, 0x40, 0x40];
+
+/// The X11 keysym for the W key.
+const KEYSYM_W: u32 = 0x77;
+const BOUND_KEY: Key = Key(5);
+
+/// Bytes per pixel in the server's default format.
+const PIXEL_SIZE: usize = 4;
+
+#[test]
+fn handshake_update_and_keys() {
+    let bindings: HashMap<String, Key> = vec![("W".to_string(), BOUND_KEY)].into_iter().collect();
+    let any_port: SocketAddr = ([127, 0, 0, 1], 0).into();
+    let mut frontend =
+        VncFrontend::new(any_port, any_port, 1, Palette::default(), bindings).unwrap();
+    let (control_address, view_only_address) = frontend.local_addresses();
+
+    let mut machine = Machine::from_rom(&ROM);
+    machine.run_frame(INSTRUCTIONS_PER_FRAME);
+    frontend.render(&machine);
+
+    let mut player = connect(control_address);
+    request_full_update(&mut player);
+    let pixels = read_update(&mut player);
+    let background = &pixels[WIDTH * PIXEL_SIZE - PIXEL_SIZE..WIDTH * PIXEL_SIZE];
+    for (y, row) in pixels.chunks(WIDTH * PIXEL_SIZE).enumerate() {
+        for (x, pixel) in row.chunks(PIXEL_SIZE).enumerate() {
+            let lit = y < SEVEN.len() && x < 8 && SEVEN[y] & (0x80 >> x) != 0;
+            assert_eq!(pixel != background, lit, "wrong pixel at {}, {}", x, y);
+        }
+    }
+
+    // Keys pressed by a viewer that can only watch are ignored
+    let mut spectator = connect(view_only_address);
+    send_key(&mut spectator, true);
+    thread::sleep(Duration::from_millis(100));
+    frontend.process_events(machine.keyboard_mut());
+    assert!(!machine.keyboard().is_pressed(BOUND_KEY));
+
+    send_key(&mut player, true);
+    wait_for_key(&mut frontend, &mut machine, true);
+
+    // A second player letting go doesn't release the key the first is still holding
+    let mut second_player = connect(control_address);
+    send_key(&mut second_player, true);
+    send_key(&mut second_player, false);
+    thread::sleep(Duration::from_millis(100));
+    frontend.process_events(machine.keyboard_mut());
+    assert!(machine.keyboard().is_pressed(BOUND_KEY));
+
+    // Disconnecting lets go of the keys a viewer was holding
+    drop(player);
+    wait_for_key(&mut frontend, &mut machine, false);
+}
+
+/// Connects to the server and goes through the version 3.8 handshake, checking the server's
+/// description of the display.
+fn connect(address: SocketAddr) -> TcpStream {
+    let mut stream = TcpStream::connect(address).unwrap();
+    stream
+        .set_read_timeout(Some(Duration::from_secs(5)))
+        .unwrap();
+
+    assert_eq!(read(&mut stream, 12), b"RFB 003.008\n");
+    stream.write_all(b"RFB 003.008\n").unwrap();
+    // One security type, which is None
+    assert_eq!(read(&mut stream, 2), [1, 1]);
+    stream.write_all(&[1]).unwrap();
+    assert_eq!(read(&mut stream, 4), [0, 0, 0, 0], "security failed");
+    // Shared
+    stream.write_all(&[1]).unwrap();
+
+    let server_init = read(&mut stream, 24);
+    assert_eq!(
+        u16::from_be_bytes([server_init[0], server_init[1]]),
+        WIDTH as u16
+    );
+    assert_eq!(
+        u16::from_be_bytes([server_init[2], server_init[3]]),
+        HEIGHT as u16
+    );
+    assert_eq!(server_init[4] as usize, PIXEL_SIZE * 8);
+    let name_length = u32::from_be_bytes([
+        server_init[20],
+        server_init[21],
+        server_init[22],
+        server_init[23],
+    ]) as usize;
+    assert_eq!(read(&mut stream, name_length), b"Chemu");
+
+    stream
+}
+
+fn request_full_update(stream: &mut TcpStream) {
+    let mut request = vec![3, 0, 0, 0, 0, 0];
+    request.extend_from_slice(&(WIDTH as u16).to_be_bytes());
+    request.extend_from_slice(&(HEIGHT as u16).to_be_bytes());
+    stream.write_all(&request).unwrap();
+}
+
+/// Reads a framebuffer update, which must cover the whole display in the raw encoding, and
+/// returns its pixels.
+fn read_update(stream: &mut TcpStream) -> Vec<u8> {
+    let header = read(stream, 4);
+    assert_eq!(header[0], 0, "expected a framebuffer update");
+    assert_eq!(u16::from_be_bytes([header[2], header[3]]), 1);
+
+    let rectangle = read(stream, 12);
+    let field = |i: usize| u16::from_be_bytes([rectangle[i], rectangle[i + 1]]) as usize;
+    assert_eq!(
+        (field(0), field(2), field(4), field(6)),
+        (0, 0, WIDTH, HEIGHT)
+    );
+    assert_eq!(&rectangle[8..12], [0, 0, 0, 0], "expected the raw encoding");
+
+    read(stream, WIDTH * HEIGHT * PIXEL_SIZE)
+}
+
+fn send_key(stream: &mut TcpStream, down: bool) {
+    let mut event = vec![4, down as u8, 0, 0];
+    event.extend_from_slice(&KEYSYM_W.to_be_bytes());
+    stream.write_all(&event).unwrap();
+}
+
+/// Applies key events from the server until the bound key is pressed or released.
+fn wait_for_key(frontend: &mut VncFrontend, machine: &mut Machine, pressed: bool) {
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while machine.keyboard().is_pressed(BOUND_KEY) != pressed {
+        assert!(Instant::now() < deadline, "key never changed");
+        thread::sleep(Duration::from_millis(10));
+        frontend.process_events(machine.keyboard_mut());
+    }
+}
+
+fn read(stream: &mut TcpStream, length: usize) -> Vec<u8> {
+    let mut bytes = vec![0; length];
+    stream.read_exact(&mut bytes).unwrap();
+    bytes
+}
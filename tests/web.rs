@@ -0,0 +1,187 @@
+//! Checks the web frontend's protocol over the loopback interface.
+
+use chemu::display::HEIGHT;
+use chemu::frontend::web::{
+    self, WebFrontend, CHANGED_ROWS, FULL_FRAME, PALETTE, PRESS, RELEASE, ROW_BYTES, SOUND,
+};
+use chemu::frontend::Frontend;
+use chemu::machine::{Machine, INSTRUCTIONS_PER_FRAME};
+use chemu::palette::Palette;
+use std::io::{Read, Write};
+use std::net::{SocketAddr, TcpStream};
+use std::sync::mpsc::channel;
+use std::thread;
+use std::time::Duration;
+
+/// Waits for a key, then draws its digit in the top left corner, beeps briefly and loops forever.
+const ROM: [u8; 14] = [
+    0xF0, 0x0A, // LD V0, K
+    0xF0, 0x29, // LD F, V0
+    0x61, 0x00, // LD V1, 0
+    0xD1, 0x15, // DRW V1, V1, 5
+    0x62, 0x06, // LD V2, 6
+    0xF2, 0x18, // LD ST, V2
+    0x12, 0x0C, // JP 20C
+];
+
+/// The font's sprite for 7.
+const SEVEN: [u8; 5] = [0xF0, 0x10, 0x20, 0x40, 0x40];
+
+/// The example key and accept value given in RFC 6455.
+const SAMPLE_KEY: &str = "dGhlIHNhbXBsZSBub25jZQ==";
+const SAMPLE_ACCEPT: &str = "s3pPLMBiTxaQ9kYGzzhZRbK+xOo=";
+
+#[test]
+fn accept_key_matches_rfc_6455() {
+    assert_eq!(web::accept_key(SAMPLE_KEY), SAMPLE_ACCEPT);
+}
+
+#[test]
+fn serves_the_page() {
+    let address = start_server();
+
+    let mut not_found = TcpStream::connect(address).unwrap();
+    not_found
+        .write_all(b"GET /missing HTTP/1.1\r\n\r\n")
+        .unwrap();
+    let mut response = String::new();
+    not_found.read_to_string(&mut response).unwrap();
+    assert!(response.starts_with("HTTP/1.1 404"), "{}", response);
+
+    let mut page = TcpStream::connect(address).unwrap();
+    page.write_all(b"GET / HTTP/1.1\r\n\r\n").unwrap();
+    let mut response = String::new();
+    page.read_to_string(&mut response).unwrap();
+    assert!(response.starts_with("HTTP/1.1 200"), "{}", response);
+    assert!(response.contains("<canvas"));
+}
+
+/// A spectator and a player both connect. The spectator's key presses must be ignored, so the
+/// player's key is the one whose digit shows up on both screens, followed by the beep.
+#[test]
+fn players_press_keys_and_spectators_watch() {
+    let address = start_server();
+
+    let mut spectator = open_socket(address, "/watch");
+    expect_initial_state(&mut spectator);
+    send(&mut spectator, &[PRESS, 0x1]);
+    send(&mut spectator, &[RELEASE, 0x1]);
+    thread::sleep(Duration::from_millis(100));
+
+    let mut player = open_socket(address, "/");
+    expect_initial_state(&mut player);
+    send(&mut player, &[PRESS, 0x7]);
+    send(&mut player, &[RELEASE, 0x7]);
+
+    for (name, stream) in [("player", &mut player), ("spectator", &mut spectator)].iter_mut() {
+        let message = receive(stream);
+        assert_eq!(message[0], CHANGED_ROWS, "{} got the wrong message", name);
+
+        let rows: Vec<(u8, &[u8])> = message[1..]
+            .chunks(1 + ROW_BYTES)
+            .map(|row| (row[0], &row[1..]))
+            .collect();
+        assert_eq!(rows.len(), SEVEN.len(), "{} got the wrong rows", name);
+        for (y, (row, bytes)) in rows.iter().enumerate() {
+            assert_eq!(*row as usize, y);
+            assert_eq!(bytes[0], SEVEN[y], "{} saw the wrong digit", name);
+            assert!(bytes[1..].iter().all(|&byte| byte == 0));
+        }
+
+        assert_eq!(receive(stream), [SOUND, 1], "{} didn't hear the beep", name);
+        assert_eq!(
+            receive(stream),
+            [SOUND, 0],
+            "{} heard the beep forever",
+            name
+        );
+    }
+}
+
+/// Runs the ROM behind a web frontend on a thread of its own, returning the address it's serving
+/// on.
+fn start_server() -> SocketAddr {
+    let (address_sender, address) = channel();
+    thread::spawn(move || {
+        let mut frontend =
+            WebFrontend::new(([127, 0, 0, 1], 0).into(), Palette::default()).unwrap();
+        address_sender.send(frontend.local_address()).unwrap();
+
+        let mut machine = Machine::from_rom(&ROM);
+        loop {
+            frontend.process_events(machine.keyboard_mut());
+            machine.run_frame(INSTRUCTIONS_PER_FRAME);
+            frontend.render(&machine);
+            machine.display_mut().mark_presented();
+            thread::sleep(Duration::from_millis(16));
+        }
+    });
+    address.recv().unwrap()
+}
+
+/// Connects to the server and upgrades the connection to a WebSocket.
+fn open_socket(address: SocketAddr, path: &str) -> TcpStream {
+    let mut stream = TcpStream::connect(address).unwrap();
+    stream
+        .set_read_timeout(Some(Duration::from_secs(5)))
+        .unwrap();
+    let request = format!(
+        "GET {} HTTP/1.1\r\n\
+         Host: {}\r\n\
+         Upgrade: websocket\r\n\
+         Connection: Upgrade\r\n\
+         Sec-WebSocket-Key: {}\r\n\
+         Sec-WebSocket-Version: 13\r\n\r\n",
+        path, address, SAMPLE_KEY
+    );
+    stream.write_all(request.as_bytes()).unwrap();
+
+    // Read the response a byte at a time so none of the first message is swallowed
+    let mut response = Vec::new();
+    while !response.ends_with(b"\r\n\r\n") {
+        let mut byte = [0; 1];
+        stream.read_exact(&mut byte).unwrap();
+        response.push(byte[0]);
+    }
+    let response = String::from_utf8(response).unwrap();
+    assert!(response.starts_with("HTTP/1.1 101"), "{}", response);
+    assert!(response.contains(SAMPLE_ACCEPT), "{}", response);
+
+    stream
+}
+
+/// Checks the palette, blank display and silence sent to every new viewer.
+fn expect_initial_state(stream: &mut TcpStream) {
+    let mut expected = vec![PALETTE];
+    for colour in Palette::default().colours.iter() {
+        expected.extend_from_slice(&[colour.0, colour.1, colour.2]);
+    }
+    assert_eq!(receive(stream), expected);
+
+    let frame = receive(stream);
+    assert_eq!(frame[0], FULL_FRAME);
+    assert_eq!(frame.len(), 1 + HEIGHT * ROW_BYTES);
+    assert!(frame[1..].iter().all(|&byte| byte == 0));
+
+    assert_eq!(receive(stream), [SOUND, 0]);
+}
+
+fn receive(stream: &mut TcpStream) -> Vec<u8> {
+    let (opcode, payload) = web::read_frame(stream).unwrap();
+    assert_eq!(opcode, 0x2, "expected a binary message");
+    payload
+}
+
+/// Sends a binary message, masked as the protocol requires of clients.
+fn send(stream: &mut TcpStream, payload: &[u8]) {
+    let mask = [0x12, 0x34, 0x56, 0x78];
+    let mut frame = vec![0x82, 0x80 | payload.len() as u8];
+    frame.extend_from_slice(&mask);
+    frame.extend(
+        payload
+            .iter()
+            .zip(mask.iter().cycle())
+            .map(|(byte, mask)| byte ^ mask),
+    );
+    stream.write_all(&frame).unwrap();
+}